@@ -1,8 +1,21 @@
-use crate::cmd::{Command, MountKind, Runnable, SandboxBuilder};
-use crate::prepare::Prepare;
+use crate::cmd::{Command, CommandError, MountKind, Runnable, SandboxBuilder};
+use crate::metadata::CargoMetadata;
+use crate::metrics::{timed, Operation};
+use crate::prepare::{Prepare, PrepareError};
+use crate::rustdoc::RustdocOptions;
+use crate::timings::UnitTiming;
 use crate::{Crate, Toolchain, Workspace};
-use std::path::PathBuf;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::{info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
+use walkdir::WalkDir;
 
 #[derive(Clone)]
 pub(crate) enum CratePatch {
@@ -31,6 +44,7 @@ pub(crate) struct PathCratePatch {
 pub struct BuildDirectory {
     workspace: Workspace,
     name: String,
+    purge_on_drop: bool,
 }
 
 /// Builder for configuring builds in a [`BuildDirectory`](struct.BuildDirectory.html).
@@ -40,9 +54,108 @@ pub struct BuildBuilder<'a> {
     krate: &'a Crate,
     sandbox: SandboxBuilder,
     patches: Vec<CratePatch>,
+    options: BuildOptions,
+    validation: Option<Box<Validation<'a>>>,
 }
 
-impl BuildBuilder<'_> {
+type Validation<'a> = dyn for<'b, 'ws> Fn(&'b Build<'ws>) -> Command<'ws, 'b> + 'a;
+
+/// Options of a build configured through the [`BuildBuilder`](struct.BuildBuilder.html).
+#[derive(Default, Clone, Hash)]
+pub(crate) struct BuildOptions {
+    jobs: Option<u32>,
+    fast_compile: bool,
+    incremental: Option<bool>,
+    seed_target_dir: Option<PathBuf>,
+    clean_target_before: bool,
+    skip_if_unchanged: bool,
+    package: Option<String>,
+    files: Vec<(PathBuf, Vec<u8>)>,
+    removed_files: Vec<PathBuf>,
+    skip_fetch_deps: bool,
+}
+
+/// Error returned by [`BuildBuilder::run`](struct.BuildBuilder.html#method.run) when the build
+/// was skipped because its inputs didn't change since the last successful build, as requested
+/// with [`BuildBuilder::skip_if_unchanged`](struct.BuildBuilder.html#method.skip_if_unchanged).
+#[derive(Debug, thiserror::Error)]
+#[error("the build was skipped, as its inputs didn't change since the last successful build")]
+#[non_exhaustive]
+pub struct BuildSkipped;
+
+/// Breakdown of the time spent in each phase of a build, returned by
+/// [`BuildBuilder::run_with_report`](struct.BuildBuilder.html#method.run_with_report).
+///
+/// Fetching the crate itself with [`Crate::fetch`](struct.Crate.html#method.fetch) happens before
+/// the build: its duration is recorded by the crate, and it's reported separately from the
+/// [`total`](#method.total).
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    pub(crate) fetch: Option<Duration>,
+    pub(crate) copy_source: Duration,
+    pub(crate) validate_manifest: Duration,
+    pub(crate) tweak: Duration,
+    pub(crate) lockfile: Duration,
+    pub(crate) fetch_deps: Duration,
+    pub(crate) build: Duration,
+    pub(crate) total: Duration,
+}
+
+impl BuildReport {
+    /// Time spent by the last successful [`Crate::fetch`](struct.Crate.html#method.fetch) (or
+    /// [`Crate::fetch_with_progress`](struct.Crate.html#method.fetch_with_progress)) of the
+    /// crate before the build, or `None` if this instance of the crate wasn't fetched.
+    pub fn fetch(&self) -> Option<Duration> {
+        self.fetch
+    }
+
+    /// Time spent copying (or extracting) the source code of the crate in the build directory.
+    pub fn copy_source(&self) -> Duration {
+        self.copy_source
+    }
+
+    /// Time spent validating the crate's manifest with `cargo metadata`.
+    pub fn validate_manifest(&self) -> Duration {
+        self.validate_manifest
+    }
+
+    /// Time spent tweaking the crate's `Cargo.toml` and configuring its registry.
+    pub fn tweak(&self) -> Duration {
+        self.tweak
+    }
+
+    /// Time spent generating or updating the crate's `Cargo.lock`.
+    pub fn lockfile(&self) -> Duration {
+        self.lockfile
+    }
+
+    /// Time spent fetching the crate's dependencies with `cargo fetch`.
+    pub fn fetch_deps(&self) -> Duration {
+        self.fetch_deps
+    }
+
+    /// Time spent running the closure passed to
+    /// [`BuildBuilder::run_with_report`](struct.BuildBuilder.html#method.run_with_report).
+    pub fn build(&self) -> Duration {
+        self.build
+    }
+
+    /// Total time spent in the build, including the phases not broken down in the report (like
+    /// seeding the target directory and cleaning up after the build).
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+}
+
+/// Call the function, storing how long it took in `duration`.
+pub(crate) fn timed_phase<T>(duration: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+    *duration = start.elapsed();
+    res
+}
+
+impl<'a> BuildBuilder<'a> {
     /// Add a git-based patch to this build.
     /// Patches get added to the crate's Cargo.toml in the `patch.crates-io` table.
     /// # Example
@@ -107,6 +220,209 @@ impl BuildBuilder<'_> {
         self
     }
 
+    /// Limit the number of parallel jobs cargo runs in this build, setting `CARGO_BUILD_JOBS` on
+    /// all the commands run in the sandbox. By default cargo's own default is used, which is the
+    /// number of CPUs of the host.
+    ///
+    /// The [`cpu_limit`](cmd/struct.SandboxBuilder.html#method.cpu_limit) of the sandbox only
+    /// restricts the CPU time the build can use, and it doesn't change the number of CPUs cargo
+    /// detects: when a CPU limit is set, setting the jobs to the limit rounded up avoids
+    /// spawning more compiler processes than the ones that can run at the same time.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use rustwide::{WorkspaceBuilder, Toolchain, Crate, cmd::SandboxBuilder};
+    /// # use std::error::Error;
+    /// # fn main() -> anyhow::Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// # let toolchain = Toolchain::dist("");
+    /// # let krate = Crate::local("".as_ref());
+    /// let sandbox = SandboxBuilder::new().cpu_limit(Some(2.0));
+    /// let mut build_dir = workspace.build_dir("foo");
+    /// build_dir.build(&toolchain, &krate, sandbox)
+    ///     .jobs(2)
+    ///     .run(|build| {
+    ///         build.cargo().args(&["test", "--all"]).run()?;
+    ///         Ok(())
+    ///     })?;
+    /// # Ok(())
+    /// # }
+    pub fn jobs(mut self, jobs: u32) -> Self {
+        self.options.jobs = Some(jobs);
+        self
+    }
+
+    /// Configure cargo to minimize compile times at the expense of the performance of the
+    /// compiled code, a common setup for CI builds. This sets the following environment variables
+    /// on all the commands run in the sandbox:
+    ///
+    /// * `CARGO_PROFILE_DEV_CODEGEN_UNITS=256` and `CARGO_PROFILE_RELEASE_CODEGEN_UNITS=256`
+    /// * `CARGO_PROFILE_DEV_LTO=off` and `CARGO_PROFILE_RELEASE_LTO=off`
+    ///
+    /// Profile settings from the environment take precedence over the ones in `Cargo.toml`, so
+    /// this overrides the crate's own profiles. The `test` and `bench` profiles inherit the
+    /// settings from `dev` and `release` respectively, unless the crate overrides them.
+    pub fn fast_compile(mut self) -> Self {
+        self.options.fast_compile = true;
+        self
+    }
+
+    /// Enable or disable incremental compilation in this build, setting `CARGO_INCREMENTAL` to
+    /// `1` or `0` on all the commands run in the sandbox. If this method is not called the
+    /// variable is not set, and cargo uses the settings of the crate's profiles.
+    ///
+    /// The incremental compilation data is stored in the build's target directory, which is
+    /// always mounted read-write inside the sandbox and configured with `CARGO_TARGET_DIR`.
+    /// Nothing is written to the cargo home or to the crate's source, which are mounted
+    /// read-only.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.options.incremental = Some(incremental);
+        self
+    }
+
+    /// Populate the target directory of the build before running it, allowing cargo to reuse the
+    /// artifacts of a previous build when their fingerprints match. The seed can either be a
+    /// directory or a tarball (optionally compressed with gzip) created by
+    /// [`Build::archive_target_dir`](struct.Build.html#method.archive_target_dir). Its contents
+    /// are added to the existing contents of the target directory.
+    ///
+    /// To avoid confusing cargo, the seed must have been built with the same version of rustc
+    /// and on the same host as this build, which is checked through the `.rustc_info.json` file
+    /// cargo stores in the target directory. If the seed is not compatible the target directory
+    /// is cleared and the build fails. As the source code of the crate is copied again for every
+    /// build, the artifacts of its dependencies are reused while the crate itself is usually
+    /// rebuilt.
+    pub fn seed_target_dir(mut self, seed: &Path) -> Self {
+        self.options.seed_target_dir = Some(seed.into());
+        self
+    }
+
+    /// Remove the contents of the target directory before running the build, so that no artifact
+    /// of previous builds in the same build directory is reused. This is useful to guarantee a
+    /// clean compilation, for example when checking whether a build is reproducible or when
+    /// switching toolchains. By default the target directory is shared between the builds.
+    ///
+    /// The target directory is cleaned before being populated by
+    /// [`seed_target_dir`](#method.seed_target_dir).
+    pub fn clean_target_before(mut self, clean: bool) -> Self {
+        self.options.clean_target_before = clean;
+        self
+    }
+
+    /// Select a package of the crate's Cargo workspace, for example a member of a virtual
+    /// manifest (a `Cargo.toml` only containing a `[workspace]` section, common in git
+    /// repositories containing multiple crates).
+    ///
+    /// The commands created by [`Build::cmd`](struct.Build.html#method.cmd) and
+    /// [`Build::cargo`](struct.Build.html#method.cargo) are run in the directory of the package,
+    /// so cargo only builds it. If this method is not called the commands run in the root of the
+    /// workspace, where cargo builds all the default members of a virtual manifest. The build
+    /// fails with [`PrepareError::MissingPackage`](enum.PrepareError.html#variant.MissingPackage)
+    /// if the package is not a member of the workspace.
+    pub fn package(mut self, name: &str) -> Self {
+        self.options.package = Some(name.into());
+        self
+    }
+
+    /// Add a file to the source code of the crate, at the provided path relative to the root of
+    /// the source. The file is written after the source is prepared (so after the `Cargo.toml`
+    /// is tweaked and the patches are applied) and before the closure passed to
+    /// [`run`](#method.run) is called, replacing any existing file at the same path. Missing
+    /// parent directories are created.
+    ///
+    /// The path must not be absolute nor contain `..` components, and it must not point outside
+    /// of the source through symbolic links in it (nor be a symbolic link itself), otherwise the
+    /// build fails with
+    /// [`PrepareError::InvalidFilePath`](enum.PrepareError.html#variant.InvalidFilePath).
+    pub fn add_file(mut self, path: &Path, contents: &[u8]) -> Self {
+        self.options.files.push((path.into(), contents.into()));
+        self
+    }
+
+    /// Remove a file or a directory from the source code of the crate, at the provided path
+    /// relative to the root of the source, for example to skip a broken integration test. Like
+    /// [`add_file`](#method.add_file), the removal happens after the source is prepared, and
+    /// before any file is added. Nothing happens if the path doesn't exist.
+    ///
+    /// The path must not be absolute nor contain `..` components, and its parent directory must
+    /// not point outside of the source through symbolic links in it, otherwise the build fails
+    /// with [`PrepareError::InvalidFilePath`](enum.PrepareError.html#variant.InvalidFilePath). A
+    /// symbolic link at the path is removed without touching its target.
+    pub fn remove_file(mut self, path: &Path) -> Self {
+        self.options.removed_files.push(path.into());
+        self
+    }
+
+    /// Skip fetching the dependencies of the crate with `cargo fetch` while preparing the build,
+    /// avoiding network access for crates whose dependencies are vendored or were already
+    /// fetched. The `Cargo.lock` is still generated if the crate doesn't have one.
+    ///
+    /// The commands run in the sandbox then fetch the missing dependencies themselves, which
+    /// only works if networking is enabled in the sandbox. With networking disabled (or with
+    /// cargo's `--offline` flag) they fail if a dependency is neither vendored nor already in
+    /// the workspace's cargo home.
+    pub fn skip_fetch_deps(mut self, skip: bool) -> Self {
+        self.options.skip_fetch_deps = skip;
+        self
+    }
+
+    /// Run the command returned by `cmd` inside the sandbox after the source code is prepared,
+    /// failing the build with
+    /// [`PrepareError::ValidationFailed`](enum.PrepareError.html#variant.ValidationFailed)
+    /// without calling the closure passed to [`run`](#method.run) if the command fails.
+    ///
+    /// `cmd` receives the [`Build`](struct.Build.html), so the command can be created with
+    /// [`Build::cargo`](struct.Build.html#method.cargo) or [`Build::cmd`](struct.Build.html#method.cmd)
+    /// to run with the same sandbox configuration, environment and working directory as the
+    /// build. The validation command is not part of the fingerprint used by
+    /// [`skip_if_unchanged`](#method.skip_if_unchanged).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use rustwide::{WorkspaceBuilder, Toolchain, Crate, cmd::SandboxBuilder};
+    /// # use std::error::Error;
+    /// # fn main() -> anyhow::Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// # let toolchain = Toolchain::dist("");
+    /// # let krate = Crate::local("".as_ref());
+    /// # let sandbox = SandboxBuilder::new();
+    /// let mut build_dir = workspace.build_dir("foo");
+    /// build_dir
+    ///     .build(&toolchain, &krate, sandbox)
+    ///     .validate_with(|build| build.cargo().args(&["verify-project"]))
+    ///     .run(|build| {
+    ///         build.cargo().args(&["test", "--all"]).run()?;
+    ///         Ok(())
+    ///     })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn validate_with(
+        mut self,
+        cmd: impl for<'b, 'ws> Fn(&'b Build<'ws>) -> Command<'ws, 'b> + 'a,
+    ) -> Self {
+        self.validation = Some(Box::new(cmd));
+        self
+    }
+
+    /// Skip running the build if its inputs didn't change since the last successful build in the
+    /// same build directory. When the build is skipped, [`run`](#method.run) doesn't call the
+    /// closure and returns a [`BuildSkipped`](struct.BuildSkipped.html) error.
+    ///
+    /// The inputs are fingerprinted after the source code is prepared, and include the prepared
+    /// source code (with the tweaked `Cargo.toml` and the generated `Cargo.lock`), the output of
+    /// `rustc -vV` and the options set on this builder. The sandbox configuration and the
+    /// commands run by the closure are not part of the fingerprint, and changes to them don't
+    /// cause the build to run again. The fingerprint of the last successful build is stored in
+    /// the build directory, and it's removed by
+    /// [`BuildDirectory::purge`](struct.BuildDirectory.html#method.purge).
+    pub fn skip_if_unchanged(mut self, skip: bool) -> Self {
+        self.options.skip_if_unchanged = skip;
+        self
+    }
+
     /// Run a sandboxed build of the provided crate with the provided toolchain. The closure will
     /// be provided an instance of [`Build`](struct.Build.html) that allows spawning new processes
     /// inside the sandbox.
@@ -131,8 +447,114 @@ impl BuildBuilder<'_> {
     /// # Ok(())
     /// # }
     pub fn run<R, F: FnOnce(&Build) -> anyhow::Result<R>>(self, f: F) -> anyhow::Result<R> {
-        self.build_dir
-            .run(self.toolchain, self.krate, self.sandbox, self.patches, f)
+        self.run_with_report(f).map(|(res, _)| res)
+    }
+
+    /// Run the build like [`run`](#method.run), running it again when it fails with an error
+    /// `is_spurious` considers spurious (for example a flaky test), at most `attempts` times in
+    /// total. If all the attempts fail the error of the last one is returned.
+    ///
+    /// Each attempt prepares the source code of the crate again from scratch, so changes made to
+    /// it by a failed attempt are discarded, and then calls the closure again. The target
+    /// directory is kept between attempts, allowing cargo to reuse the artifacts compiled by the
+    /// failed attempts. Builds skipped because of
+    /// [`skip_if_unchanged`](#method.skip_if_unchanged) are never retried.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use rustwide::{WorkspaceBuilder, Toolchain, Crate, cmd::SandboxBuilder};
+    /// # use std::error::Error;
+    /// # fn main() -> anyhow::Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// # let toolchain = Toolchain::dist("");
+    /// # let krate = Crate::local("".as_ref());
+    /// # let sandbox = SandboxBuilder::new();
+    /// let mut build_dir = workspace.build_dir("foo");
+    /// build_dir
+    ///     .build(&toolchain, &krate, sandbox)
+    ///     .run_with_retry(
+    ///         3,
+    ///         |err| err.to_string().contains("spurious"),
+    ///         |build| {
+    ///             build.cargo().args(&["test", "--all"]).run()?;
+    ///             Ok(())
+    ///         },
+    ///     )?;
+    /// # Ok(())
+    /// # }
+    pub fn run_with_retry<R, F: FnMut(&Build) -> anyhow::Result<R>>(
+        mut self,
+        attempts: u32,
+        is_spurious: impl Fn(&anyhow::Error) -> bool,
+        mut f: F,
+    ) -> anyhow::Result<R> {
+        let mut attempt = 1;
+        loop {
+            match self.run_attempt(&mut f) {
+                Err(err)
+                    if attempt < attempts && !err.is::<BuildSkipped>() && is_spurious(&err) =>
+                {
+                    warn!(
+                        "build attempt {} of {} failed, retrying: {}",
+                        attempt, attempts, err
+                    );
+                    attempt += 1;
+                }
+                res => return res.map(|(res, _)| res),
+            }
+        }
+    }
+
+    /// Run the build like [`run`](#method.run), also returning a
+    /// [`BuildReport`](struct.BuildReport.html) with the time spent in each phase of the build.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use rustwide::{WorkspaceBuilder, Toolchain, Crate, cmd::SandboxBuilder};
+    /// # use std::error::Error;
+    /// # fn main() -> anyhow::Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// # let toolchain = Toolchain::dist("");
+    /// # let krate = Crate::local("".as_ref());
+    /// # let sandbox = SandboxBuilder::new();
+    /// let mut build_dir = workspace.build_dir("foo");
+    /// let ((), report) = build_dir.build(&toolchain, &krate, sandbox).run_with_report(|build| {
+    ///     build.cargo().args(&["test", "--all"]).run()?;
+    ///     Ok(())
+    /// })?;
+    /// println!("fetching the dependencies took {:?}", report.fetch_deps());
+    /// # Ok(())
+    /// # }
+    pub fn run_with_report<R, F: FnOnce(&Build) -> anyhow::Result<R>>(
+        mut self,
+        f: F,
+    ) -> anyhow::Result<(R, BuildReport)> {
+        self.run_attempt(f)
+    }
+
+    fn run_attempt<R, F: FnOnce(&Build) -> anyhow::Result<R>>(
+        &mut self,
+        f: F,
+    ) -> anyhow::Result<(R, BuildReport)> {
+        let mut report = BuildReport {
+            fetch: self.krate.last_fetch_duration(),
+            ..BuildReport::default()
+        };
+        let start = Instant::now();
+        let res = self.build_dir.run(
+            self.toolchain,
+            self.krate,
+            self.sandbox.clone(),
+            self.patches.clone(),
+            self.options.clone(),
+            self.validation.as_deref(),
+            &mut report,
+            f,
+        );
+        report.total = start.elapsed();
+        res.map(|res| (res, report))
     }
 }
 
@@ -141,9 +563,16 @@ impl BuildDirectory {
         Self {
             workspace,
             name: name.into(),
+            purge_on_drop: false,
         }
     }
 
+    /// Remove the contents of the build directory when it's dropped.
+    pub(crate) fn purge_on_drop(mut self, enable: bool) -> Self {
+        self.purge_on_drop = enable;
+        self
+    }
+
     /// Create a build in this build directory.  Returns a builder that can be used
     /// to configure the build and run it.
     ///
@@ -177,36 +606,164 @@ impl BuildDirectory {
             krate,
             sandbox,
             patches: Vec::new(),
+            options: BuildOptions::default(),
+            validation: None,
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn run<R, F: FnOnce(&Build) -> anyhow::Result<R>>(
         &mut self,
         toolchain: &Toolchain,
         krate: &Crate,
         sandbox: SandboxBuilder,
         patches: Vec<CratePatch>,
+        options: BuildOptions,
+        validation: Option<&Validation<'_>>,
+        report: &mut BuildReport,
         f: F,
     ) -> anyhow::Result<R> {
+        self.workspace.check_crate_filter(krate)?;
+
         let source_dir = self.source_dir();
         if source_dir.exists() {
             crate::utils::remove_dir_all(&source_dir)?;
         }
 
-        let mut prepare = Prepare::new(&self.workspace, toolchain, krate, &source_dir, patches);
-        prepare.prepare()?;
+        let mut prepare = Prepare::new(
+            &self.workspace,
+            toolchain,
+            krate,
+            &source_dir,
+            patches,
+            options.skip_fetch_deps,
+        );
+        let metadata = timed(self.workspace.metrics(), Operation::Prepare, || {
+            prepare.prepare(report)
+        })?;
+        let package_dir = match &options.package {
+            Some(name) => Some(
+                package_dir(&metadata, name)
+                    .ok_or_else(|| PrepareError::MissingPackage(name.clone()))?,
+            ),
+            None => None,
+        };
+        // Directory the commands run in, relative to the root of the source.
+        let workdir = match (krate.subdir(), &package_dir) {
+            (Some(subdir), Some(package_dir)) => Some(subdir.join(package_dir)),
+            (Some(subdir), None) => Some(subdir.to_path_buf()),
+            (None, package_dir) => package_dir.clone(),
+        };
+
+        for path in &options.removed_files {
+            let dest = source_dir.join(validate_source_path(path)?);
+            // Paths accepted by `validate_source_path` always have a parent inside of the source.
+            let parent = dest.parent().unwrap();
+            if !parent.is_dir() {
+                continue;
+            }
+            check_inside_source(&source_dir, parent, path)?;
+            // Symbolic links are removed without following them.
+            match dest.symlink_metadata() {
+                Ok(metadata) if metadata.is_dir() => crate::utils::remove_dir_all(&dest)?,
+                Ok(_) => crate::utils::remove_file(&dest)?,
+                Err(_) => {}
+            }
+        }
+        for (path, contents) in &options.files {
+            let dest = source_dir.join(validate_source_path(path)?);
+            // Paths accepted by `validate_source_path` always have a parent inside of the source.
+            let parent = dest.parent().unwrap();
+            // The missing directories are created following the symbolic links in the source.
+            if let Some(existing) = parent.ancestors().find(|dir| dir.is_dir()) {
+                check_inside_source(&source_dir, existing, path)?;
+            }
+            std::fs::create_dir_all(parent)?;
+            check_inside_source(&source_dir, parent, path)?;
+            if dest
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+            {
+                return Err(PrepareError::InvalidFilePath(path.clone()).into());
+            }
+            std::fs::write(&dest, contents)?;
+        }
 
+        let fingerprint = if options.skip_if_unchanged {
+            let fingerprint = self.fingerprint(toolchain, &options)?;
+            if std::fs::read_to_string(self.fingerprint_file())
+                .ok()
+                .as_ref()
+                == Some(&fingerprint)
+            {
+                info!("skipping the build, as its inputs didn't change");
+                crate::utils::remove_dir_all(&source_dir)?;
+                return Err(BuildSkipped.into());
+            }
+            Some(fingerprint)
+        } else {
+            None
+        };
+
+        if options.clean_target_before && self.target_dir().exists() {
+            crate::utils::remove_dir_all(&self.target_dir())?;
+        }
         std::fs::create_dir_all(self.target_dir())?;
-        let res = f(&Build {
+        if let Some(seed) = &options.seed_target_dir {
+            let target_dir = self.target_dir();
+            let res = copy_seed(seed, &target_dir)
+                .and_then(|()| check_seed(&self.workspace, toolchain, &target_dir));
+            if let Err(err) = res {
+                crate::utils::remove_dir_all(&target_dir)?;
+                std::fs::create_dir_all(&target_dir)?;
+                return Err(err.context(format!(
+                    "failed to seed the target directory from {}",
+                    seed.display()
+                )));
+            }
+        }
+        let build = Build {
             dir: self,
             toolchain,
             sandbox,
+            metadata,
+            options,
+            package_dir,
+            workdir,
+        };
+        if let Some(validation) = validation {
+            build.validate(validation)?;
+        }
+        let res = timed(build.dir.workspace.metrics(), Operation::Build, || {
+            timed_phase(&mut report.build, || f(&build))
         })?;
 
+        if let Some(fingerprint) = fingerprint {
+            std::fs::write(self.fingerprint_file(), fingerprint)?;
+        }
         crate::utils::remove_dir_all(&source_dir)?;
         Ok(res)
     }
 
+    /// Fingerprint the inputs of a build, after its source code is prepared.
+    fn fingerprint(&self, toolchain: &Toolchain, options: &BuildOptions) -> anyhow::Result<String> {
+        // The hashing algorithm might change between Rust releases, but that only causes the
+        // build to run again.
+        let mut hasher = DefaultHasher::new();
+        rustc_version(&self.workspace, toolchain)?.hash(&mut hasher);
+        options.hash(&mut hasher);
+
+        let source_dir = self.source_dir();
+        for entry in WalkDir::new(&source_dir).sort_by_file_name() {
+            let entry = entry?;
+            entry.path().strip_prefix(&source_dir)?.hash(&mut hasher);
+            if entry.file_type().is_file() {
+                std::fs::read(entry.path())?.hash(&mut hasher);
+            }
+        }
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
     /// Remove all the contents of the build directory, freeing disk space.
     pub fn purge(&mut self) -> anyhow::Result<()> {
         let build_dir = self.build_dir();
@@ -220,6 +777,10 @@ impl BuildDirectory {
         self.workspace.builds_dir().join(&self.name)
     }
 
+    fn fingerprint_file(&self) -> PathBuf {
+        self.build_dir().join("fingerprint")
+    }
+
     fn source_dir(&self) -> PathBuf {
         self.build_dir().join("source")
     }
@@ -229,6 +790,201 @@ impl BuildDirectory {
     }
 }
 
+impl Drop for BuildDirectory {
+    fn drop(&mut self) {
+        if self.purge_on_drop {
+            if let Err(err) = self.purge() {
+                warn!(
+                    "failed to purge build directory {}: {}",
+                    self.build_dir().display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn copy_seed(seed: &Path, target_dir: &Path) -> anyhow::Result<()> {
+    if seed.is_dir() {
+        for entry in WalkDir::new(seed) {
+            let entry = entry?;
+            let dest = target_dir.join(entry.path().strip_prefix(seed)?);
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(&dest)?;
+            } else if entry.file_type().is_symlink() {
+                // Like in the tarballs, symbolic links are copied as links without following them.
+                copy_symlink(entry.path(), &dest)?;
+            } else {
+                std::fs::copy(entry.path(), &dest)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut file = BufReader::new(File::open(seed)?);
+    let is_gzip = file.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if is_gzip {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        // Entries are inside the `target` directory created by `Build::archive_target_dir`.
+        let mut components = path.components();
+        components.next();
+        let relative = components.as_path();
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            anyhow::bail!("invalid path in the seed tarball: {}", path.display());
+        }
+        if relative.as_os_str().is_empty() {
+            // The `target` directory itself already exists.
+            continue;
+        }
+        if entry.header().entry_type().is_hard_link() {
+            anyhow::bail!("hard link in the seed tarball: {}", path.display());
+        }
+        let dest = target_dir.join(relative);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+            // Prevent writing outside of the target directory through a symbolic link extracted
+            // from an earlier entry.
+            if !parent
+                .canonicalize()?
+                .starts_with(target_dir.canonicalize()?)
+            {
+                anyhow::bail!(
+                    "path outside of the target directory in the seed tarball: {}",
+                    path.display()
+                );
+            }
+        }
+        entry.unpack(&dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(src)?, dest)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, _dest: &Path) -> anyhow::Result<()> {
+    // Creating symbolic links requires special privileges on Windows.
+    warn!("skipping symbolic link {} in the seed", src.display());
+    Ok(())
+}
+
+/// Check the seeded target directory was built by the same rustc as the one used in the build.
+fn check_seed(
+    workspace: &Workspace,
+    toolchain: &Toolchain,
+    target_dir: &Path,
+) -> anyhow::Result<()> {
+    #[derive(serde::Deserialize)]
+    struct RustcInfo {
+        outputs: HashMap<String, RustcOutput>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct RustcOutput {
+        stdout: String,
+    }
+
+    let info = match std::fs::read_to_string(target_dir.join(".rustc_info.json")) {
+        Ok(info) => info,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            anyhow::bail!("the seed doesn't contain cargo's `.rustc_info.json`")
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let info: RustcInfo = serde_json::from_str(&info)?;
+
+    let version = rustc_version(workspace, toolchain)?;
+    if !info
+        .outputs
+        .values()
+        .any(|output| output.stdout.trim_end() == version)
+    {
+        anyhow::bail!(
+            "the seed was built with a different version of rustc or on a different host"
+        );
+    }
+    Ok(())
+}
+
+/// Return the directory of a workspace member, relative to the root of the workspace.
+fn package_dir(metadata: &CargoMetadata, name: &str) -> Option<PathBuf> {
+    let package = metadata
+        .workspace_members()
+        .find(|package| package.name() == name)?;
+    let dir = package.manifest_path().parent()?;
+    Some(dir.strip_prefix(metadata.workspace_root()).ok()?.into())
+}
+
+/// Check the path of a file added to or removed from the source doesn't point outside of it.
+pub(crate) fn validate_source_path(path: &Path) -> Result<&Path, PrepareError> {
+    let valid = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if valid && path.file_name().is_some() {
+        Ok(path)
+    } else {
+        Err(PrepareError::InvalidFilePath(path.into()))
+    }
+}
+
+/// Check the existing directory `dir` is inside of the source directory once the symbolic links
+/// are resolved, as the source of the crate is untrusted and its links could point anywhere.
+fn check_inside_source(source_dir: &Path, dir: &Path, path: &Path) -> anyhow::Result<()> {
+    if dir.canonicalize()?.starts_with(source_dir.canonicalize()?) {
+        Ok(())
+    } else {
+        Err(PrepareError::InvalidFilePath(path.into()).into())
+    }
+}
+
+/// Return the verbose version information of the toolchain's rustc, including the host target.
+fn rustc_version(workspace: &Workspace, toolchain: &Toolchain) -> anyhow::Result<String> {
+    Ok(Command::new(workspace, toolchain.rustc())
+        .args(&["-vV"])
+        .log_output(false)
+        .run_capture()?
+        .stdout_lines()
+        .join("\n"))
+}
+
+fn write_tarball<W: Write>(writer: W, target_dir: &Path) -> anyhow::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    tar.follow_symlinks(false);
+    tar.append_dir_all("target", target_dir)?;
+    Ok(tar.into_inner()?)
+}
+
+/// Kinds of the targets documented by `cargo rustdoc --lib`.
+static LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"];
+
+static FAST_COMPILE_ENV: &[(&str, &str)] = &[
+    ("CARGO_PROFILE_DEV_CODEGEN_UNITS", "256"),
+    ("CARGO_PROFILE_RELEASE_CODEGEN_UNITS", "256"),
+    ("CARGO_PROFILE_DEV_LTO", "off"),
+    ("CARGO_PROFILE_RELEASE_LTO", "off"),
+];
+
+fn is_secret_env(key: &str) -> bool {
+    let key = key.to_uppercase();
+    ["TOKEN", "SECRET", "PASSWORD", "KEY"]
+        .iter()
+        .any(|secret| key.contains(secret))
+}
+
 /// API to interact with a running build.
 ///
 /// This is created from [`BuildDirectory::build`](struct.BuildDirectory.html#method.build)
@@ -236,9 +992,19 @@ pub struct Build<'ws> {
     dir: &'ws BuildDirectory,
     toolchain: &'ws Toolchain,
     sandbox: SandboxBuilder,
+    metadata: CargoMetadata,
+    options: BuildOptions,
+    package_dir: Option<PathBuf>,
+    workdir: Option<PathBuf>,
 }
 
 impl<'ws> Build<'ws> {
+    /// Return the metadata of the crate, collected with `cargo metadata --no-deps` while
+    /// preparing the build. Retrieving it doesn't run any command.
+    pub fn cargo_metadata(&self) -> &CargoMetadata {
+        &self.metadata
+    }
+
     /// Run a command inside the sandbox.
     ///
     /// Any `cargo` invocation will automatically be configured to use a target directory mounted
@@ -266,7 +1032,7 @@ impl<'ws> Build<'ws> {
     pub fn cmd<'pl, R: Runnable>(&self, bin: R) -> Command<'ws, 'pl> {
         let container_dir = &*crate::cmd::container_dirs::TARGET_DIR;
 
-        Command::new_sandboxed(
+        let mut cmd = Command::new_sandboxed(
             &self.dir.workspace,
             self.sandbox
                 .clone()
@@ -274,7 +1040,22 @@ impl<'ws> Build<'ws> {
             bin,
         )
         .cd(self.dir.source_dir())
-        .env("CARGO_TARGET_DIR", container_dir)
+        .env("CARGO_TARGET_DIR", container_dir);
+        if let Some(workdir) = &self.workdir {
+            cmd = cmd.sandbox_workdir(workdir);
+        }
+        if let Some(jobs) = self.options.jobs {
+            cmd = cmd.env("CARGO_BUILD_JOBS", jobs.to_string());
+        }
+        if let Some(incremental) = self.options.incremental {
+            cmd = cmd.env("CARGO_INCREMENTAL", if incremental { "1" } else { "0" });
+        }
+        if self.options.fast_compile {
+            for (key, value) in FAST_COMPILE_ENV {
+                cmd = cmd.env(key, value);
+            }
+        }
+        cmd
     }
 
     /// Run `cargo` inside the sandbox, using the toolchain chosen for the build.
@@ -304,7 +1085,153 @@ impl<'ws> Build<'ws> {
         self.cmd(self.toolchain.cargo())
     }
 
-    /// Get the path to the source code on the host machine (outside the sandbox).
+    /// Run the validation command configured with
+    /// [`BuildBuilder::validate_with`](struct.BuildBuilder.html#method.validate_with).
+    fn validate(&self, validation: &Validation<'_>) -> anyhow::Result<()> {
+        match validation(self).run_capture() {
+            Ok(_) => Ok(()),
+            Err(CommandError::ExecutionFailed { stderr, .. }) => {
+                Err(PrepareError::ValidationFailed(stderr).into())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Return the environment variables rustwide sets on `cargo` invocations inside the sandbox
+    /// (like `CARGO_HOME`, `RUSTUP_HOME` and `CARGO_TARGET_DIR`), to audit or reproduce the
+    /// build elsewhere. Variables added to a single command with
+    /// [`Command::env`](cmd/struct.Command.html#method.env) are not included.
+    ///
+    /// Variables whose name suggests they contain a secret (containing `TOKEN`, `SECRET`,
+    /// `PASSWORD` or `KEY`) are excluded from the returned list.
+    pub fn effective_env(&self) -> Vec<(String, String)> {
+        self.cargo()
+            .sandbox_env()
+            .into_iter()
+            .filter(|(key, _)| !is_secret_env(key))
+            .collect()
+    }
+
+    /// Run `cargo` with the provided arguments (for example `&["build"]`) and the
+    /// `--timings=json` flag inside the sandbox, returning how long each unit of the build took
+    /// to compile. Units that didn't need to be compiled again are not included.
+    ///
+    /// The machine-readable `--timings=json` flag is unstable, and it's only available on nightly
+    /// toolchains: other toolchains will fail to run the command. The timings are printed by
+    /// cargo on its standard output, so the output of commands like `cargo run` is ignored.
+    pub fn run_with_timings(&self, args: &[&str]) -> anyhow::Result<Vec<UnitTiming>> {
+        let output = self
+            .cargo()
+            .args(args)
+            .args(&["--timings=json", "-Zunstable-options"])
+            .run_capture()?;
+        crate::timings::parse_timings(output.stdout_lines().iter().map(|line| line.as_str()))
+    }
+
+    /// Build the documentation of the crate with `cargo doc` inside the sandbox, configuring
+    /// rustdoc with the provided [`RustdocOptions`](struct.RustdocOptions.html), and return the
+    /// path on the host machine of the directory containing the generated documentation.
+    ///
+    /// The rustdoc flags are passed with the `CARGO_ENCODED_RUSTDOCFLAGS` environment variable,
+    /// which requires Rust 1.55 or later.
+    pub fn rustdoc_build(&self, options: &RustdocOptions) -> anyhow::Result<PathBuf> {
+        self.cargo()
+            .args(&options.cargo_args())
+            .env(
+                "CARGO_ENCODED_RUSTDOCFLAGS",
+                options.rustdoc_flags().join("\x1f"),
+            )
+            .run()?;
+
+        let mut doc_dir = self.host_target_dir();
+        if let Some(target) = options.target_name() {
+            doc_dir.push(target);
+        }
+        Ok(doc_dir.join("doc"))
+    }
+
+    /// Generate the documentation of the crate in the [rustdoc JSON format][json] with
+    /// `cargo rustdoc` inside the sandbox, returning the path on the host machine of the JSON
+    /// file. The crate's library is documented if it has one, otherwise its first binary is.
+    ///
+    /// The JSON output is an unstable feature of rustdoc, and this method returns an error if the
+    /// toolchain used for the build is not a nightly toolchain. The format of the file changes
+    /// between nightly versions: check its `format_version` field before parsing it.
+    ///
+    /// [json]: https://doc.rust-lang.org/nightly/nightly-rustc/rustdoc_json_types/
+    pub fn rustdoc_json(&self, options: &RustdocOptions) -> anyhow::Result<PathBuf> {
+        let version = rustc_version(&self.dir.workspace, self.toolchain)?;
+        if !version.contains("-nightly") && !version.contains("-dev") {
+            anyhow::bail!("rustdoc JSON output requires a nightly toolchain");
+        }
+
+        let target = self
+            .documented_target()
+            .ok_or_else(|| anyhow::anyhow!("the crate has no library or binary to document"))?;
+        let mut doc_dir = self.host_target_dir();
+        if let Some(target) = options.target_name() {
+            doc_dir.push(target);
+        }
+        let json = doc_dir
+            .join("doc")
+            .join(format!("{}.json", target.name().replace('-', "_")));
+        // Avoid returning the output of a previous command if rustdoc doesn't generate a new one.
+        if json.exists() {
+            std::fs::remove_file(&json)?;
+        }
+
+        let mut flags = options.rustdoc_flags();
+        if !flags.iter().any(|flag| flag == "-Zunstable-options") {
+            flags.push("-Zunstable-options".into());
+        }
+        flags.extend(["--output-format".to_string(), "json".to_string()]);
+
+        let mut cmd = self.cargo().args(&["rustdoc"]);
+        if target.kind().iter().any(|kind| kind == "bin") {
+            cmd = cmd.args(&["--bin", target.name()]);
+        } else {
+            cmd = cmd.args(&["--lib"]);
+        }
+        cmd.args(&options.cargo_flags())
+            .env("CARGO_ENCODED_RUSTDOCFLAGS", flags.join("\x1f"))
+            .run()?;
+
+        if !json.is_file() {
+            anyhow::bail!("rustdoc didn't generate the JSON output");
+        }
+        Ok(json)
+    }
+
+    /// Return the target documented by `cargo rustdoc`: the library of the package being built
+    /// if present, or its first binary.
+    fn documented_target(&self) -> Option<&crate::Target> {
+        let root = match &self.package_dir {
+            Some(dir) => self.metadata.workspace_root().join(dir),
+            None => self.metadata.workspace_root().to_path_buf(),
+        };
+        let package = self
+            .metadata
+            .workspace_members()
+            .find(|package| package.manifest_path().parent() == Some(root.as_path()))?;
+        let targets = package.targets();
+        targets
+            .iter()
+            .find(|target| {
+                target
+                    .kind()
+                    .iter()
+                    .any(|kind| LIB_KINDS.contains(&kind.as_str()))
+            })
+            .or_else(|| {
+                targets
+                    .iter()
+                    .find(|target| target.kind().iter().any(|kind| kind == "bin"))
+            })
+    }
+
+    /// Get the path to the source code on the host machine (outside the sandbox). For crates
+    /// located in a subdirectory of their source (see
+    /// [`Crate::git_subdir`](struct.Crate.html#method.git_subdir)) this is the root of the source.
     pub fn host_source_dir(&self) -> PathBuf {
         self.dir.source_dir()
     }
@@ -314,19 +1241,236 @@ impl<'ws> Build<'ws> {
         self.dir.target_dir()
     }
 
+    /// Archive the contents of the target directory in a tarball written to `dest`, compressing
+    /// it with gzip if `compress` is true. The contents are placed inside a `target` directory at
+    /// the root of the tarball.
+    ///
+    /// The archive captures the artifacts as they are on the host machine (see
+    /// [`host_target_dir`](#method.host_target_dir)), and it's streamed to `dest` without loading
+    /// the whole directory in memory. Symbolic links are archived as links, without following
+    /// them.
+    pub fn archive_target_dir(&self, dest: &Path, compress: bool) -> anyhow::Result<()> {
+        let target_dir = self.host_target_dir();
+        let file = BufWriter::new(File::create(dest)?);
+        if compress {
+            let encoder = GzEncoder::new(file, Compression::default());
+            write_tarball(encoder, &target_dir)?.finish()?.flush()?;
+        } else {
+            write_tarball(file, &target_dir)?.flush()?;
+        }
+        Ok(())
+    }
+
     /// Pre-fetching the dependencies for `-Z build-std` outside the sandbox.
     ///
     /// When this function is called, it is possible to use `-Zbuild-std` inside
     /// the sandbox to build the standard library from source even when
     /// networking is disabled.
+    ///
+    /// `-Zbuild-std` is an unstable cargo feature: on non-nightly toolchains the build inside the
+    /// sandbox needs the `RUSTC_BOOTSTRAP=1` environment variable to use it. If the toolchain
+    /// doesn't support `-Zbuild-std` at all,
+    /// [`PrepareError::BuildStdUnsupported`](enum.PrepareError.html#variant.BuildStdUnsupported)
+    /// is returned for each target.
+    ///
+    /// The dependencies are fetched separately for each target, and a failure for one of them
+    /// doesn't prevent the others from being fetched: check the returned
+    /// [`BuildStdFetch`](struct.BuildStdFetch.html) to see which targets failed.
+    ///
+    /// This installs the `rust-src` component in the toolchain, which is shared with all the other
+    /// builds using it. Use
+    /// [`fetch_build_std_dependencies_preinstalled`](#method.fetch_build_std_dependencies_preinstalled)
+    /// to avoid modifying the toolchain.
+    #[cfg(any(feature = "unstable", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "unstable")))]
+    pub fn fetch_build_std_dependencies(&self, targets: &[&str]) -> anyhow::Result<BuildStdFetch> {
+        crate::prepare::fetch_build_std_deps(
+            &self.dir.workspace,
+            self.toolchain,
+            &self.host_source_dir(),
+            targets,
+            true,
+        )
+    }
+
+    /// Pre-fetching the dependencies for `-Z build-std` outside the sandbox, without installing
+    /// the `rust-src` component in the toolchain.
+    ///
+    /// `-Zbuild-std` can only use the standard library source installed in the toolchain, so it
+    /// can't be provided for a single build. This method behaves like
+    /// [`fetch_build_std_dependencies`](#method.fetch_build_std_dependencies), but it leaves the
+    /// toolchain unchanged and returns
+    /// [`PrepareError::MissingRustSrc`](enum.PrepareError.html#variant.MissingRustSrc) if
+    /// `rust-src` was not installed beforehand with
+    /// [`Toolchain::add_component`](struct.Toolchain.html#method.add_component).
     #[cfg(any(feature = "unstable", doc))]
     #[cfg_attr(docs_rs, doc(cfg(feature = "unstable")))]
-    pub fn fetch_build_std_dependencies(&self, targets: &[&str]) -> anyhow::Result<()> {
-        crate::prepare::fetch_deps(
+    pub fn fetch_build_std_dependencies_preinstalled(
+        &self,
+        targets: &[&str],
+    ) -> anyhow::Result<BuildStdFetch> {
+        crate::prepare::fetch_build_std_deps(
             &self.dir.workspace,
             self.toolchain,
             &self.host_source_dir(),
             targets,
+            false,
         )
     }
 }
+
+/// Targets for which the dependencies of `-Z build-std` were fetched, returned by
+/// [`Build::fetch_build_std_dependencies`](struct.Build.html#method.fetch_build_std_dependencies).
+#[cfg(any(feature = "unstable", doc))]
+#[cfg_attr(docs_rs, doc(cfg(feature = "unstable")))]
+#[must_use = "some targets might have failed to fetch"]
+pub struct BuildStdFetch {
+    pub(crate) fetched: Vec<String>,
+    pub(crate) failed: Vec<(String, anyhow::Error)>,
+}
+
+#[cfg(any(feature = "unstable", doc))]
+impl BuildStdFetch {
+    /// Return the targets whose dependencies were fetched successfully.
+    pub fn fetched(&self) -> &[String] {
+        &self.fetched
+    }
+
+    /// Return the targets whose dependencies couldn't be fetched, alongside the error.
+    pub fn failed(&self) -> &[(String, anyhow::Error)] {
+        &self.failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_seed, package_dir, validate_source_path, write_tarball};
+    use crate::metadata::CargoMetadata;
+    use flate2::{write::GzEncoder, Compression};
+    use std::path::Path;
+
+    #[test]
+    fn test_copy_seed() -> anyhow::Result<()> {
+        let target = tempfile::tempdir()?;
+        std::fs::create_dir_all(target.path().join("debug").join("deps"))?;
+        std::fs::write(target.path().join("debug").join("deps").join("foo"), b"foo")?;
+        std::fs::write(target.path().join(".rustc_info.json"), b"{}")?;
+
+        let archives = tempfile::tempdir()?;
+        let plain = archives.path().join("target.tar");
+        write_tarball(std::fs::File::create(&plain)?, target.path())?;
+        let compressed = archives.path().join("target.tar.gz");
+        let encoder = GzEncoder::new(std::fs::File::create(&compressed)?, Compression::default());
+        write_tarball(encoder, target.path())?.finish()?;
+
+        for seed in [target.path(), plain.as_path(), compressed.as_path()] {
+            let dest = tempfile::tempdir()?;
+            copy_seed(seed, dest.path())?;
+            assert_eq!(
+                std::fs::read(dest.path().join("debug").join("deps").join("foo"))?,
+                b"foo"
+            );
+            assert_eq!(std::fs::read(dest.path().join(".rustc_info.json"))?, b"{}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_copy_seed_symlinks() -> anyhow::Result<()> {
+        let outside = tempfile::tempdir()?;
+        let archives = tempfile::tempdir()?;
+
+        // Symbolic links in a directory seed are copied as links, without following them.
+        let target = tempfile::tempdir()?;
+        std::os::unix::fs::symlink(outside.path(), target.path().join("link"))?;
+        let dest = tempfile::tempdir()?;
+        copy_seed(target.path(), dest.path())?;
+        assert_eq!(
+            std::fs::read_link(dest.path().join("link"))?,
+            outside.path()
+        );
+
+        // Entries can't be written through a symbolic link pointing outside of the target dir.
+        let malicious = archives.path().join("malicious.tar");
+        let mut tar = tar::Builder::new(std::fs::File::create(&malicious)?);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, "target/link", outside.path())?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "target/link/escaped", &b"evil"[..])?;
+        tar.finish()?;
+
+        let dest = tempfile::tempdir()?;
+        assert!(copy_seed(&malicious, dest.path()).is_err());
+        assert!(!outside.path().join("escaped").exists());
+
+        // Hard links are rejected.
+        let hardlink = archives.path().join("hardlink.tar");
+        let mut tar = tar::Builder::new(std::fs::File::create(&hardlink)?);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        tar.append_link(&mut header, "target/passwd", "/etc/passwd")?;
+        tar.finish()?;
+
+        let dest = tempfile::tempdir()?;
+        assert!(copy_seed(&hardlink, dest.path()).is_err());
+        assert!(!dest.path().join("passwd").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_source_path() {
+        assert!(validate_source_path(Path::new("foo.txt")).is_ok());
+        assert!(validate_source_path(Path::new("./tests/broken.rs")).is_ok());
+        assert!(validate_source_path(Path::new("src/../../escape.rs")).is_err());
+        assert!(validate_source_path(Path::new("/etc/passwd")).is_err());
+        assert!(validate_source_path(Path::new("")).is_err());
+        assert!(validate_source_path(Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_package_dir() -> anyhow::Result<()> {
+        let metadata = CargoMetadata::parse(
+            r#"{
+                "packages": [
+                    {
+                        "id": "foo",
+                        "name": "foo",
+                        "version": "0.1.0",
+                        "manifest_path": "/source/crates/foo/Cargo.toml",
+                        "targets": []
+                    },
+                    {
+                        "id": "bar",
+                        "name": "bar",
+                        "version": "0.1.0",
+                        "manifest_path": "/source/Cargo.toml",
+                        "targets": []
+                    }
+                ],
+                "workspace_members": ["foo", "bar"],
+                "workspace_root": "/source"
+            }"#,
+        )?;
+
+        assert_eq!(
+            package_dir(&metadata, "foo").as_deref(),
+            Some(Path::new("crates/foo"))
+        );
+        assert_eq!(
+            package_dir(&metadata, "bar").as_deref(),
+            Some(Path::new(""))
+        );
+        assert_eq!(package_dir(&metadata, "baz"), None);
+
+        Ok(())
+    }
+}
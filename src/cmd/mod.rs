@@ -9,24 +9,26 @@ pub use sandbox::*;
 use crate::native;
 use crate::workspace::Workspace;
 use futures_util::{
-    future::{self, FutureExt},
-    stream::{self, TryStreamExt},
+    future::{self, Either, FutureExt},
+    stream::{self, Stream, TryStreamExt},
 };
-use log::{error, info};
+use log::{error, info, warn};
 use process_lines_actions::InnerState;
 use std::convert::AsRef;
 use std::env::consts::EXE_SUFFIX;
 use std::ffi::{OsStr, OsString};
-use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
 use std::process::{ExitStatus, Stdio};
 use std::time::{Duration, Instant};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
     process::Command as AsyncCommand,
     runtime::Runtime,
     time,
 };
-use tokio_stream::{wrappers::LinesStream, StreamExt};
+use tokio_stream::StreamExt;
 
 lazy_static::lazy_static! {
     // TODO: Migrate to asynchronous code and remove runtime
@@ -70,13 +72,22 @@ pub enum CommandError {
     #[error("command timed out after {0} seconds")]
     Timeout(u64),
 
+    /// The command was still running at its deadline, and it was killed. The time the command had
+    /// left to run when it was started is the first value.
+    #[error("command killed after reaching its deadline ({0:?} after it started)")]
+    DeadlineExceeded(Duration),
+
     /// The command failed to execute.
-    #[error("command failed: {status}\n\n{stderr}")]
+    #[non_exhaustive]
+    #[error("command failed: {status}\n\n{stderr}{}", stdout_section(.stdout))]
     ExecutionFailed {
         /// the exit status we got from the command
         status: ExitStatus,
         /// the stderr output, if it was captured via `.run_capture()`
         stderr: String,
+        /// the stdout output, if it was captured via `.run_capture()`. Only its last
+        /// lines are included in the error message.
+        stdout: String,
     },
 
     /// Killing the underlying process after the timeout failed.
@@ -95,6 +106,12 @@ pub enum CommandError {
     #[error("sandbox image missing from the local system: {0}")]
     SandboxImageMissing(#[source] Box<CommandError>),
 
+    /// The sandbox image is not available for the platform requested (or for the platform of the
+    /// host), or its binaries can't be executed on it. The platform is the first value, for
+    /// example `linux/arm64`.
+    #[error("the sandbox image is not available for the platform {0}")]
+    SandboxImagePlatformMismatch(String),
+
     /// Failed to create the sandbox container
     #[error("sandbox container could not be created: {0}")]
     SandboxContainerCreate(#[source] Box<CommandError>),
@@ -109,11 +126,69 @@ pub enum CommandError {
     #[error("invalid output of `docker inspect`: {0}")]
     InvalidDockerInspectOutput(#[source] serde_json::Error),
 
+    /// The working directory requested for a sandboxed command is absolute or points outside of
+    /// the directory mounted inside the sandbox.
+    #[error("the sandbox working directory {0} is outside of the mounted directory")]
+    InvalidSandboxWorkdir(PathBuf),
+
     /// An I/O error occured while executing the command.
     #[error(transparent)]
     IO(#[from] std::io::Error),
 }
 
+/// Resolve a path relative to the directory mounted in the sandbox, without allowing it to escape
+/// the mounted directory.
+fn resolve_sandbox_workdir(relative: &Path) -> Result<PathBuf, CommandError> {
+    let mut resolved = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(CommandError::InvalidSandboxWorkdir(relative.into()));
+            }
+        }
+    }
+    Ok(container_dirs::WORK_DIR.join(resolved))
+}
+
+/// Maximum number of lines of the standard output included in the message of
+/// [`CommandError::ExecutionFailed`], as the output of a failed build can be huge.
+const STDOUT_ERROR_LINES: usize = 20;
+
+/// Whether a failed command could succeed if it's run again.
+fn is_retryable(err: &CommandError) -> bool {
+    match err {
+        CommandError::ExecutionFailed { .. } => true,
+        CommandError::IO(err) => matches!(
+            err.kind(),
+            std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::WouldBlock
+        ),
+        _ => false,
+    }
+}
+
+fn stdout_section(stdout: &str) -> String {
+    if stdout.is_empty() {
+        return String::new();
+    }
+    let lines = stdout.lines().collect::<Vec<_>>();
+    if lines.len() > STDOUT_ERROR_LINES {
+        format!(
+            "\n\nstdout (last {} of {} lines):\n{}",
+            STDOUT_ERROR_LINES,
+            lines.len(),
+            lines[lines.len() - STDOUT_ERROR_LINES..].join("\n")
+        )
+    } else {
+        format!("\n\nstdout:\n{}", stdout)
+    }
+}
+
 /// Error happened while trying to kill a process.
 #[derive(Debug, thiserror::Error)]
 #[cfg_attr(unix, error(
@@ -142,6 +217,7 @@ impl KillFailedError {
 }
 
 /// Name and kind of a binary executed by [`Command`](struct.Command.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum Binary {
     /// Global binary, available in `$PATH`. Rustwide doesn't apply any tweaks to its execution
@@ -169,6 +245,12 @@ pub trait Runnable {
     }
 }
 
+impl Runnable for Binary {
+    fn name(&self) -> Binary {
+        self.clone()
+    }
+}
+
 impl Runnable for &str {
     fn name(&self) -> Binary {
         Binary::Global(self.into())
@@ -205,10 +287,16 @@ pub struct Command<'w, 'pl> {
     binary: Binary,
     args: Vec<OsString>,
     env: Vec<(OsString, OsString)>,
-    process_lines: Option<&'pl mut dyn FnMut(&str, &mut ProcessLinesActions)>,
+    env_clear: bool,
+    stdin: Option<Vec<u8>>,
+    process_lines: Option<Box<dyn FnMut(&str, OutputKind, &mut ProcessLinesActions) + 'pl>>,
+    output_sink: Option<Box<dyn FnMut(OutputKind, &str) + Send + 'pl>>,
     cd: Option<PathBuf>,
+    sandbox_workdir: Option<PathBuf>,
     timeout: Option<Duration>,
     no_output_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    retry: Option<(u32, Duration)>,
     log_command: bool,
     log_output: bool,
 }
@@ -255,10 +343,16 @@ impl<'w, 'pl> Command<'w, 'pl> {
             binary,
             args: Vec::new(),
             env: Vec::new(),
+            env_clear: false,
+            stdin: None,
             process_lines: None,
+            output_sink: None,
             cd: None,
+            sandbox_workdir: None,
             timeout,
             no_output_timeout,
+            deadline: None,
+            retry: None,
             log_output: true,
             log_command: true,
         }
@@ -281,12 +375,51 @@ impl<'w, 'pl> Command<'w, 'pl> {
         self
     }
 
+    /// Run the command with a clean environment, only containing the variables added with
+    /// [`env`](#method.env) (either before or after calling this method).
+    ///
+    /// Outside the sandbox the command won't inherit the environment of the current process,
+    /// including `PATH`. Inside the sandbox only the explicitly added variables are passed to the
+    /// container, while the variables defined by the sandbox image are still present. In both
+    /// cases `CARGO_HOME` and `RUSTUP_HOME` are still set when running binaries managed by
+    /// rustwide or running in the sandbox, as they're required to find the toolchains.
+    pub fn env_clear(mut self) -> Self {
+        self.env_clear = true;
+        self
+    }
+
+    /// Write the provided data to the standard input of the command. The standard input will be
+    /// closed once all the data is written.
+    ///
+    /// If this method is not called the command inherits the standard input of the current
+    /// process.
+    pub fn stdin(mut self, data: Vec<u8>) -> Self {
+        self.stdin = Some(data);
+        self
+    }
+
     /// Change the directory where the command will be executed in.
+    ///
+    /// For sandboxed commands this is the directory mounted read-only inside the sandbox, and the
+    /// command is executed at the root of the mount. Use
+    /// [`sandbox_workdir`](#method.sandbox_workdir) to execute it in a subdirectory.
     pub fn cd<P: AsRef<Path>>(mut self, path: P) -> Self {
         self.cd = Some(path.as_ref().to_path_buf());
         self
     }
 
+    /// Execute a sandboxed command in a subdirectory of the directory mounted inside the sandbox,
+    /// for example the directory of a member of a Cargo workspace. The path is relative to the
+    /// directory set with [`cd`](#method.cd), and the command fails with
+    /// [`CommandError::InvalidSandboxWorkdir`](enum.CommandError.html#variant.InvalidSandboxWorkdir)
+    /// if it's absolute or if it points outside of the mounted directory.
+    ///
+    /// This has no effect on commands not running inside the sandbox.
+    pub fn sandbox_workdir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.sandbox_workdir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Set the timeout of this command. If it runs for more time the process will be killed.
     ///
     /// Its default value is configured through
@@ -306,6 +439,16 @@ impl<'w, 'pl> Command<'w, 'pl> {
         self
     }
 
+    /// Set the deadline of this command. If it's still running at that time the process will be
+    /// killed, regardless of the output it's producing.
+    ///
+    /// The deadline is enforced in addition to the [`timeout`](#method.timeout) and the
+    /// [`no_output_timeout`](#method.no_output_timeout), and by default no deadline is set.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
     /// Set the function that will be called each time a line is outputted to either the standard
     /// output or the standard error. Only one function can be set at any time for a command.
     ///
@@ -330,7 +473,67 @@ impl<'w, 'pl> Command<'w, 'pl> {
     /// # }
     /// ```
     pub fn process_lines(mut self, f: &'pl mut dyn FnMut(&str, &mut ProcessLinesActions)) -> Self {
-        self.process_lines = Some(f);
+        self.process_lines = Some(Box::new(move |line, _kind, actions| f(line, actions)));
+        self
+    }
+
+    /// Set the function that will be called each time a line is outputted, like
+    /// [`process_lines`](struct.Command.html#method.process_lines), but also passing to it the
+    /// stream the line was printed on. Only one function can be set at any time for a command,
+    /// replacing the one set by either method.
+    ///
+    /// This example only looks for compiler errors (ICEs) in the standard error:
+    ///
+    /// ```no_run
+    /// # use rustwide::{cmd::{Command, OutputKind}, WorkspaceBuilder};
+    /// # use std::error::Error;
+    /// # fn main() -> Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// let mut ice = false;
+    /// Command::new(&workspace, "cargo")
+    ///     .args(&["build", "--all"])
+    ///     .process_lines_with_kind(&mut |line, kind, _| {
+    ///         if kind == OutputKind::Stderr && line.contains("internal compiler error") {
+    ///             ice = true;
+    ///         }
+    ///     })
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn process_lines_with_kind(
+        mut self,
+        f: &'pl mut dyn FnMut(&str, OutputKind, &mut ProcessLinesActions),
+    ) -> Self {
+        self.process_lines = Some(Box::new(f));
+        self
+    }
+
+    /// Set the function that will be called with each line outputted by the command, for example
+    /// to stream the output of a build to a live log viewer.
+    ///
+    /// Unlike [`process_lines`](#method.process_lines) the function is called after the actions
+    /// of the `process_lines` function are applied, so it receives exactly the lines that are
+    /// logged. The function is called even if [`log_output`](#method.log_output) is disabled.
+    #[allow(clippy::type_complexity)]
+    pub fn output_sink(mut self, sink: Box<dyn FnMut(OutputKind, &str) + Send>) -> Self {
+        self.output_sink = Some(sink);
+        self
+    }
+
+    /// Retry the command if it fails with a non-zero exit code or a transient I/O error (for
+    /// example an interrupted system call), running it at most `attempts` times in total. The first retry happens after waiting for `backoff`, and the
+    /// wait is doubled after each failed attempt. If all the attempts fail the error of the last
+    /// one is returned.
+    ///
+    /// The timeouts apply to each attempt separately, while the [`deadline`](#method.deadline)
+    /// applies to all of them. Commands failing because of a timeout or of the deadline are not
+    /// retried, as those failures are usually not transient, and neither are I/O errors like a
+    /// missing binary. The output captured by
+    /// [`run_capture`](#method.run_capture) only contains the output of the last attempt, but the
+    /// [`process_lines`](#method.process_lines) function is called for the output of all of them.
+    pub fn retry(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.retry = Some((attempts, backoff));
         self
     }
 
@@ -354,8 +557,11 @@ impl<'w, 'pl> Command<'w, 'pl> {
 
     /// Run the prepared command and return an error if it fails (for example with a non-zero exit
     /// code or a timeout).
+    ///
+    /// This method blocks until the command ends, and it panics if called inside an async
+    /// context: use [`run_async`](#method.run_async) there instead.
     pub fn run(self) -> Result<(), CommandError> {
-        self.run_inner(false)?;
+        self.run_inner(Capture::None)?;
         Ok(())
     }
 
@@ -366,15 +572,121 @@ impl<'w, 'pl> Command<'w, 'pl> {
     /// is by default) the output will be also logged. You can disable this behavior by calling the
     /// [`log_output`](struct.Command.html#method.log_output) method.
     pub fn run_capture(self) -> Result<ProcessOutput, CommandError> {
-        self.run_inner(true)
+        self.run_inner(Capture::Lines)
     }
 
-    fn run_inner(self, capture: bool) -> Result<ProcessOutput, CommandError> {
-        if let Some(mut builder) = self.sandbox {
+    /// Run the prepared command asynchronously, returning an error if it fails (for example with a
+    /// non-zero exit code or a timeout).
+    ///
+    /// Unlike [`run`](#method.run), this method doesn't block on the runtime used internally by
+    /// rustwide, so it can be called from inside an existing async context. The returned future
+    /// must be polled inside a Tokio runtime with both the I/O and the time drivers enabled, and
+    /// it's not `Send`, as the functions passed to
+    /// [`process_lines`](#method.process_lines) don't need to be.
+    pub async fn run_async(self) -> Result<(), CommandError> {
+        self.run_inner_async(Capture::None).await?;
+        Ok(())
+    }
+
+    /// Run the prepared command asynchronously and return its output if it succeedes. If it fails
+    /// (for example with a non-zero exit code or a timeout) an error will be returned instead.
+    ///
+    /// This is the asynchronous version of [`run_capture`](#method.run_capture), with the same
+    /// requirements as [`run_async`](#method.run_async).
+    pub async fn run_capture_async(self) -> Result<ProcessOutput, CommandError> {
+        self.run_inner_async(Capture::Lines).await
+    }
+
+    /// Run the prepared command and return its raw output if it succeedes. If it fails (for
+    /// example with a non-zero exit code or a timeout) an error will be returned instead.
+    ///
+    /// Unlike [`run_capture`](struct.Command.html#method.run_capture), the output doesn't need
+    /// to be valid UTF-8, and it's returned exactly as the process printed it. Invalid UTF-8 is
+    /// replaced only in the lines passed to the logger and to the
+    /// [`process_lines`](struct.Command.html#method.process_lines) function, whose actions don't
+    /// change the captured output.
+    pub fn run_capture_raw(self) -> Result<RawOutput, CommandError> {
+        let output = self.run_inner(Capture::Raw)?;
+        Ok(RawOutput {
+            stdout: output.raw_stdout,
+            stderr: output.raw_stderr,
+        })
+    }
+
+    /// Return the environment variables set when the command runs inside the sandbox.
+    pub(crate) fn sandbox_env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if !self.env_clear {
+            env.push((
+                "SOURCE_DIR".to_string(),
+                container_dirs::WORK_DIR.to_str().unwrap().to_string(),
+            ));
+        }
+        for (key, value) in &self.env {
+            env.push((
+                key.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            ));
+        }
+        env.push((
+            "CARGO_HOME".to_string(),
+            container_dirs::CARGO_HOME.to_str().unwrap().to_string(),
+        ));
+        env.push((
+            "RUSTUP_HOME".to_string(),
+            container_dirs::RUSTUP_HOME.to_str().unwrap().to_string(),
+        ));
+        env
+    }
+
+    fn run_inner(self, capture: Capture) -> Result<ProcessOutput, CommandError> {
+        let workspace = self.workspace;
+        block_on(workspace, self.run_inner_async(capture))
+    }
+
+    async fn run_inner_async(mut self, capture: Capture) -> Result<ProcessOutput, CommandError> {
+        // The callback is passed down with `.map(|f| f as _)`, shortening the lifetime of the
+        // trait object to the one of the borrow.
+        let mut process_lines = self.process_lines.take();
+        let mut output_sink = self.output_sink.take();
+        let (attempts, mut backoff) = self.retry.unwrap_or((1, Duration::from_secs(0)));
+        let mut attempt = 1;
+        loop {
+            match self
+                .run_once(
+                    process_lines.as_deref_mut().map(|f| f as _),
+                    output_sink.as_deref_mut().map(|f| f as _),
+                    capture,
+                )
+                .await
+            {
+                Err(err) if attempt < attempts && is_retryable(&err) => {
+                    warn!(
+                        "attempt {} of {} failed, retrying in {:?}: {}",
+                        attempt, attempts, backoff, err
+                    );
+                    time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    async fn run_once(
+        &self,
+        process_lines: Option<&mut dyn FnMut(&str, OutputKind, &mut ProcessLinesActions)>,
+        output_sink: Option<&mut (dyn FnMut(OutputKind, &str) + Send)>,
+        capture: Capture,
+    ) -> Result<ProcessOutput, CommandError> {
+        let sandbox_env = self.sandbox_env();
+        if let Some(mut builder) = self.sandbox.clone() {
             let workspace = self
                 .workspace
                 .expect("sandboxed builds without a workspace are not supported");
-            let binary = match self.binary {
+            let binary = match self.binary.clone() {
                 Binary::Global(path) => path,
                 Binary::ManagedByRustwide(path) => {
                     container_dirs::CARGO_BIN_DIR.join(exe_suffix(path.as_os_str()))
@@ -383,30 +695,30 @@ impl<'w, 'pl> Command<'w, 'pl> {
 
             let mut cmd = vec![binary.to_string_lossy().as_ref().to_string()];
 
-            for arg in self.args {
+            for arg in &self.args {
                 cmd.push(arg.to_string_lossy().to_string());
             }
 
-            let source_dir = match self.cd {
-                Some(path) => path,
+            let source_dir = match &self.cd {
+                Some(path) => path.clone(),
                 None => PathBuf::from("."),
             };
+            let workdir = match &self.sandbox_workdir {
+                Some(path) => resolve_sandbox_workdir(path)?,
+                None => container_dirs::WORK_DIR.clone(),
+            };
 
             builder = builder
                 .mount(&source_dir, &container_dirs::WORK_DIR, MountKind::ReadOnly)
-                .env("SOURCE_DIR", container_dirs::WORK_DIR.to_str().unwrap())
-                .workdir(container_dirs::WORK_DIR.to_str().unwrap())
+                .workdir(workdir.to_str().unwrap())
                 .cmd(cmd);
 
             if let Some(user) = native::current_user() {
-                builder = builder.user(user.user_id, user.group_id);
+                builder = builder.default_user(user.user_id, user.group_id);
             }
 
-            for (key, value) in self.env {
-                builder = builder.env(
-                    key.to_string_lossy().as_ref(),
-                    value.to_string_lossy().as_ref(),
-                );
+            for (key, value) in sandbox_env {
+                builder = builder.env(key, value);
             }
 
             builder = builder
@@ -419,21 +731,24 @@ impl<'w, 'pl> Command<'w, 'pl> {
                     &workspace.rustup_home(),
                     &container_dirs::RUSTUP_HOME,
                     MountKind::ReadOnly,
-                )
-                .env("CARGO_HOME", container_dirs::CARGO_HOME.to_str().unwrap())
-                .env("RUSTUP_HOME", container_dirs::RUSTUP_HOME.to_str().unwrap());
+                );
 
-            builder.run(
+            // Running the sandbox runs other commands, so the future is boxed to allow recursion.
+            let run: Pin<Box<dyn Future<Output = _> + '_>> = Box::pin(builder.run(
                 workspace,
                 self.timeout,
                 self.no_output_timeout,
-                self.process_lines,
+                self.deadline,
+                process_lines,
+                output_sink,
+                self.stdin.clone(),
                 self.log_output,
                 self.log_command,
                 capture,
-            )
+            ));
+            run.await
         } else {
-            let (binary, managed_by_rustwide) = match self.binary {
+            let (binary, managed_by_rustwide) = match self.binary.clone() {
                 // global paths should never be normalized
                 Binary::Global(path) => (path, false),
                 Binary::ManagedByRustwide(path) => {
@@ -450,6 +765,9 @@ impl<'w, 'pl> Command<'w, 'pl> {
 
             let mut cmd = AsyncCommand::new(binary);
             cmd.args(&self.args);
+            if self.env_clear {
+                cmd.env_clear();
+            }
 
             if managed_by_rustwide {
                 let workspace = self
@@ -488,36 +806,66 @@ impl<'w, 'pl> Command<'w, 'pl> {
                 info!("running `{}`", cmdstr);
             }
 
-            let out = RUNTIME
-                .block_on(log_command(
-                    cmd,
-                    self.process_lines,
-                    capture,
-                    self.timeout,
-                    self.no_output_timeout,
-                    self.log_output,
-                ))
-                .map_err(|e| {
-                    error!("error running command: {}", e);
-                    e
-                })?;
+            let out = log_command(
+                cmd,
+                process_lines,
+                output_sink,
+                self.stdin.clone(),
+                capture,
+                self.timeout,
+                self.no_output_timeout,
+                self.deadline,
+                self.log_output,
+            )
+            .await
+            .map_err(|e| {
+                error!("error running command: {}", e);
+                e
+            })?;
 
             if out.status.success() {
                 Ok(out.into())
+            } else if capture == Capture::Raw {
+                Err(CommandError::ExecutionFailed {
+                    status: out.status,
+                    stderr: String::from_utf8_lossy(&out.raw_stderr).trim_end().into(),
+                    stdout: String::from_utf8_lossy(&out.raw_stdout).trim_end().into(),
+                })
             } else {
                 Err(CommandError::ExecutionFailed {
                     status: out.status,
                     stderr: out.stderr.join("\n"),
+                    stdout: out.stdout.join("\n"),
                 })
             }
         }
     }
 }
 
+/// Block on the future with the runtime configured in the workspace, or with the runtime shared
+/// by all the workspaces if none is configured.
+pub(super) fn block_on<F: Future>(workspace: Option<&Workspace>, future: F) -> F::Output {
+    match workspace.and_then(|workspace| workspace.tokio_handle()) {
+        Some(handle) => handle.block_on(future),
+        None => RUNTIME.block_on(future),
+    }
+}
+
+/// How the output of a command is captured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Capture {
+    None,
+    Lines,
+    Raw,
+}
+
 struct InnerProcessOutput {
     status: ExitStatus,
     stdout: Vec<String>,
     stderr: Vec<String>,
+    combined: Vec<(OutputKind, String)>,
+    raw_stdout: Vec<u8>,
+    raw_stderr: Vec<u8>,
 }
 
 impl From<InnerProcessOutput> for ProcessOutput {
@@ -525,6 +873,9 @@ impl From<InnerProcessOutput> for ProcessOutput {
         ProcessOutput {
             stdout: orig.stdout,
             stderr: orig.stderr,
+            combined: orig.combined,
+            raw_stdout: orig.raw_stdout,
+            raw_stderr: orig.raw_stderr,
         }
     }
 }
@@ -534,6 +885,10 @@ impl From<InnerProcessOutput> for ProcessOutput {
 pub struct ProcessOutput {
     stdout: Vec<String>,
     stderr: Vec<String>,
+    combined: Vec<(OutputKind, String)>,
+    // Only filled when running the command with `run_capture_raw`.
+    raw_stdout: Vec<u8>,
+    raw_stderr: Vec<u8>,
 }
 
 impl ProcessOutput {
@@ -546,10 +901,40 @@ impl ProcessOutput {
     pub fn stderr_lines(&self) -> &[String] {
         &self.stderr
     }
+
+    /// Return a list of the lines printed by the process on both the standard output and the
+    /// standard error, in the order they were received, along with the stream they came from.
+    pub fn combined_lines(&self) -> &[(OutputKind, String)] {
+        &self.combined
+    }
+}
+
+/// Output of a [`Command`](struct.Command.html) when it was executed with the
+/// [`run_capture_raw`](struct.Command.html#method.run_capture_raw) method.
+pub struct RawOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl RawOutput {
+    /// Return the bytes printed by the process on the standard output.
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// Return the bytes printed by the process on the standard error.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
 }
 
-enum OutputKind {
+/// Stream a line of output was printed on, returned by
+/// [`ProcessOutput::combined_lines`](struct.ProcessOutput.html#method.combined_lines).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OutputKind {
+    /// The standard output of the process.
     Stdout,
+    /// The standard error of the process.
     Stderr,
 }
 
@@ -562,13 +947,17 @@ impl OutputKind {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
 async fn log_command(
     mut cmd: AsyncCommand,
-    mut process_lines: Option<&mut dyn FnMut(&str, &mut ProcessLinesActions)>,
-    capture: bool,
+    mut process_lines: Option<&mut dyn FnMut(&str, OutputKind, &mut ProcessLinesActions)>,
+    mut output_sink: Option<&mut (dyn FnMut(OutputKind, &str) + Send)>,
+    stdin: Option<Vec<u8>>,
+    capture: Capture,
     timeout: Option<Duration>,
     no_output_timeout: Option<Duration>,
+    deadline: Option<Instant>,
     log_output: bool,
 ) -> Result<InnerProcessOutput, CommandError> {
     let timeout = if let Some(t) = timeout {
@@ -585,13 +974,29 @@ async fn log_command(
         timeout
     };
 
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
     let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
     let child_id = child.id().unwrap();
 
-    let stdout = LinesStream::new(BufReader::new(child.stdout.take().unwrap()).lines())
-        .map(|line| (OutputKind::Stdout, line));
-    let stderr = LinesStream::new(BufReader::new(child.stderr.take().unwrap()).lines())
-        .map(|line| (OutputKind::Stderr, line));
+    // The input is written concurrently with reading the output, so a process that doesn't drain
+    // its standard input is still subject to the timeouts instead of blocking rustwide.
+    let child_stdin = child.stdin.take();
+    let input = async move {
+        if let (Some(mut pipe), Some(data)) = (child_stdin, stdin) {
+            match pipe.write_all(&data).await {
+                // The process exited (or closed its standard input) before reading everything.
+                Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {}
+                other => other?,
+            }
+            // Dropping the pipe closes the standard input of the process.
+        }
+        Ok::<_, CommandError>(())
+    };
+
+    let stdout = raw_lines(child.stdout.take().unwrap()).map(|line| (OutputKind::Stdout, line));
+    let stderr = raw_lines(child.stderr.take().unwrap()).map(|line| (OutputKind::Stderr, line));
 
     let start = Instant::now();
     let mut actions = ProcessLinesActions::new();
@@ -611,15 +1016,29 @@ async fn log_command(
             // If the read was successful, return the `OutputKind` and the read line
             Ok((out_kind, Ok(line))) => Ok((out_kind, line)),
         })
-        .and_then(move |(kind, line): (OutputKind, String)| {
+        .and_then(move |(kind, raw): (OutputKind, Vec<u8>)| {
             // If the process is in a tight output loop the timeout on the process might fail to
             // be executed, so this extra check prevents the process to run without limits.
             if start.elapsed() > timeout {
                 return future::err(CommandError::Timeout(timeout.as_secs()));
             }
 
+            let line = strip_newline(&raw);
+            let line = if capture == Capture::Raw {
+                String::from_utf8_lossy(line).into_owned()
+            } else {
+                match String::from_utf8(line.to_vec()) {
+                    Ok(line) => line,
+                    Err(err) => {
+                        return future::err(
+                            std::io::Error::new(std::io::ErrorKind::InvalidData, err).into(),
+                        )
+                    }
+                }
+            };
+
             if let Some(f) = &mut process_lines {
-                f(&line, &mut actions);
+                f(&line, kind, &mut actions);
             }
             // this is done here to avoid duplicating the output line
             let lines = match actions.take_lines() {
@@ -633,22 +1052,38 @@ async fn log_command(
                     info!("[{}] {}", kind.prefix(), line);
                 }
             }
+            if let Some(sink) = &mut output_sink {
+                for line in &lines {
+                    sink(kind, line);
+                }
+            }
 
-            future::ok((kind, lines))
+            future::ok((kind, lines, raw))
         })
         .try_fold(
-            (Vec::<String>::new(), Vec::<String>::new()),
-            move |(mut stdout, mut stderr), (kind, mut lines)| async move {
+            CapturedOutput::default(),
+            move |mut captured, (kind, mut lines, mut raw)| async move {
                 // If stdio/stdout is supposed to be captured, append it to
-                // the accumulated stdio/stdout
-                if capture {
-                    match kind {
-                        OutputKind::Stdout => stdout.append(&mut lines),
-                        OutputKind::Stderr => stderr.append(&mut lines),
+                // the accumulated stdio/stdout, and keep track of the order
+                // the lines were received in
+                match capture {
+                    Capture::None => {}
+                    Capture::Lines => {
+                        captured
+                            .combined
+                            .extend(lines.iter().map(|line| (kind, line.clone())));
+                        match kind {
+                            OutputKind::Stdout => captured.stdout.append(&mut lines),
+                            OutputKind::Stderr => captured.stderr.append(&mut lines),
+                        }
                     }
+                    Capture::Raw => match kind {
+                        OutputKind::Stdout => captured.raw_stdout.append(&mut raw),
+                        OutputKind::Stderr => captured.raw_stderr.append(&mut raw),
+                    },
                 }
 
-                Ok((stdout, stderr))
+                Ok(captured)
             },
         );
 
@@ -668,22 +1103,385 @@ async fn log_command(
         }
     });
 
-    let ((stdout, stderr), status) = {
-        let (output, child) = future::join(output, child).await;
-        let (stdout, stderr) = output?;
+    // Unlike the timeouts, the deadline is enforced even if the process is blocked without
+    // producing any output or exiting.
+    let deadline = async move {
+        match deadline {
+            Some(deadline) => {
+                time::sleep_until(deadline.into()).await;
+                deadline
+            }
+            None => future::pending().await,
+        }
+    };
+
+    let (captured, status) = {
+        let joined = future::join3(output, child, input);
+        let (output, child, input) =
+            match future::select(Box::pin(joined), Box::pin(deadline)).await {
+                Either::Left((joined, _)) => joined,
+                Either::Right((deadline, _)) => {
+                    return Err(match native::kill_process(child_id) {
+                        Ok(()) => CommandError::DeadlineExceeded(deadline.duration_since(start)),
+                        Err(err) => CommandError::KillAfterTimeoutFailed(err),
+                    });
+                }
+            };
+        let captured = output?;
+        input?;
 
-        ((stdout, stderr), child?)
+        (captured, child?)
     };
 
     Ok(InnerProcessOutput {
         status,
-        stdout,
-        stderr,
+        stdout: captured.stdout,
+        stderr: captured.stderr,
+        combined: captured.combined,
+        raw_stdout: captured.raw_stdout,
+        raw_stderr: captured.raw_stderr,
     })
 }
 
+#[derive(Default)]
+struct CapturedOutput {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    combined: Vec<(OutputKind, String)>,
+    raw_stdout: Vec<u8>,
+    raw_stderr: Vec<u8>,
+}
+
+/// Stream of the lines read from the reader, including the trailing newline. Unlike
+/// `AsyncBufReadExt::lines` the lines don't need to be valid UTF-8.
+fn raw_lines<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    stream::unfold(BufReader::new(reader), |mut reader| async move {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(line), reader)),
+            Err(err) => Some((Err(err), reader)),
+        }
+    })
+}
+
+/// Remove the trailing `\n` or `\r\n` from a line, like `AsyncBufReadExt::lines` does.
+fn strip_newline(line: &[u8]) -> &[u8] {
+    match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => line,
+    }
+}
+
 fn exe_suffix(file: &OsStr) -> OsString {
     let mut path = OsString::from(file);
     path.push(EXE_SUFFIX);
     path
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        container_dirs, resolve_sandbox_workdir, Command, CommandError, OutputKind,
+        ProcessLinesActions,
+    };
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_resolve_sandbox_workdir() {
+        let work_dir = &*container_dirs::WORK_DIR;
+        let resolve = |path: &str| resolve_sandbox_workdir(Path::new(path)).ok();
+
+        assert_eq!(resolve("").as_deref(), Some(work_dir.as_path()));
+        assert_eq!(resolve("foo/bar"), Some(work_dir.join("foo").join("bar")));
+        assert_eq!(resolve("./foo/../bar"), Some(work_dir.join("bar")));
+        assert_eq!(resolve(".."), None);
+        assert_eq!(resolve("foo/../../bar"), None);
+        assert_eq!(resolve("/foo"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_combined_lines() -> anyhow::Result<()> {
+        let output = Command::new_workspaceless("sh")
+            .args(&["-c", "echo a; sleep 0.2; echo b >&2; sleep 0.2; echo c"])
+            .log_output(false)
+            .run_capture()?;
+
+        assert_eq!(output.stdout_lines(), ["a", "c"]);
+        assert_eq!(output.stderr_lines(), ["b"]);
+        assert_eq!(
+            output.combined_lines(),
+            [
+                (OutputKind::Stdout, "a".to_string()),
+                (OutputKind::Stderr, "b".to_string()),
+                (OutputKind::Stdout, "c".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_async() -> anyhow::Result<()> {
+        // The async methods work inside a runtime different from the one used by rustwide.
+        let runtime = tokio::runtime::Runtime::new()?;
+        let output = runtime.block_on(async {
+            Command::new_workspaceless("sh")
+                .args(&["-c", "echo out; echo err >&2"])
+                .log_output(false)
+                .run_capture_async()
+                .await
+        })?;
+        assert_eq!(output.stdout_lines(), ["out"]);
+        assert_eq!(output.stderr_lines(), ["err"]);
+
+        let err = runtime.block_on(Command::new_workspaceless("false").run_async());
+        assert!(matches!(err, Err(CommandError::ExecutionFailed { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_process_lines_with_kind() -> anyhow::Result<()> {
+        let mut stderr = Vec::new();
+        let output = Command::new_workspaceless("sh")
+            .args(&["-c", "echo out; echo err >&2"])
+            .process_lines_with_kind(&mut |line, kind, actions| {
+                if kind == OutputKind::Stderr {
+                    stderr.push(line.to_string());
+                    actions.remove_line();
+                }
+            })
+            .log_output(false)
+            .run_capture()?;
+
+        assert_eq!(stderr, ["err"]);
+        assert_eq!(output.stdout_lines(), ["out"]);
+        assert!(output.stderr_lines().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_output_sink() -> anyhow::Result<()> {
+        let sunk = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let sunk = sunk.clone();
+            Box::new(move |kind, line: &str| sunk.lock().unwrap().push((kind, line.to_string())))
+        };
+        Command::new_workspaceless("sh")
+            .args(&["-c", "echo out; echo secret >&2"])
+            .process_lines(&mut |line, actions| {
+                if line == "secret" {
+                    actions.replace_with_lines(std::iter::once("[redacted]"));
+                }
+            })
+            .output_sink(sink)
+            .log_output(false)
+            .run()?;
+
+        let mut sunk = sunk.lock().unwrap().clone();
+        sunk.sort_by_key(|(kind, _)| *kind == OutputKind::Stderr);
+        assert_eq!(
+            sunk,
+            [
+                (OutputKind::Stdout, "out".to_string()),
+                (OutputKind::Stderr, "[redacted]".to_string())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_execution_failed_output() {
+        let err = Command::new_workspaceless("sh")
+            .args(&["-c", "echo out; echo err >&2; exit 1"])
+            .log_output(false)
+            .run_capture()
+            .err()
+            .expect("the command should fail");
+        match &err {
+            CommandError::ExecutionFailed { stdout, stderr, .. } => {
+                assert_eq!(stdout, "out");
+                assert_eq!(stderr, "err");
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(err.to_string().ends_with("\n\nerr\n\nstdout:\nout"));
+    }
+
+    #[test]
+    fn test_execution_failed_output_truncated() {
+        let stdout = (1..=30).map(|i| i.to_string()).collect::<Vec<_>>();
+        let section = super::stdout_section(&stdout.join("\n"));
+        assert_eq!(
+            section,
+            format!(
+                "\n\nstdout (last 20 of 30 lines):\n{}",
+                stdout[10..].join("\n")
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_deadline() {
+        let start = Instant::now();
+        // The process doesn't output anything, and the other timeouts are disabled.
+        let res = Command::new_workspaceless("sleep")
+            .args(&["30"])
+            .timeout(None)
+            .no_output_timeout(None)
+            .deadline(start + Duration::from_secs(1))
+            .run();
+
+        match res {
+            Err(CommandError::DeadlineExceeded(remaining)) => {
+                assert!(remaining <= Duration::from_secs(1))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_retry() -> anyhow::Result<()> {
+        // Each attempt appends a line to the `attempts` file, and prints its number first.
+        let script = "echo >> attempts; n=$(wc -l < attempts); echo attempt $n; [ $n -ge 3 ]";
+        let count_attempts = |dir: &Path| -> anyhow::Result<usize> {
+            Ok(std::fs::read_to_string(dir.join("attempts"))?
+                .lines()
+                .count())
+        };
+
+        // The command succeeds at the third attempt.
+        let dir = tempfile::tempdir()?;
+        let mut attempts = Vec::new();
+        let mut record = |line: &str, _: &mut ProcessLinesActions| {
+            if line.starts_with("attempt ") {
+                attempts.push(line.to_string());
+            }
+        };
+        let output = Command::new_workspaceless("sh")
+            .args(&["-c", &format!("{} && echo done", script)])
+            .cd(dir.path())
+            .retry(3, Duration::from_millis(10))
+            .process_lines(&mut record)
+            .log_output(false)
+            .run_capture()?;
+        assert_eq!(attempts, ["attempt 1", "attempt 2", "attempt 3"]);
+        assert_eq!(output.stdout_lines().last().unwrap(), "done");
+        assert_eq!(count_attempts(dir.path())?, 3);
+
+        // All the attempts fail, and the last error is returned.
+        let dir = tempfile::tempdir()?;
+        let start = Instant::now();
+        let res = Command::new_workspaceless("sh")
+            .args(&["-c", "echo >> attempts; echo failed >&2; exit 1"])
+            .cd(dir.path())
+            .retry(3, Duration::from_millis(100))
+            .log_output(false)
+            .run();
+        assert_eq!(count_attempts(dir.path())?, 3);
+        match res {
+            Err(CommandError::ExecutionFailed { status, .. }) => {
+                assert_eq!(status.code(), Some(1))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        // The backoff is doubled after each attempt.
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        // Timeouts are not retried.
+        let start = Instant::now();
+        let res = Command::new_workspaceless("sleep")
+            .args(&["30"])
+            .timeout(Some(Duration::from_secs(1)))
+            .retry(3, Duration::from_secs(0))
+            .run();
+        assert!(matches!(
+            res,
+            Err(CommandError::Timeout(1)) | Err(CommandError::NoOutputFor(1))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // Missing binaries fail at the first attempt.
+        let start = Instant::now();
+        let res = Command::new_workspaceless("rustwide-missing-binary")
+            .retry(3, Duration::from_secs(10))
+            .run();
+        match res {
+            Err(CommandError::IO(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_capture_raw() -> anyhow::Result<()> {
+        let script = r"printf 'valid\n\377invalid\r\n'; printf 'err' >&2";
+
+        let res = Command::new_workspaceless("sh")
+            .args(&["-c", script])
+            .log_output(false)
+            .run_capture();
+        assert!(matches!(res, Err(CommandError::IO(_))));
+
+        let output = Command::new_workspaceless("sh")
+            .args(&["-c", script])
+            .run_capture_raw()?;
+        assert_eq!(output.stdout(), b"valid\n\xffinvalid\r\n");
+        assert_eq!(output.stderr(), b"err");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_env_clear() -> anyhow::Result<()> {
+        let output = Command::new_workspaceless("env")
+            .env("RUSTWIDE_TEST", "foo")
+            .env_clear()
+            .log_output(false)
+            .run_capture()?;
+        assert_eq!(output.stdout_lines(), ["RUSTWIDE_TEST=foo"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stdin() -> anyhow::Result<()> {
+        let output = Command::new_workspaceless("cat")
+            .stdin(b"hello\nworld\n".to_vec())
+            .log_output(false)
+            .run_capture()?;
+        assert_eq!(output.stdout_lines(), ["hello", "world"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stdin_not_drained() {
+        // The process never reads its input, which is bigger than the pipe buffer.
+        let res = Command::new_workspaceless("sleep")
+            .args(&["10"])
+            .stdin(vec![0; 1024 * 1024])
+            .no_output_timeout(Some(Duration::from_secs(1)))
+            .run();
+        assert!(matches!(res, Err(CommandError::NoOutputFor(1))));
+    }
+}
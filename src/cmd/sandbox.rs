@@ -1,11 +1,21 @@
-use crate::cmd::{Command, CommandError, ProcessLinesActions, ProcessOutput};
+use crate::cmd::{
+    block_on, Capture, Command, CommandError, OutputKind, ProcessLinesActions, ProcessOutput,
+};
+use crate::metrics::{timed_async, Operation};
 use crate::Workspace;
 use log::{error, info};
+use scopeguard::ScopeGuard;
 use serde::Deserialize;
 use std::error::Error;
 use std::fmt;
+use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+pub(crate) static DEFAULT_CONTAINER_RUNTIME: &str = "docker";
+
+/// Files in the cargo home cargo locks to synchronize the access to its registry cache.
+static CARGO_CACHE_LOCK_FILES: &[&str] = &[".package-cache", ".package-cache-mutate"];
 
 /// The Docker image used for sandboxing.
 pub struct SandboxImage {
@@ -17,9 +27,18 @@ impl SandboxImage {
     ///
     /// If the image is not available locally an error will be returned instead.
     pub fn local(name: &str) -> Result<Self, CommandError> {
+        Self::local_with_runtime(name, DEFAULT_CONTAINER_RUNTIME)
+    }
+
+    /// Load a local image present in the host machine, using the provided container runtime
+    /// (like `podman`) instead of `docker`. See
+    /// [`WorkspaceBuilder::container_runtime`](../struct.WorkspaceBuilder.html#method.container_runtime).
+    ///
+    /// If the image is not available locally an error will be returned instead.
+    pub fn local_with_runtime(name: &str, runtime: &str) -> Result<Self, CommandError> {
         let image = SandboxImage { name: name.into() };
         info!("sandbox image is local, skipping pull");
-        image.ensure_exists_locally()?;
+        image.ensure_exists_locally(runtime)?;
         Ok(image)
     }
 
@@ -28,23 +47,72 @@ impl SandboxImage {
     /// This will access the network to download the image from the registry. If pulling fails an
     /// error will be returned instead.
     pub fn remote(name: &str) -> Result<Self, CommandError> {
+        Self::remote_with_runtime(name, DEFAULT_CONTAINER_RUNTIME)
+    }
+
+    /// Pull an image from its Docker registry, using the provided container runtime (like
+    /// `podman`) instead of `docker`. See
+    /// [`WorkspaceBuilder::container_runtime`](../struct.WorkspaceBuilder.html#method.container_runtime).
+    ///
+    /// This will access the network to download the image from the registry. If pulling fails an
+    /// error will be returned instead.
+    pub fn remote_with_runtime(name: &str, runtime: &str) -> Result<Self, CommandError> {
         let mut image = SandboxImage { name: name.into() };
-        info!("pulling image {} from Docker Hub", name);
-        Command::new_workspaceless("docker")
-            .args(&["pull", name])
-            .run()
-            .map_err(|e| CommandError::SandboxImagePullFailed(Box::new(e)))?;
-        if let Some(name_with_hash) = image.get_name_with_hash() {
+        image.pull(runtime)?;
+        if let Some(name_with_hash) = image.get_name_with_hash(runtime) {
             image.name = name_with_hash;
             info!("pulled image {}", image.name);
         }
-        image.ensure_exists_locally()?;
+        image.ensure_exists_locally(runtime)?;
+        Ok(image)
+    }
+
+    /// Pull an image from its Docker registry, pinned to the provided digest (for example
+    /// `sha256:0123...`).
+    ///
+    /// Unlike [`remote`](#method.remote) the image is not resolved from its tag, so the same image
+    /// is used every time even if the tag is later updated to point to a different image. If
+    /// pulling fails an error will be returned instead.
+    pub fn remote_by_digest(name: &str, digest: &str) -> Result<Self, CommandError> {
+        Self::remote_by_digest_with_runtime(name, digest, DEFAULT_CONTAINER_RUNTIME)
+    }
+
+    /// Pull an image from its Docker registry pinned to the provided digest, using the provided
+    /// container runtime (like `podman`) instead of `docker`. See
+    /// [`WorkspaceBuilder::container_runtime`](../struct.WorkspaceBuilder.html#method.container_runtime).
+    pub fn remote_by_digest_with_runtime(
+        name: &str,
+        digest: &str,
+        runtime: &str,
+    ) -> Result<Self, CommandError> {
+        let image = SandboxImage {
+            name: reference_by_digest(name, digest),
+        };
+        image.pull(runtime)?;
+        image.ensure_exists_locally(runtime)?;
         Ok(image)
     }
 
-    fn ensure_exists_locally(&self) -> Result<(), CommandError> {
+    fn pull(&self, runtime: &str) -> Result<(), CommandError> {
+        info!("pulling image {} from Docker Hub", self.name);
+        let mut mismatch = None;
+        let res = Command::new_workspaceless(runtime)
+            .args(&["pull", &self.name])
+            .process_lines(&mut |line, _| {
+                if let Some(platform) = platform_from_pull_error(line) {
+                    mismatch = Some(platform);
+                }
+            })
+            .run();
+        res.map_err(|e| match mismatch {
+            Some(platform) => CommandError::SandboxImagePlatformMismatch(platform),
+            None => CommandError::SandboxImagePullFailed(Box::new(e)),
+        })
+    }
+
+    fn ensure_exists_locally(&self, runtime: &str) -> Result<(), CommandError> {
         info!("checking the image {} is available locally", self.name);
-        Command::new_workspaceless("docker")
+        Command::new_workspaceless(runtime)
             .args(&["image", "inspect", &self.name])
             .log_output(false)
             .run()
@@ -52,8 +120,8 @@ impl SandboxImage {
         Ok(())
     }
 
-    fn get_name_with_hash(&self) -> Option<String> {
-        Command::new_workspaceless("docker")
+    fn get_name_with_hash(&self, runtime: &str) -> Option<String> {
+        Command::new_workspaceless(runtime)
             .args(&[
                 "inspect",
                 &self.name,
@@ -69,6 +137,31 @@ impl SandboxImage {
     }
 }
 
+/// Extract the platform from the error emitted when pulling an image not built for it, like
+/// `no matching manifest for linux/arm64/v8 in the manifest list entries`.
+fn platform_from_pull_error(line: &str) -> Option<String> {
+    let rest = line.split("no matching manifest for ").nth(1)?;
+    let platform = rest.split(" in the manifest").next().unwrap_or(rest).trim();
+    Some(platform.into())
+}
+
+/// Platform of the host in the format used by Docker, for example `linux/amd64`.
+fn host_platform() -> String {
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        other => other,
+    };
+    format!("{}/{}", std::env::consts::OS, arch)
+}
+
+fn reference_by_digest(name: &str, digest: &str) -> String {
+    // Any digest already present in the name is replaced by the pinned one.
+    let name = name.split('@').next().unwrap_or(name);
+    format!("{}@{}", name, digest)
+}
+
 /// Whether to mount a path in the sandbox with write permissions or not.
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -142,10 +235,19 @@ pub struct SandboxBuilder {
     env: Vec<(String, String)>,
     memory_limit: Option<usize>,
     cpu_limit: Option<f32>,
+    pids_limit: Option<u64>,
+    read_only_rootfs: bool,
+    tmpfs: Vec<(PathBuf, Option<usize>)>,
     workdir: Option<String>,
-    user: Option<String>,
+    // `None` if the user was not configured, `Some(None)` if the image's default user is used.
+    user: Option<Option<(u32, u32)>>,
     cmd: Vec<String>,
-    enable_networking: bool,
+    network: Option<String>,
+    entrypoint: Option<String>,
+    hostname: Option<String>,
+    cargo_registry_cache: Option<PathBuf>,
+    extra_docker_args: Vec<String>,
+    platform: Option<String>,
 }
 
 impl SandboxBuilder {
@@ -157,9 +259,17 @@ impl SandboxBuilder {
             workdir: None,
             memory_limit: None,
             cpu_limit: None,
+            pids_limit: None,
+            read_only_rootfs: false,
+            tmpfs: Vec::new(),
             user: None,
             cmd: Vec::new(),
-            enable_networking: true,
+            network: None,
+            entrypoint: None,
+            hostname: None,
+            cargo_registry_cache: None,
+            extra_docker_args: Vec::new(),
+            platform: None,
         }
     }
 
@@ -194,12 +304,122 @@ impl SandboxBuilder {
         self
     }
 
+    /// Enable or disable the sandbox's limit on the number of processes and threads, protecting
+    /// the host from fork bombs. When the limit is reached, creating new processes or threads
+    /// inside the sandbox fails.
+    ///
+    /// By default no limit is present.
+    pub fn pids_limit(mut self, limit: Option<u64>) -> Self {
+        self.pids_limit = limit;
+        self
+    }
+
+    /// Enable or disable mounting the root filesystem of the sandbox as read-only. The
+    /// directories mounted with [`mount`](#method.mount) keep their own permissions, so for
+    /// example the target directory of a build is still writable. Use [`tmpfs`](#method.tmpfs)
+    /// to provide writable scratch directories, like `/tmp`.
+    ///
+    /// By default the root filesystem is writable.
+    pub fn read_only_rootfs(mut self, read_only: bool) -> Self {
+        self.read_only_rootfs = read_only;
+        self
+    }
+
+    /// Mount an empty and writable in-memory filesystem at the provided path inside the sandbox,
+    /// optionally limiting its size (in bytes). Its contents are discarded when the sandbox
+    /// exits. This is only supported on Linux hosts.
+    pub fn tmpfs(mut self, path: &Path, size: Option<usize>) -> Self {
+        self.tmpfs.push((path.into(), size));
+        self
+    }
+
     /// Enable or disable the sandbox's networking. When it's disabled processes inside the sandbox
     /// won't be able to reach network service on the Internet or the host machine.
     ///
-    /// By default networking is enabled.
-    pub fn enable_networking(mut self, enable: bool) -> Self {
-        self.enable_networking = enable;
+    /// By default networking is enabled. Disabling it is the same as calling
+    /// [`network`](#method.network) with `Some("none")`, and enabling it is the same as calling it
+    /// with `None`: if both methods are called the last call wins.
+    pub fn enable_networking(self, enable: bool) -> Self {
+        self.network(if enable { None } else { Some("none".into()) })
+    }
+
+    /// Connect the sandbox to the provided Docker network, for example a network with access to
+    /// an internal crate mirror. When `None` is provided the sandbox uses the default network.
+    ///
+    /// This overrides any previous call to [`enable_networking`](#method.enable_networking), and
+    /// if both methods are called the last call wins.
+    pub fn network(mut self, network: Option<String>) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Override the entrypoint of the sandbox image. The command run in the sandbox is passed as
+    /// arguments to the entrypoint.
+    ///
+    /// By default (or when `None` is passed) the image's own entrypoint is used. Passing an empty
+    /// string clears the image's entrypoint, running the command directly: this is useful with
+    /// images whose entrypoint interferes with the commands rustwide runs.
+    pub fn entrypoint(mut self, entrypoint: Option<String>) -> Self {
+        self.entrypoint = entrypoint;
+        self
+    }
+
+    /// Set the hostname of the sandbox. By default the hostname is the ID of the container, which
+    /// changes every time a command is run.
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Run the sandbox with the image built for the provided platform (for example
+    /// `linux/amd64`), instead of the platform of the host. Running an image built for a
+    /// different architecture requires emulation to be configured on the host.
+    ///
+    /// If the image is not available for the platform, or it can't be executed, running the
+    /// command fails with
+    /// [`CommandError::SandboxImagePlatformMismatch`](enum.CommandError.html#variant.SandboxImagePlatformMismatch).
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
+    /// Share a writable cache of cargo's registry and git checkouts between sandboxes, stored in
+    /// the provided directory of the host. The `registry` and `git` subdirectories of the cache
+    /// are mounted read-write in place of the ones of the (read-only) cargo home, so dependencies
+    /// downloaded by a sandbox with networking enabled are reused by the following ones.
+    ///
+    /// The cache hides the dependencies fetched by rustwide while preparing a build, so it's only
+    /// useful for sandboxes with networking enabled. The files cargo locks while accessing its
+    /// cache are stored in the same directory and mounted in the cargo home, so concurrent
+    /// sandboxes sharing the cache are synchronized by cargo itself.
+    pub fn cargo_registry_cache(mut self, host_dir: &Path) -> Self {
+        for dir in &["registry", "git"] {
+            self = self.mount(
+                &host_dir.join(dir),
+                &super::container_dirs::CARGO_HOME.join(dir),
+                MountKind::ReadWrite,
+            );
+        }
+        for file in CARGO_CACHE_LOCK_FILES {
+            self = self.mount(
+                &host_dir.join(file),
+                &super::container_dirs::CARGO_HOME.join(file),
+                MountKind::ReadWrite,
+            );
+        }
+        self.cargo_registry_cache = Some(host_dir.into());
+        self
+    }
+
+    /// Pass additional arguments to the `docker create` command used to create the sandbox, for
+    /// flags rustwide doesn't have a method for (like `--ulimit`, `--pids-limit` or `--tmpfs`).
+    ///
+    /// The arguments are appended verbatim after all the flags set by rustwide, right before the
+    /// name of the image and the command to run. Calling this method multiple times appends more
+    /// arguments. Arguments conflicting with the ones set by rustwide (for example mounting over
+    /// the directories used by rustwide) can break the sandbox.
+    pub fn extra_docker_args(mut self, args: Vec<String>) -> Self {
+        self.extra_docker_args.extend(args);
         self
     }
 
@@ -218,14 +438,37 @@ impl SandboxBuilder {
         self
     }
 
-    pub(super) fn user(mut self, user: u32, group: u32) -> Self {
-        self.user = Some(format!("{}:{}", user, group));
+    /// Set the user ID and group ID the commands inside the sandbox run as. Passing `None` uses
+    /// the default user of the sandbox image, which is needed for example with rootless Docker
+    /// or Podman, where the user inside the container is mapped to a different user on the host.
+    ///
+    /// By default (if this method is not called) on Linux the commands run as the user running
+    /// rustwide, so that the files they write to the mounted directories are owned by it.
+    pub fn run_as_user(mut self, user: Option<(u32, u32)>) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Run the commands as the provided user, unless another one was configured through
+    /// [`run_as_user`](#method.run_as_user).
+    pub(super) fn default_user(mut self, user: u32, group: u32) -> Self {
+        if self.user.is_none() {
+            self.user = Some(Some((user, group)));
+        }
         self
     }
 
-    fn create(self, workspace: &Workspace) -> Result<Container<'_>, CommandError> {
+    async fn create(
+        self,
+        workspace: &Workspace,
+        stdin: bool,
+    ) -> Result<Container<'_>, CommandError> {
         let mut args: Vec<String> = vec!["create".into()];
 
+        if stdin {
+            args.push("--interactive".into());
+        }
+
         for mount in &self.mounts {
             std::fs::create_dir_all(&mount.host_path)?;
 
@@ -260,71 +503,161 @@ impl SandboxBuilder {
             args.push(limit.to_string());
         }
 
-        if let Some(user) = self.user {
+        if let Some(limit) = self.pids_limit {
+            args.push("--pids-limit".into());
+            args.push(limit.to_string());
+        }
+
+        if self.read_only_rootfs {
+            args.push("--read-only".into());
+        }
+
+        for (path, size) in &self.tmpfs {
+            args.push("--tmpfs".into());
+            match size {
+                Some(size) => args.push(format!("{}:size={}", path.display(), size)),
+                None => args.push(path.display().to_string()),
+            }
+        }
+
+        if let Some(Some((user, group))) = self.user {
             args.push("--user".into());
-            args.push(user);
+            args.push(format!("{}:{}", user, group));
         }
 
-        if !self.enable_networking {
+        if let Some(network) = self.network {
             args.push("--network".into());
-            args.push("none".into());
+            args.push(network);
+        }
+
+        if let Some(hostname) = self.hostname {
+            args.push("--hostname".into());
+            args.push(hostname);
+        }
+
+        if let Some(entrypoint) = self.entrypoint {
+            args.push("--entrypoint".into());
+            args.push(entrypoint);
+        }
+
+        if let Some(platform) = &self.platform {
+            args.push("--platform".into());
+            args.push(platform.clone());
         }
 
         if cfg!(windows) {
             args.push("--isolation=process".into());
         }
 
+        args.extend(self.extra_docker_args);
+
         args.push(workspace.sandbox_image().name.clone());
 
         for arg in self.cmd {
             args.push(arg);
         }
 
-        let out = Command::new(workspace, "docker")
+        let platform = self.platform;
+        let out = Command::new(workspace, workspace.container_runtime())
             .args(&args)
-            .run_capture()
-            .map_err(|err| CommandError::SandboxContainerCreate(Box::new(err)))?;
+            .run_inner_async(Capture::Lines)
+            .await
+            .map_err(|err| match &err {
+                CommandError::ExecutionFailed { stderr, .. }
+                    if stderr.contains("no matching manifest") =>
+                {
+                    CommandError::SandboxImagePlatformMismatch(
+                        platform.clone().unwrap_or_else(host_platform),
+                    )
+                }
+                _ => CommandError::SandboxContainerCreate(Box::new(err)),
+            })?;
         Ok(Container {
             id: out.stdout_lines()[0].clone(),
             workspace,
+            platform,
         })
     }
 
     #[allow(clippy::too_many_arguments)]
     #[allow(clippy::type_complexity)]
-    pub(super) fn run(
+    pub(super) async fn run(
         self,
         workspace: &Workspace,
         timeout: Option<Duration>,
         no_output_timeout: Option<Duration>,
-        process_lines: Option<&mut dyn FnMut(&str, &mut ProcessLinesActions)>,
+        deadline: Option<Instant>,
+        process_lines: Option<&mut dyn FnMut(&str, OutputKind, &mut ProcessLinesActions)>,
+        output_sink: Option<&mut (dyn FnMut(OutputKind, &str) + Send)>,
+        stdin: Option<Vec<u8>>,
         log_output: bool,
         log_command: bool,
-        capture: bool,
+        capture: Capture,
     ) -> Result<ProcessOutput, CommandError> {
-        let container = self.create(workspace)?;
-
-        // Ensure the container is properly deleted even if something panics
-        scopeguard::defer! {{
-            if let Err(err) = container.delete() {
-                error!("failed to delete container {}", container.id);
-                error!("caused by: {}", err);
-                let mut err: &dyn Error = &err;
-                while let Some(cause) = err.source() {
-                    error!("caused by: {}", cause);
-                    err = cause;
+        if let Some(dir) = &self.cargo_registry_cache {
+            // The lock files must exist before being mounted, both in the cache and in the
+            // read-only cargo home they're mounted in.
+            std::fs::create_dir_all(dir)?;
+            for file in CARGO_CACHE_LOCK_FILES {
+                for dir in &[dir.clone(), workspace.cargo_home()] {
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(dir.join(file))?;
                 }
             }
-        }}
-
-        container.run(
-            timeout,
-            no_output_timeout,
-            process_lines,
-            log_output,
-            log_command,
-            capture,
+        }
+
+        let container = timed_async(
+            workspace.metrics(),
+            Operation::ContainerCreate,
+            self.create(workspace, stdin.is_some()),
         )
+        .await?;
+        workspace.container_created(&container.id);
+
+        // Ensure the container is properly deleted even if something panics or the future is
+        // dropped before completing. The deletion can't be awaited there, so it blocks on a
+        // separate thread, which is allowed even when called inside an async context.
+        let guard = scopeguard::guard(&container, |container| {
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    if let Err(err) = block_on(Some(container.workspace), container.delete()) {
+                        log_delete_error(container, &err);
+                    }
+                });
+            });
+        });
+
+        let res = container
+            .run(
+                timeout,
+                no_output_timeout,
+                deadline,
+                process_lines,
+                output_sink,
+                stdin,
+                log_output,
+                log_command,
+                capture,
+            )
+            .await;
+
+        let container = ScopeGuard::into_inner(guard);
+        if let Err(err) = container.delete().await {
+            log_delete_error(container, &err);
+        }
+        res
+    }
+}
+
+fn log_delete_error(container: &Container<'_>, err: &CommandError) {
+    error!("failed to delete container {}", container.id);
+    error!("caused by: {}", err);
+    let mut err: &dyn Error = err;
+    while let Some(cause) = err.source() {
+        error!("caused by: {}", cause);
+        err = cause;
     }
 }
 
@@ -345,6 +678,7 @@ struct Container<'w> {
     // Docker container ID
     id: String,
     workspace: &'w Workspace,
+    platform: Option<String>,
 }
 
 impl fmt::Display for Container<'_> {
@@ -354,11 +688,12 @@ impl fmt::Display for Container<'_> {
 }
 
 impl Container<'_> {
-    fn inspect(&self) -> Result<InspectContainer, CommandError> {
-        let output = Command::new(self.workspace, "docker")
+    async fn inspect(&self) -> Result<InspectContainer, CommandError> {
+        let output = Command::new(self.workspace, self.workspace.container_runtime())
             .args(&["inspect", &self.id])
             .log_output(false)
-            .run_capture()?;
+            .run_capture_async()
+            .await?;
 
         let mut data: Vec<InspectContainer> =
             ::serde_json::from_str(&output.stdout_lines().join("\n"))
@@ -367,29 +702,59 @@ impl Container<'_> {
         Ok(data.pop().unwrap())
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[allow(clippy::type_complexity)]
-    fn run(
+    async fn run(
         &self,
         timeout: Option<Duration>,
         no_output_timeout: Option<Duration>,
-        process_lines: Option<&mut dyn FnMut(&str, &mut ProcessLinesActions)>,
+        deadline: Option<Instant>,
+        process_lines: Option<&mut dyn FnMut(&str, OutputKind, &mut ProcessLinesActions)>,
+        output_sink: Option<&mut (dyn FnMut(OutputKind, &str) + Send)>,
+        stdin: Option<Vec<u8>>,
         log_output: bool,
         log_command: bool,
-        capture: bool,
+        capture: Capture,
     ) -> Result<ProcessOutput, CommandError> {
-        let mut cmd = Command::new(self.workspace, "docker")
-            .args(&["start", "-a", &self.id])
+        let mut args = vec!["start", "-a"];
+        if stdin.is_some() {
+            args.push("--interactive");
+        }
+        args.push(&self.id);
+
+        // Binaries built for a different architecture fail to start, which is detected from the
+        // first line of output to return a more actionable error.
+        let mut first_line = None;
+        let mut process_lines = process_lines;
+        let mut detect_platform_mismatch =
+            |line: &str, kind: OutputKind, actions: &mut ProcessLinesActions| {
+                if first_line.is_none() {
+                    first_line = Some((kind, line.to_string()));
+                }
+                if let Some(f) = process_lines.as_mut() {
+                    f(line, kind, actions);
+                }
+            };
+
+        let mut cmd = Command::new(self.workspace, self.workspace.container_runtime())
+            .args(&args)
             .timeout(timeout)
             .log_output(log_output)
             .log_command(log_command)
             .no_output_timeout(no_output_timeout);
-
-        if let Some(f) = process_lines {
-            cmd = cmd.process_lines(f);
+        cmd = cmd.process_lines_with_kind(&mut detect_platform_mismatch);
+        if let Some(sink) = output_sink {
+            cmd.output_sink = Some(Box::new(move |kind, line| sink(kind, line)));
+        }
+        if let Some(data) = stdin {
+            cmd = cmd.stdin(data);
+        }
+        if let Some(deadline) = deadline {
+            cmd = cmd.deadline(deadline);
         }
 
-        let res = cmd.run_inner(capture);
-        let details = self.inspect()?;
+        let res = cmd.run_inner_async(capture).await;
+        let details = self.inspect().await?;
 
         // Return a different error if the container was killed due to an OOM
         if details.state.oom_killed {
@@ -397,28 +762,139 @@ impl Container<'_> {
                 Ok(_) | Err(CommandError::ExecutionFailed { .. }) => CommandError::SandboxOOM,
                 Err(err) => err,
             })
+        } else if is_platform_mismatch(first_line.as_ref(), &res) {
+            Err(CommandError::SandboxImagePlatformMismatch(
+                self.platform.clone().unwrap_or_else(host_platform),
+            ))
         } else {
             res
         }
     }
 
-    fn delete(&self) -> Result<(), CommandError> {
-        Command::new(self.workspace, "docker")
-            .args(&["rm", "-f", &self.id])
-            .run()
+    async fn delete(&self) -> Result<(), CommandError> {
+        timed_async(
+            self.workspace.metrics(),
+            Operation::ContainerDelete,
+            Command::new(self.workspace, self.workspace.container_runtime())
+                .args(&["rm", "-f", &self.id])
+                .run_async(),
+        )
+        .await?;
+        self.workspace.container_deleted(&self.id);
+        Ok(())
     }
 }
 
+/// Return whether the run failed because the container runtime couldn't execute the entrypoint
+/// of the image, as it was built for a different platform. The runtime reports it on the standard
+/// error before any output of the process (as none was started) and exits with the status used
+/// for commands that can't be executed.
+fn is_platform_mismatch(
+    first_line: Option<&(OutputKind, String)>,
+    res: &Result<ProcessOutput, CommandError>,
+) -> bool {
+    let cannot_execute = match res {
+        Err(CommandError::ExecutionFailed { status, .. }) => {
+            matches!(status.code(), Some(126) | Some(127))
+        }
+        _ => false,
+    };
+    cannot_execute
+        && matches!(first_line, Some((OutputKind::Stderr, line)) if line.contains("exec format error"))
+}
+
 /// Check whether the Docker daemon is running.
 ///
 /// The Docker daemon is required for sandboxing to work, and this function returns whether the
 /// daemon is online and reachable or not. Calling a sandboxed command when the daemon is offline
-/// will error too, but this function allows the caller to error earlier.
+/// will error too, but this function allows the caller to error earlier. The check uses the
+/// container runtime configured in the workspace.
 pub fn docker_running(workspace: &Workspace) -> bool {
     info!("checking if the docker daemon is running");
-    Command::new(workspace, "docker")
+    Command::new(workspace, workspace.container_runtime())
         .args(&["info"])
         .log_output(false)
         .run()
         .is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{is_platform_mismatch, platform_from_pull_error, reference_by_digest};
+    use crate::cmd::{CommandError, OutputKind, ProcessOutput};
+
+    #[test]
+    fn test_platform_from_pull_error() {
+        assert_eq!(
+            platform_from_pull_error(
+                "no matching manifest for linux/arm64/v8 in the manifest list entries"
+            )
+            .as_deref(),
+            Some("linux/arm64/v8")
+        );
+        assert_eq!(
+            platform_from_pull_error(
+                "Error response from daemon: no matching manifest for linux/amd64 in the \
+                 manifest list entries"
+            )
+            .as_deref(),
+            Some("linux/amd64")
+        );
+        assert_eq!(
+            platform_from_pull_error("Pulling from library/ubuntu"),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_platform_mismatch() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let failed = |code: i32| -> Result<ProcessOutput, CommandError> {
+            Err(CommandError::ExecutionFailed {
+                status: std::process::ExitStatus::from_raw(code << 8),
+                stderr: String::new(),
+                stdout: String::new(),
+            })
+        };
+        let line = |kind, line: &str| Some((kind, line.to_string()));
+        let exec_error = line(OutputKind::Stderr, "exec /usr/bin/cargo: exec format error");
+
+        assert!(is_platform_mismatch(exec_error.as_ref(), &failed(126)));
+        assert!(is_platform_mismatch(exec_error.as_ref(), &failed(127)));
+
+        // Processes that ran and mentioned the error are not affected.
+        assert!(!is_platform_mismatch(exec_error.as_ref(), &failed(1)));
+        let success = Ok(ProcessOutput {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            combined: Vec::new(),
+            raw_stdout: Vec::new(),
+            raw_stderr: Vec::new(),
+        });
+        assert!(!is_platform_mismatch(exec_error.as_ref(), &success));
+        let after_output = line(OutputKind::Stdout, "running 1 test");
+        assert!(!is_platform_mismatch(after_output.as_ref(), &failed(126)));
+        assert!(!is_platform_mismatch(None, &failed(126)));
+    }
+
+    #[test]
+    fn test_reference_by_digest() {
+        const DIGEST: &str =
+            "sha256:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+        assert_eq!(
+            reference_by_digest("ghcr.io/rust-lang/crates-build-env/linux", DIGEST),
+            format!("ghcr.io/rust-lang/crates-build-env/linux@{}", DIGEST)
+        );
+        assert_eq!(
+            reference_by_digest("ubuntu:22.04", DIGEST),
+            format!("ubuntu:22.04@{}", DIGEST)
+        );
+        assert_eq!(
+            reference_by_digest("ubuntu@sha256:bbbb", DIGEST),
+            format!("ubuntu@{}", DIGEST)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use super::CrateTrait;
+use crate::Workspace;
+use flate2::read::GzDecoder;
+use log::info;
+use std::path::Path;
+use tar::Archive;
+
+pub(super) struct InMemoryCrate {
+    name: String,
+    version: String,
+    bytes: Vec<u8>,
+}
+
+impl InMemoryCrate {
+    pub(super) fn new(name: &str, version: &str, bytes: Vec<u8>) -> Self {
+        InMemoryCrate {
+            name: name.into(),
+            version: version.into(),
+            bytes,
+        }
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(super) fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub(super) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Return the contents of the `Cargo.toml` at the root of the tarball, if present.
+    pub(super) fn manifest(&self) -> anyhow::Result<Option<String>> {
+        let mut tar = Archive::new(GzDecoder::new(self.bytes.as_slice()));
+        super::registry::manifest_from_tarball(&mut tar)
+    }
+
+    fn unpack(&self, dest: &Path, max_size: Option<u64>) -> anyhow::Result<()> {
+        info!(
+            "extracting crate {} {} into {}",
+            self.name,
+            self.version,
+            dest.display()
+        );
+        let mut tar = Archive::new(GzDecoder::new(self.bytes.as_slice()));
+        if let Err(err) = super::registry::unpack_without_first_dir(&mut tar, dest, max_size) {
+            let _ = crate::utils::remove_dir_all(dest);
+            Err(err.context(format!(
+                "unable to extract {} version {}",
+                self.name, self.version
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl CrateTrait for InMemoryCrate {
+    fn fetch(&self, _workspace: &Workspace) -> anyhow::Result<()> {
+        // There is no fetch to do for a crate already in memory.
+        Ok(())
+    }
+
+    fn purge_from_cache(&self, _workspace: &Workspace) -> anyhow::Result<()> {
+        // There is no cache to purge for a crate already in memory.
+        Ok(())
+    }
+
+    fn copy_source_to(&self, workspace: &Workspace, dest: &Path) -> anyhow::Result<()> {
+        self.unpack(dest, workspace.max_extracted_crate_size())
+    }
+}
+
+impl std::fmt::Display for InMemoryCrate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "in-memory crate {} {}", self.name, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryCrate;
+    use flate2::{write::GzEncoder, Compression};
+
+    fn tarball(files: &[(&str, &str)]) -> anyhow::Result<Vec<u8>> {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, path, content.as_bytes())?;
+        }
+        Ok(tar.into_inner()?.finish()?)
+    }
+
+    #[test]
+    fn test_unpack() -> anyhow::Result<()> {
+        let bytes = tarball(&[
+            (
+                "foo-0.1.0/Cargo.toml",
+                "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+            ),
+            ("foo-0.1.0/src/main.rs", "fn main() {}\n"),
+        ])?;
+        let krate = InMemoryCrate::new("foo", "0.1.0", bytes);
+
+        let dest = tempfile::tempdir()?;
+        krate.unpack(dest.path(), None)?;
+        assert!(std::fs::read_to_string(dest.path().join("Cargo.toml"))?.contains("name = \"foo\""));
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("src").join("main.rs"))?,
+            "fn main() {}\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest() -> anyhow::Result<()> {
+        let bytes = tarball(&[
+            ("foo-0.1.0/src/Cargo.toml", "not the manifest"),
+            (
+                "foo-0.1.0/Cargo.toml",
+                "[package]\nname = \"foo\"\nrust-version = \"1.60\"\n",
+            ),
+        ])?;
+        let krate = InMemoryCrate::new("foo", "0.1.0", bytes);
+        assert_eq!(
+            krate.manifest()?.as_deref(),
+            Some("[package]\nname = \"foo\"\nrust-version = \"1.60\"\n")
+        );
+
+        let empty = InMemoryCrate::new("foo", "0.1.0", tarball(&[])?);
+        assert_eq!(empty.manifest()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_invalid() -> anyhow::Result<()> {
+        let krate = InMemoryCrate::new("foo", "0.1.0", b"not a tarball".to_vec());
+
+        let dest = tempfile::tempdir()?;
+        let path = dest.path().join("source");
+        assert!(krate.unpack(&path, None).is_err());
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_size_limit() -> anyhow::Result<()> {
+        use crate::crates::CrateError;
+
+        let big = "a".repeat(1024);
+        let bytes = tarball(&[
+            ("foo-0.1.0/Cargo.toml", "[package]\nname = \"foo\"\n"),
+            ("foo-0.1.0/src/big.txt", &big),
+        ])?;
+        // The compressed archive is much smaller than its contents.
+        assert!(bytes.len() < big.len());
+        let krate = InMemoryCrate::new("foo", "0.1.0", bytes);
+
+        let dest = tempfile::tempdir()?;
+        let path = dest.path().join("source");
+        let err = krate.unpack(&path, Some(512)).unwrap_err();
+        match err.downcast_ref::<CrateError>() {
+            Some(CrateError::ExtractedSizeLimitExceeded { limit }) => assert_eq!(*limit, 512),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert!(!path.exists());
+
+        krate.unpack(&path, Some(2048))?;
+        assert!(path.join("src").join("big.txt").is_file());
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use super::CrateTrait;
-use crate::cmd::{Command, ProcessLinesActions};
+use crate::cmd::{Command, CommandError, ProcessLinesActions, ProcessOutput};
 use crate::prepare::PrepareError;
 use crate::Workspace;
 use anyhow::Context as _;
@@ -8,18 +8,86 @@ use std::path::{Path, PathBuf};
 
 pub(super) struct GitRepo {
     url: String,
+    rev: Option<String>,
+    depth: Option<u32>,
+    submodules: bool,
+    subdir: Option<PathBuf>,
 }
 
 impl GitRepo {
     pub(super) fn new(url: &str) -> Self {
-        Self { url: url.into() }
+        Self {
+            url: url.into(),
+            rev: None,
+            depth: None,
+            submodules: false,
+            subdir: None,
+        }
+    }
+
+    pub(super) fn with_rev(mut self, rev: &str) -> Self {
+        self.rev = Some(rev.into());
+        self
+    }
+
+    pub(super) fn with_depth(mut self, depth: u32) -> Self {
+        // Git rejects a depth of zero.
+        self.depth = Some(depth.max(1));
+        self
+    }
+
+    pub(super) fn with_submodules(mut self, submodules: bool) -> Self {
+        self.submodules = submodules;
+        self
+    }
+
+    pub(super) fn with_subdir(mut self, subdir: &Path) -> Self {
+        self.subdir = Some(subdir.into());
+        self
+    }
+
+    pub(super) fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(super) fn rev(&self) -> Option<&str> {
+        self.rev.as_deref()
+    }
+
+    pub(super) fn depth(&self) -> Option<u32> {
+        self.depth
+    }
+
+    pub(super) fn submodules(&self) -> bool {
+        self.submodules
+    }
+
+    pub(super) fn subdir(&self) -> Option<&Path> {
+        self.subdir.as_deref()
+    }
+
+    /// Arguments limiting the history fetched from the repository, if the cached repository is
+    /// shallow or a new shallow clone is being created.
+    fn depth_args(&self, workspace: &Workspace) -> Vec<String> {
+        match self.depth {
+            Some(depth)
+                if !self.cached_path(workspace).join("HEAD").is_file()
+                    || self.is_shallow(workspace) =>
+            {
+                vec!["--depth".into(), depth.to_string()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Return whether the cached repository has a truncated history.
+    fn is_shallow(&self, workspace: &Workspace) -> bool {
+        self.cached_path(workspace).join("shallow").exists()
     }
 
     pub(super) fn git_commit(&self, workspace: &Workspace) -> Option<String> {
-        let res = Command::new(workspace, "git")
-            .args(&["rev-parse", "HEAD"])
-            .cd(self.cached_path(workspace))
-            .run_capture();
+        let rev = self.rev.as_deref().unwrap_or("HEAD");
+        let res = self.resolve_rev(workspace, rev);
 
         match res {
             Ok(out) => {
@@ -28,7 +96,7 @@ impl GitRepo {
                         return Some(shaline.to_string());
                     }
                 }
-                warn!("bad output from `git rev-parse HEAD`");
+                warn!("bad output from `git rev-parse {}`", rev);
             }
             Err(e) => {
                 warn!("unable to capture sha for {}: {}", self.url, e);
@@ -37,6 +105,82 @@ impl GitRepo {
         None
     }
 
+    /// Return the contents of the crate's `Cargo.toml` at the pinned revision (or `HEAD`), if
+    /// the repository was fetched. The cached repository is bare, so the file is read from the
+    /// git objects rather than from a working tree.
+    pub(super) fn manifest(&self, workspace: &Workspace) -> anyhow::Result<Option<String>> {
+        let path = self.cached_path(workspace);
+        if !path.join("HEAD").is_file() {
+            return Ok(None);
+        }
+
+        let mut manifest = match &self.subdir {
+            // Git always uses forward slashes in the paths of the objects.
+            Some(subdir) => format!("{}/", subdir.to_string_lossy().replace('\\', "/")),
+            None => String::new(),
+        };
+        manifest.push_str("Cargo.toml");
+        let object = format!("{}:{}", self.rev.as_deref().unwrap_or("HEAD"), manifest);
+
+        let exists = Command::new(workspace, "git")
+            .args(&["cat-file", "-e", "--end-of-options", &object])
+            .cd(&path)
+            .log_output(false)
+            .run();
+        if exists.is_err() {
+            return Ok(None);
+        }
+        let out = Command::new(workspace, "git")
+            .args(&["cat-file", "blob", "--end-of-options", &object])
+            .cd(&path)
+            .log_output(false)
+            .run_capture()?;
+        Ok(Some(out.stdout_lines().join("\n")))
+    }
+
+    /// Resolve a revision to the hash of its commit in the cached repository.
+    fn resolve_rev(&self, workspace: &Workspace, rev: &str) -> Result<ProcessOutput, CommandError> {
+        Command::new(workspace, "git")
+            .args(&["rev-parse", "--verify", "--quiet", "--end-of-options"])
+            .args(&[format!("{}^{{commit}}", rev)])
+            .cd(self.cached_path(workspace))
+            .log_output(false)
+            .run_capture()
+    }
+
+    /// Fetch the pinned revision if it's missing from the cached repository, for example a
+    /// commit not reachable from any branch or tag. The revision is stored in a dedicated ref, to
+    /// keep it in the cache when the branches are pruned.
+    fn fetch_rev(&self, workspace: &Workspace, rev: &str) -> anyhow::Result<()> {
+        if self.resolve_rev(workspace, rev).is_ok() {
+            return Ok(());
+        }
+
+        info!("fetching revision {} of {}", rev, self.url);
+        let path = self.cached_path(workspace);
+        Command::new(workspace, "git")
+            .args(&self.suppress_password_prompt_args(workspace))
+            .args(&["fetch"])
+            .args(&self.depth_args(workspace))
+            .args(&["--end-of-options", "origin", rev])
+            .cd(&path)
+            .run()
+            .with_context(|| format!("failed to fetch revision {} of {}", rev, self.url))?;
+        let commit = self
+            .resolve_rev(workspace, "FETCH_HEAD")?
+            .stdout_lines()
+            .join("");
+        Command::new(workspace, "git")
+            .args(&[
+                "update-ref",
+                &format!("refs/rustwide/pinned/{}", commit),
+                &commit,
+            ])
+            .cd(&path)
+            .run()?;
+        Ok(())
+    }
+
     fn cached_path(&self, workspace: &Workspace) -> PathBuf {
         workspace
             .cache_dir()
@@ -71,7 +215,7 @@ impl CrateTrait for GitRepo {
         //
         let mut private_repository = false;
         let mut detect_private_repositories = |line: &str, _actions: &mut ProcessLinesActions| {
-            if line.starts_with("fatal: credential helper") && line.ends_with("told us to quit") {
+            if is_private_repository_error(line) {
                 private_repository = true;
             }
         };
@@ -79,10 +223,15 @@ impl CrateTrait for GitRepo {
         let path = self.cached_path(workspace);
         let res = if path.join("HEAD").is_file() {
             info!("updating cached repository {}", self.url);
+            // A cached shallow clone is converted to a full one when the full history is needed,
+            // while a cached full clone is kept full, as it also contains the shallow history.
+            let unshallow = self.depth.is_none() && self.is_shallow(workspace);
             Command::new(workspace, "git")
                 .args(&self.suppress_password_prompt_args(workspace))
                 .args(&["-c", "remote.origin.fetch=refs/heads/*:refs/heads/*"])
                 .args(&["fetch", "origin", "--force", "--prune"])
+                .args(&self.depth_args(workspace))
+                .args(if unshallow { &["--unshallow"][..] } else { &[] })
                 .cd(&path)
                 .process_lines(&mut detect_private_repositories)
                 .run()
@@ -92,6 +241,7 @@ impl CrateTrait for GitRepo {
             Command::new(workspace, "git")
                 .args(&self.suppress_password_prompt_args(workspace))
                 .args(&["clone", "--bare", &self.url])
+                .args(&self.depth_args(workspace))
                 .args(&[&path])
                 .process_lines(&mut detect_private_repositories)
                 .run()
@@ -99,10 +249,14 @@ impl CrateTrait for GitRepo {
         };
 
         if private_repository && res.is_err() {
-            Err(PrepareError::PrivateGitRepository.into())
-        } else {
-            Ok(res?)
+            return Err(PrepareError::PrivateGitRepository.into());
+        }
+        res?;
+
+        if let Some(rev) = &self.rev {
+            self.fetch_rev(workspace, rev)?;
         }
+        Ok(())
     }
 
     fn purge_from_cache(&self, workspace: &Workspace) -> anyhow::Result<()> {
@@ -119,12 +273,75 @@ impl CrateTrait for GitRepo {
             .args(&[self.cached_path(workspace).as_path(), dest])
             .run()
             .with_context(|| format!("failed to checkout {}", self.url))?;
+
+        if let Some(rev) = &self.rev {
+            // The revision is resolved in the cached repository, as the clone doesn't copy the
+            // refs created by `fetch_rev`. Its objects are still available in the clone.
+            let commit = self
+                .resolve_rev(workspace, rev)
+                .with_context(|| format!("revision {} not found in {}", rev, self.url))?
+                .stdout_lines()
+                .join("");
+            Command::new(workspace, "git")
+                .args(&["checkout", "--detach", &commit])
+                .cd(dest)
+                .run()
+                .with_context(|| format!("failed to checkout {} of {}", rev, self.url))?;
+        }
+
+        if self.submodules {
+            self.update_submodules(workspace, dest)?;
+        }
         Ok(())
     }
 }
 
+impl GitRepo {
+    /// Initialize and fetch the submodules of the checkout, recursively.
+    fn update_submodules(&self, workspace: &Workspace, dest: &Path) -> anyhow::Result<()> {
+        // Relative submodule URLs are resolved against the URL of the origin remote, which is
+        // the cached repository after the clone.
+        Command::new(workspace, "git")
+            .args(&["remote", "set-url", "origin", &self.url])
+            .cd(dest)
+            .run()?;
+
+        info!("fetching the submodules of {}", self.url);
+        let mut private_repository = false;
+        let res = Command::new(workspace, "git")
+            .args(&self.suppress_password_prompt_args(workspace))
+            .args(&["submodule", "update", "--init", "--recursive"])
+            .cd(dest)
+            .process_lines(&mut |line, _| {
+                if is_private_repository_error(line) {
+                    private_repository = true;
+                }
+            })
+            .run()
+            .with_context(|| format!("failed to fetch the submodules of {}", self.url));
+
+        if private_repository && res.is_err() {
+            Err(PrepareError::PrivateGitRepository.into())
+        } else {
+            res
+        }
+    }
+}
+
+/// Return whether the line is the message the credential helper suppressing the password prompt
+/// shows when a repository requires authentication:
+///
+///    fatal: credential helper '{path}' told us to quit
+///
+fn is_private_repository_error(line: &str) -> bool {
+    line.starts_with("fatal: credential helper") && line.ends_with("told us to quit")
+}
+
 impl std::fmt::Display for GitRepo {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "git repo {}", self.url)
+        match &self.rev {
+            Some(rev) => write!(f, "git repo {} at {}", self.url, rev),
+            None => write!(f, "git repo {}", self.url),
+        }
     }
 }
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+/// Entry of a registry index describing a published version of a crate, returned by
+/// [`Crate::index_entry`](struct.Crate.html#method.index_entry).
+///
+/// The entry is parsed from the [index format] shared by all Cargo registries.
+///
+/// [index format]: https://doc.rust-lang.org/cargo/reference/registry-index.html#json-schema
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub(super) name: String,
+    pub(super) vers: String,
+    #[serde(default)]
+    pub(super) deps: Vec<IndexDependency>,
+    pub(super) cksum: String,
+    #[serde(default)]
+    pub(super) features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(super) features2: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(super) yanked: bool,
+    #[serde(default)]
+    pub(super) links: Option<String>,
+    #[serde(default)]
+    pub(super) rust_version: Option<String>,
+}
+
+impl IndexEntry {
+    /// Name of the crate.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the crate.
+    pub fn version(&self) -> &str {
+        &self.vers
+    }
+
+    /// SHA256 checksum of the `.crate` file.
+    pub fn checksum(&self) -> &str {
+        &self.cksum
+    }
+
+    /// Dependencies of this version of the crate.
+    pub fn dependencies(&self) -> &[IndexDependency] {
+        &self.deps
+    }
+
+    /// Features defined by this version of the crate, mapped to the features and dependencies
+    /// they enable.
+    pub fn features(&self) -> BTreeMap<&str, &[String]> {
+        // Features using the newer syntax are stored separately in `features2`.
+        self.features
+            .iter()
+            .chain(self.features2.iter())
+            .map(|(name, enables)| (name.as_str(), enables.as_slice()))
+            .collect()
+    }
+
+    /// Whether this version of the crate was yanked.
+    pub fn is_yanked(&self) -> bool {
+        self.yanked
+    }
+
+    /// Name of the native library this crate links to, as declared by the `links` key of its
+    /// `Cargo.toml`.
+    pub fn links(&self) -> Option<&str> {
+        self.links.as_deref()
+    }
+
+    /// Minimum supported Rust version declared by the crate, if any.
+    pub fn rust_version(&self) -> Option<&str> {
+        self.rust_version.as_deref()
+    }
+}
+
+/// Dependency of a crate, as recorded in a registry index [`IndexEntry`](struct.IndexEntry.html).
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexDependency {
+    name: String,
+    req: String,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default = "default_true")]
+    default_features: bool,
+    #[serde(default)]
+    target: Option<String>,
+    // Some older index entries contain an explicit `null` kind.
+    #[serde(default)]
+    kind: Option<DependencyKind>,
+    #[serde(default)]
+    registry: Option<String>,
+    #[serde(default)]
+    package: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl IndexDependency {
+    /// Name of the dependency. If the dependency was renamed this is the new name, and the name
+    /// of the crate is returned by [`package`](#method.package).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version requirement of the dependency.
+    pub fn req(&self) -> &str {
+        &self.req
+    }
+
+    /// Features enabled on the dependency.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Whether the dependency is optional.
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    /// Whether the default features of the dependency are enabled.
+    pub fn default_features(&self) -> bool {
+        self.default_features
+    }
+
+    /// Target the dependency is restricted to, if any.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Kind of the dependency.
+    pub fn kind(&self) -> DependencyKind {
+        self.kind.unwrap_or_default()
+    }
+
+    /// Index URL of the registry the dependency comes from, if it's not the same registry as the
+    /// crate depending on it.
+    pub fn registry(&self) -> Option<&str> {
+        self.registry.as_deref()
+    }
+
+    /// Name of the crate, if the dependency was renamed.
+    pub fn package(&self) -> Option<&str> {
+        self.package.as_deref()
+    }
+}
+
+/// Kind of an [`IndexDependency`](struct.IndexDependency.html).
+#[derive(serde::Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum DependencyKind {
+    /// A dependency used by the crate itself.
+    #[default]
+    Normal,
+    /// A dependency only used by tests, examples and benchmarks.
+    Dev,
+    /// A dependency only used by the build script.
+    Build,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DependencyKind, IndexEntry};
+
+    #[test]
+    fn test_parse_index_entry() -> anyhow::Result<()> {
+        let entry: IndexEntry = serde_json::from_str(
+            r#"{
+                "name": "foo",
+                "vers": "0.1.0",
+                "deps": [
+                    {
+                        "name": "bar",
+                        "req": "^1.0",
+                        "features": ["std"],
+                        "optional": true,
+                        "default_features": false,
+                        "target": "cfg(unix)",
+                        "kind": null
+                    },
+                    {
+                        "name": "baz",
+                        "req": "^0.2",
+                        "features": [],
+                        "optional": false,
+                        "default_features": true,
+                        "target": null,
+                        "kind": "dev",
+                        "package": "baz-renamed"
+                    }
+                ],
+                "cksum": "aaaa",
+                "features": {"default": ["std"], "std": []},
+                "features2": {"bar": ["dep:bar"]},
+                "yanked": false,
+                "links": "foo-sys",
+                "rust_version": "1.70"
+            }"#,
+        )?;
+
+        assert_eq!(entry.name(), "foo");
+        assert_eq!(entry.version(), "0.1.0");
+        assert_eq!(entry.checksum(), "aaaa");
+        assert!(!entry.is_yanked());
+        assert_eq!(entry.links(), Some("foo-sys"));
+        assert_eq!(entry.rust_version(), Some("1.70"));
+
+        let features = entry.features();
+        assert_eq!(
+            features.keys().copied().collect::<Vec<_>>(),
+            ["bar", "default", "std"]
+        );
+        assert_eq!(features["bar"], ["dep:bar"]);
+
+        let deps = entry.dependencies();
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].name(), "bar");
+        assert_eq!(deps[0].req(), "^1.0");
+        assert_eq!(deps[0].features(), ["std"]);
+        assert!(deps[0].is_optional());
+        assert!(!deps[0].default_features());
+        assert_eq!(deps[0].target(), Some("cfg(unix)"));
+        assert_eq!(deps[0].kind(), DependencyKind::Normal);
+        assert_eq!(deps[1].kind(), DependencyKind::Dev);
+        assert_eq!(deps[1].package(), Some("baz-renamed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_minimal_index_entry() -> anyhow::Result<()> {
+        let entry: IndexEntry = serde_json::from_str(
+            r#"{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aaaa","features":{},"yanked":true}"#,
+        )?;
+
+        assert!(entry.is_yanked());
+        assert!(entry.dependencies().is_empty());
+        assert!(entry.features().is_empty());
+        assert_eq!(entry.links(), None);
+        assert_eq!(entry.rust_version(), None);
+
+        Ok(())
+    }
+}
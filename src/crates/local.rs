@@ -1,16 +1,100 @@
 use super::CrateTrait;
+use crate::cmd::Command;
 use crate::Workspace;
-use log::info;
+use anyhow::Context as _;
+use log::{info, warn};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
 
 pub(super) struct Local {
     path: PathBuf,
+    git: bool,
+    ignores: Vec<String>,
+    subdir: Option<PathBuf>,
 }
 
 impl Local {
     pub(super) fn new(path: &Path) -> Self {
-        Local { path: path.into() }
+        Local {
+            path: path.into(),
+            git: false,
+            ignores: Vec::new(),
+            subdir: None,
+        }
+    }
+
+    /// Create a local crate whose directory is a git checkout, recording its `HEAD` commit.
+    pub(super) fn git(path: &Path) -> Self {
+        Local {
+            path: path.into(),
+            git: true,
+            ignores: Vec::new(),
+            subdir: None,
+        }
+    }
+
+    /// Skip the files and directories matching the patterns when copying the source code. See
+    /// [`Crate::local_with_ignores`](../struct.Crate.html#method.local_with_ignores) for the
+    /// syntax of the patterns.
+    pub(super) fn with_ignores(mut self, ignores: &[&str]) -> Self {
+        self.ignores = ignores.iter().map(|ignore| ignore.to_string()).collect();
+        self
+    }
+
+    /// Build the crate located in a subdirectory of the directory, relative to its root.
+    pub(super) fn with_subdir(mut self, subdir: &Path) -> Self {
+        self.subdir = Some(subdir.into());
+        self
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(super) fn ignores(&self) -> &[String] {
+        &self.ignores
+    }
+
+    pub(super) fn subdir(&self) -> Option<&Path> {
+        self.subdir.as_deref()
+    }
+
+    pub(super) fn is_git(&self) -> bool {
+        self.git
+    }
+
+    /// Return the commit checked out in the directory, if it's inside a git checkout. Uncommitted
+    /// changes are not reflected in the commit.
+    pub(super) fn git_commit(&self, workspace: &Workspace) -> Option<String> {
+        let res = Command::new(workspace, "git")
+            .args(&["rev-parse", "HEAD"])
+            .cd(&self.path)
+            .log_output(false)
+            .run_capture();
+        match res {
+            Ok(out) => match out.stdout_lines().first() {
+                Some(sha) if !sha.is_empty() => Some(sha.to_string()),
+                _ => {
+                    warn!("bad output from `git rev-parse HEAD`");
+                    None
+                }
+            },
+            // Plain local crates are not required to be inside a git checkout.
+            Err(_) if !self.git => None,
+            Err(e) => {
+                warn!("unable to capture sha for {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub(super) fn manifest(&self) -> anyhow::Result<String> {
+        let mut path = self.path.clone();
+        if let Some(subdir) = &self.subdir {
+            path.push(crate::build::validate_source_path(subdir)?);
+        }
+        path.push("Cargo.toml");
+        std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))
     }
 }
 
@@ -31,21 +115,36 @@ impl CrateTrait for Local {
             self.path.display(),
             dest.display()
         );
-        copy_dir(&self.path, dest)?;
+        copy_dir(&self.path, dest, &self.ignores)?;
         Ok(())
     }
 }
 
 impl std::fmt::Display for Local {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "local crate {}", self.path.display())
+        if self.git {
+            write!(f, "local git checkout {}", self.path.display())
+        } else {
+            write!(f, "local crate {}", self.path.display())
+        }
     }
 }
 
-fn copy_dir(src: &Path, dest: &Path) -> anyhow::Result<()> {
+/// Return whether the entry matches one of the patterns: a pattern starting with `/` only
+/// matches entries at the top level of the source, while the other patterns match at any depth.
+fn is_ignored(ignores: &[String], entry: &DirEntry) -> bool {
+    entry.depth() > 0
+        && ignores.iter().any(|ignore| match ignore.strip_prefix('/') {
+            Some(name) => entry.depth() == 1 && entry.file_name() == name,
+            None => entry.file_name() == ignore.as_str(),
+        })
+}
+
+fn copy_dir(src: &Path, dest: &Path, ignores: &[String]) -> anyhow::Result<()> {
     let src = crate::utils::normalize_path(src);
     let dest = crate::utils::normalize_path(dest);
 
+    let canonical_src = std::fs::canonicalize(&src)?;
     let src_components = src.components().count();
     let mut entries = WalkDir::new(&src).follow_links(true).into_iter();
     while let Some(entry) = entries.next() {
@@ -57,7 +156,16 @@ fn copy_dir(src: &Path, dest: &Path) -> anyhow::Result<()> {
         }
         let path = components.as_path();
 
-        if entry.file_type().is_dir() {
+        if is_ignored(ignores, &entry) {
+            info!("ignoring {}", path.display());
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+        } else if copy_internal_symlink(&canonical_src, &entry, &dest.join(path))? {
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+        } else if entry.file_type().is_dir() {
             // don't copy /target directory
             if entry.file_name() == "target" && entry.depth() == 1 {
                 info!("ignoring top-level target directory {}", path.display());
@@ -66,12 +174,35 @@ fn copy_dir(src: &Path, dest: &Path) -> anyhow::Result<()> {
                 std::fs::create_dir_all(dest.join(path))?;
             }
         } else {
+            // This also copies the permissions of the file, preserving the executable bit.
             std::fs::copy(src.join(path), dest.join(path))?;
         }
     }
     Ok(())
 }
 
+/// Recreate the entry in `dest` if it's a relative symlink resolving inside `root`, returning
+/// whether it was recreated. The other symlinks are dereferenced when copying them, to avoid
+/// pointing outside of the copy.
+#[cfg(unix)]
+fn copy_internal_symlink(root: &Path, entry: &DirEntry, dest: &Path) -> anyhow::Result<bool> {
+    if !entry.path_is_symlink() {
+        return Ok(false);
+    }
+    let target = std::fs::read_link(entry.path())?;
+    if target.is_absolute() || !std::fs::canonicalize(entry.path())?.starts_with(root) {
+        return Ok(false);
+    }
+    std::os::unix::fs::symlink(&target, dest)?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+fn copy_internal_symlink(_root: &Path, _entry: &DirEntry, _dest: &Path) -> anyhow::Result<bool> {
+    // Creating symlinks requires special privileges on Windows, so they're always dereferenced.
+    Ok(false)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -84,7 +215,7 @@ mod tests {
         std::fs::write(tmp_src.path().join("foo"), b"Hello world")?;
         std::fs::write(tmp_src.path().join("dir").join("bar"), b"Rustwide")?;
 
-        super::copy_dir(tmp_src.path(), tmp_dest.path())?;
+        super::copy_dir(tmp_src.path(), tmp_dest.path(), &[])?;
 
         assert_eq!(std::fs::read(tmp_dest.path().join("foo"))?, b"Hello world");
         assert_eq!(
@@ -105,13 +236,93 @@ mod tests {
         )?;
         println!("made subdirs and files");
 
-        super::copy_dir(src.path(), dest.path())?;
+        super::copy_dir(src.path(), dest.path(), &[])?;
         println!("copied");
 
         assert!(!dest.path().join("target").exists());
         Ok(())
     }
 
+    #[test]
+    fn test_copy_dir_ignores() -> anyhow::Result<()> {
+        let (src, dest) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        for dir in &["node_modules", "vendor", "src/node_modules", "src/vendor"] {
+            std::fs::create_dir_all(src.path().join(dir))?;
+            std::fs::write(src.path().join(dir).join("file"), b"ignore me?")?;
+        }
+        std::fs::write(src.path().join("src").join(".env"), b"SECRET=1")?;
+        std::fs::write(src.path().join("src").join("lib.rs"), b"")?;
+
+        let ignores = ["node_modules", "/vendor", ".env"].map(String::from);
+        super::copy_dir(src.path(), dest.path(), &ignores)?;
+
+        assert!(!dest.path().join("node_modules").exists());
+        assert!(!dest.path().join("src").join("node_modules").exists());
+        assert!(!dest.path().join("vendor").exists());
+        assert!(dest
+            .path()
+            .join("src")
+            .join("vendor")
+            .join("file")
+            .is_file());
+        assert!(!dest.path().join("src").join(".env").exists());
+        assert!(dest.path().join("src").join("lib.rs").is_file());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_copy_preserves_permissions_and_symlinks() -> anyhow::Result<()> {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        use std::path::Path;
+
+        let (src, dest) = (tempfile::tempdir()?, tempfile::tempdir()?);
+        let outside = tempfile::tempdir()?;
+        std::fs::create_dir(src.path().join("dir"))?;
+        std::fs::write(src.path().join("dir").join("file"), b"Rustwide")?;
+        std::fs::write(src.path().join("script.sh"), b"#!/bin/sh\n")?;
+        std::fs::set_permissions(
+            src.path().join("script.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+        std::fs::write(outside.path().join("file"), b"outside")?;
+
+        symlink("dir/file", src.path().join("file-link"))?;
+        symlink("dir", src.path().join("dir-link"))?;
+        symlink("../file-link", src.path().join("dir").join("parent-link"))?;
+        symlink(outside.path().join("file"), src.path().join("outside-link"))?;
+
+        super::copy_dir(src.path(), dest.path(), &[])?;
+        let dest = dest.path();
+
+        let mode = std::fs::metadata(dest.join("script.sh"))?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        // Symlinks inside the tree are recreated as-is.
+        assert_eq!(
+            std::fs::read_link(dest.join("file-link"))?,
+            Path::new("dir/file")
+        );
+        assert_eq!(std::fs::read_link(dest.join("dir-link"))?, Path::new("dir"));
+        assert_eq!(
+            std::fs::read_link(dest.join("dir").join("parent-link"))?,
+            Path::new("../file-link")
+        );
+        assert_eq!(
+            std::fs::read(dest.join("dir-link").join("file"))?,
+            b"Rustwide"
+        );
+
+        // Symlinks pointing outside of the tree are dereferenced.
+        let outside_link = dest.join("outside-link");
+        assert!(!outside_link.symlink_metadata()?.file_type().is_symlink());
+        assert_eq!(std::fs::read(outside_link)?, b"outside");
+
+        Ok(())
+    }
+
     #[test]
     fn test_copy_symlinks() -> anyhow::Result<()> {
         use std::{fs, os, path::Path};
@@ -119,7 +330,7 @@ mod tests {
         let tmp_src = tempfile::tempdir()?;
         let tmp_dest = tempfile::tempdir()?;
         let assert_copy_err_has_filename = || {
-            match super::copy_dir(tmp_src.path(), tmp_dest.path()) {
+            match super::copy_dir(tmp_src.path(), tmp_dest.path(), &[]) {
                 Ok(_) => panic!("copy with bad symbolic link did not fail"),
                 Err(err) => assert!(err.downcast::<walkdir::Error>().unwrap().path().is_some()),
             };
@@ -144,7 +355,7 @@ mod tests {
 
         crate::utils::remove_file(&bad_link)?;
         // make sure it works without that link
-        super::copy_dir(tmp_src.path(), tmp_dest.path())?;
+        super::copy_dir(tmp_src.path(), tmp_dest.path(), &[])?;
 
         // test link to self
         #[cfg(unix)]
@@ -1,13 +1,60 @@
+mod bytes;
 mod git;
+mod index;
 mod local;
 mod registry;
 
+use crate::metrics::Operation;
 use crate::Workspace;
+use anyhow::Context as _;
+use base64::{engine::general_purpose::STANDARD as b64, Engine};
 use log::info;
+use semver::Version;
+use std::borrow::Cow;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+pub use index::{DependencyKind, IndexDependency, IndexEntry};
 pub use registry::AlternativeRegistry;
 
+/// Error caused by methods in the `crates` module.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CrateError {
+    /// The checksum of a downloaded crate doesn't match the one recorded in the registry index,
+    /// as checked when
+    /// [`WorkspaceBuilder::verify_crate_checksums`](struct.WorkspaceBuilder.html#method.verify_crate_checksums)
+    /// is enabled. The downloaded crate is discarded.
+    #[error("checksum mismatch for crate {krate}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        /// Name and version of the crate.
+        krate: String,
+        /// Checksum recorded in the registry index.
+        expected: String,
+        /// Checksum of the downloaded crate.
+        actual: String,
+    },
+    /// The files extracted from the archive of a crate exceed the size configured with
+    /// [`WorkspaceBuilder::max_extracted_crate_size`](struct.WorkspaceBuilder.html#method.max_extracted_crate_size).
+    #[error("the extracted crate exceeds the maximum size of {limit} bytes")]
+    ExtractedSizeLimitExceeded {
+        /// Maximum size of the extracted crate, in bytes.
+        limit: u64,
+    },
+    /// The git revision passed to [`Crate::git_rev`](struct.Crate.html#method.git_rev) is not
+    /// valid.
+    #[error("invalid git revision: {0}")]
+    InvalidRevision(String),
+    /// The archive of a registry crate decompresses to more than the ratio configured with
+    /// [`WorkspaceBuilder::max_crate_compression_ratio`](struct.WorkspaceBuilder.html#method.max_crate_compression_ratio).
+    #[error("the crate exceeds the maximum compression ratio of {ratio}")]
+    CompressionRatioExceeded {
+        /// Maximum ratio between the decompressed and the compressed size of the crate.
+        ratio: u64,
+    },
+}
+
 trait CrateTrait: std::fmt::Display {
     fn fetch(&self, workspace: &Workspace) -> anyhow::Result<()>;
     fn purge_from_cache(&self, workspace: &Workspace) -> anyhow::Result<()>;
@@ -18,15 +65,93 @@ enum CrateType {
     Registry(registry::RegistryCrate),
     Git(git::GitRepo),
     Local(local::Local),
+    InMemory(bytes::InMemoryCrate),
+}
+
+/// Where the source code of a [`Crate`](struct.Crate.html) comes from, returned by
+/// [`Crate::source_kind`](struct.Crate.html#method.source_kind).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CrateSourceKind {
+    /// The [crates.io registry](https://crates.io).
+    CratesIo,
+    /// An alternative registry.
+    AlternativeRegistry,
+    /// A git repository.
+    Git,
+    /// A directory in the local filesystem.
+    Local,
+    /// A tarball provided in memory.
+    InMemory,
+}
+
+/// Rust edition declared by a crate, returned by
+/// [`Crate::edition`](struct.Crate.html#method.edition).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Edition {
+    /// The 2015 edition, used by crates not declaring an edition.
+    Edition2015,
+    /// The 2018 edition.
+    Edition2018,
+    /// The 2021 edition.
+    Edition2021,
+    /// The 2024 edition.
+    Edition2024,
+    /// An edition not known by this version of rustwide, with the value declared by the crate.
+    Unknown(String),
+}
+
+impl Edition {
+    fn parse(edition: &str) -> Self {
+        match edition {
+            "2015" => Edition::Edition2015,
+            "2018" => Edition::Edition2018,
+            "2021" => Edition::Edition2021,
+            "2024" => Edition::Edition2024,
+            other => Edition::Unknown(other.into()),
+        }
+    }
+
+    /// First stable Rust version supporting this edition, or `None` for unknown editions.
+    pub fn min_rust_version(&self) -> Option<Version> {
+        match self {
+            Edition::Edition2015 => Some(Version::new(1, 0, 0)),
+            Edition::Edition2018 => Some(Version::new(1, 31, 0)),
+            Edition::Edition2021 => Some(Version::new(1, 56, 0)),
+            Edition::Edition2024 => Some(Version::new(1, 85, 0)),
+            Edition::Unknown(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Edition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Edition::Edition2015 => write!(f, "2015"),
+            Edition::Edition2018 => write!(f, "2018"),
+            Edition::Edition2021 => write!(f, "2021"),
+            Edition::Edition2024 => write!(f, "2024"),
+            Edition::Unknown(edition) => write!(f, "{}", edition),
+        }
+    }
 }
 
 /// A Rust crate that can be used with rustwide.
-pub struct Crate(CrateType);
+///
+/// Crates can be serialized and deserialized with serde. The SSH key used to authenticate with an
+/// alternative registry is not serialized.
+pub struct Crate(CrateType, Mutex<Option<Duration>>);
 
 impl Crate {
+    fn new(krate: CrateType) -> Self {
+        // The duration of the last fetch is reported in `BuildReport::fetch`.
+        Crate(krate, Mutex::new(None))
+    }
+
     /// Load a crate from specified registry.
     pub fn registry(registry: AlternativeRegistry, name: &str, version: &str) -> Self {
-        Crate(CrateType::Registry(registry::RegistryCrate::new(
+        Crate::new(CrateType::Registry(registry::RegistryCrate::new(
             registry::Registry::Alternative(registry),
             name,
             version,
@@ -35,28 +160,334 @@ impl Crate {
 
     /// Load a crate from the [crates.io registry](https://crates.io).
     pub fn crates_io(name: &str, version: &str) -> Self {
-        Crate(CrateType::Registry(registry::RegistryCrate::new(
+        Crate::new(CrateType::Registry(registry::RegistryCrate::new(
             registry::Registry::CratesIo,
             name,
             version,
         )))
     }
 
+    /// Load the latest version of a crate from the [crates.io registry](https://crates.io).
+    ///
+    /// Unlike [`crates_io`](#method.crates_io), the version is not known upfront: it's resolved
+    /// to the highest version that isn't yanked (preferring stable versions over pre-releases)
+    /// by querying the crates.io index when the crate is fetched, which requires network access.
+    /// The resolved version can be retrieved afterwards with
+    /// [`resolved_version`](#method.resolved_version), and it doesn't change for the lifetime of
+    /// this `Crate`.
+    pub fn crates_io_latest(name: &str) -> Self {
+        Crate::new(CrateType::Registry(registry::RegistryCrate::latest(
+            registry::Registry::CratesIo,
+            name,
+        )))
+    }
+
     /// Load a crate from a git repository. The full URL needed to clone the repo has to be
     /// provided.
     pub fn git(url: &str) -> Self {
-        Crate(CrateType::Git(git::GitRepo::new(url)))
+        Crate::new(CrateType::Git(git::GitRepo::new(url)))
+    }
+
+    /// Load a crate from a git repository, also fetching its submodules (recursively) when the
+    /// source code is copied for a build. The full URL needed to clone the repo has to be
+    /// provided.
+    ///
+    /// The submodules are fetched without prompting for credentials: if one of them is private,
+    /// the build fails with
+    /// [`PrepareError::PrivateGitRepository`](enum.PrepareError.html#variant.PrivateGitRepository).
+    pub fn git_with_submodules(url: &str) -> Self {
+        Crate::new(CrateType::Git(git::GitRepo::new(url).with_submodules(true)))
+    }
+
+    /// Load a crate located in a subdirectory of a git repository, for example a crate in a
+    /// monorepo. The full URL needed to clone the repo has to be provided, and `subdir` is the
+    /// path of the directory containing the crate's `Cargo.toml`, relative to the root of the
+    /// repository.
+    ///
+    /// The whole repository is copied in the build directory, so the crate can still refer to
+    /// files outside of its subdirectory, while the build is prepared and the commands are run
+    /// in the subdirectory. The build fails with
+    /// [`PrepareError::InvalidFilePath`](enum.PrepareError.html#variant.InvalidFilePath) if
+    /// `subdir` is absolute or contains `..` components.
+    pub fn git_subdir(url: &str, subdir: &Path) -> Self {
+        Crate::new(CrateType::Git(git::GitRepo::new(url).with_subdir(subdir)))
+    }
+
+    /// Load a crate from a git repository, only fetching the latest `depth` commits of its
+    /// history (at least one). The full URL needed to clone the repo has to be provided.
+    ///
+    /// Shallow clones are faster to fetch and use less disk space for repositories with a long
+    /// history. The cached clone of the repository is shared with the other crates using the same
+    /// URL: if it already contains the full history it's kept as-is, while a shallow clone is
+    /// converted to a full one when a crate created with [`git`](#method.git) is fetched.
+    pub fn git_shallow(url: &str, depth: u32) -> Self {
+        Crate::new(CrateType::Git(git::GitRepo::new(url).with_depth(depth)))
+    }
+
+    /// Load a crate from a git repository, checking out the provided revision (a commit hash, a
+    /// tag or a branch) instead of the default branch. The full URL needed to clone the repo has
+    /// to be provided.
+    ///
+    /// If the revision is not reachable from the branches and tags of the repository, it's
+    /// fetched specifically when the crate is fetched, which requires the server to allow it.
+    /// [`git_commit`](#method.git_commit) returns the commit the revision points to.
+    ///
+    /// Revisions starting with `-` are rejected with
+    /// [`CrateError::InvalidRevision`](enum.CrateError.html#variant.InvalidRevision), as git
+    /// would parse them as options.
+    pub fn git_rev(url: &str, rev: &str) -> Result<Self, CrateError> {
+        if rev.starts_with('-') {
+            return Err(CrateError::InvalidRevision(rev.into()));
+        }
+        Ok(Crate::new(CrateType::Git(
+            git::GitRepo::new(url).with_rev(rev),
+        )))
     }
 
-    /// Load a crate from a directory in the local filesystem.
+    /// Load a crate from a directory in the local filesystem. If the directory is inside a git
+    /// checkout, [`git_commit`](#method.git_commit) returns the commit `HEAD` points to.
+    ///
+    /// The permissions of the files are preserved when copying them into the builds. On Unix,
+    /// relative symlinks pointing inside the directory are recreated as symlinks, while the other
+    /// symlinks are replaced by a copy of their target.
     pub fn local(path: &Path) -> Self {
-        Crate(CrateType::Local(local::Local::new(path)))
+        Crate::new(CrateType::Local(local::Local::new(path)))
+    }
+
+    /// Load a crate from a directory in the local filesystem like [`local`](#method.local),
+    /// without copying the files and directories matching the provided patterns into the builds
+    /// (for example `&["node_modules", "/.git"]`). Skipping large directories unrelated to the
+    /// crate speeds up preparing the builds.
+    ///
+    /// Each pattern is compared with the name of the files and directories, without wildcards.
+    /// A pattern starting with `/` only matches at the top level of the directory, while the
+    /// other patterns match at any depth. The top-level `target` directory is always skipped.
+    pub fn local_with_ignores(path: &Path, ignores: &[&str]) -> Self {
+        Crate::new(CrateType::Local(
+            local::Local::new(path).with_ignores(ignores),
+        ))
+    }
+
+    /// Load a crate located in a subdirectory of a directory in the local filesystem, for example
+    /// a crate in a monorepo. `subdir` is the path of the directory containing the crate's
+    /// `Cargo.toml`, relative to `path`.
+    ///
+    /// Like [`git_subdir`](#method.git_subdir), the whole directory is copied in the build
+    /// directory, while the build is prepared and the commands are run in the subdirectory. The
+    /// build fails with
+    /// [`PrepareError::InvalidFilePath`](enum.PrepareError.html#variant.InvalidFilePath) if
+    /// `subdir` is absolute or contains `..` components.
+    pub fn local_subdir(path: &Path, subdir: &Path) -> Self {
+        Crate::new(CrateType::Local(
+            local::Local::new(path).with_subdir(subdir),
+        ))
+    }
+
+    /// Load a crate from a git checkout in the local filesystem. Like [`local`](#method.local),
+    /// the working tree is built as-is, including any uncommitted change, while
+    /// [`git_commit`](#method.git_commit) returns the commit `HEAD` points to, for reporting.
+    pub fn local_git(path: &Path) -> Self {
+        Crate::new(CrateType::Local(local::Local::git(path)))
+    }
+
+    /// Load a crate from a gzipped tarball already in memory, with the same format as the
+    /// `.crate` files published to registries. No network access is needed to fetch the crate.
+    pub fn from_bytes(name: &str, version: &str, bytes: impl Into<Vec<u8>>) -> Self {
+        Crate::new(CrateType::InMemory(bytes::InMemoryCrate::new(
+            name,
+            version,
+            bytes.into(),
+        )))
+    }
+
+    /// Return the name of the crate, if it's known without fetching it. Only crates from
+    /// registries and in-memory crates have a known name.
+    pub fn name(&self) -> Option<&str> {
+        match &self.0 {
+            CrateType::Registry(krate) => Some(krate.name()),
+            CrateType::InMemory(krate) => Some(krate.name()),
+            CrateType::Git(_) | CrateType::Local(_) => None,
+        }
+    }
+
+    /// Return the version of the crate, if it's known without fetching it. Only crates from
+    /// registries (except the ones created with [`crates_io_latest`](#method.crates_io_latest))
+    /// and in-memory crates have a known version.
+    pub fn version(&self) -> Option<&str> {
+        match &self.0 {
+            CrateType::Registry(krate) if krate.is_latest() => None,
+            CrateType::Registry(krate) => krate.version(),
+            CrateType::InMemory(krate) => Some(krate.version()),
+            CrateType::Git(_) | CrateType::Local(_) => None,
+        }
+    }
+
+    /// Return the version of the crate, including the version resolved when fetching crates
+    /// created with [`crates_io_latest`](#method.crates_io_latest). `None` is returned for those
+    /// crates before they're fetched, and for crates without a known version.
+    pub fn resolved_version(&self) -> Option<&str> {
+        match &self.0 {
+            CrateType::Registry(krate) => krate.version(),
+            CrateType::InMemory(krate) => Some(krate.version()),
+            CrateType::Git(_) | CrateType::Local(_) => None,
+        }
+    }
+
+    /// Return where the source code of this crate comes from.
+    pub fn source_kind(&self) -> CrateSourceKind {
+        match &self.0 {
+            CrateType::Registry(krate) => match krate.registry() {
+                registry::Registry::CratesIo => CrateSourceKind::CratesIo,
+                registry::Registry::Alternative(_) => CrateSourceKind::AlternativeRegistry,
+            },
+            CrateType::Git(_) => CrateSourceKind::Git,
+            CrateType::Local(_) => CrateSourceKind::Local,
+            CrateType::InMemory(_) => CrateSourceKind::InMemory,
+        }
+    }
+
+    /// List all the versions of a crate published to [crates.io](https://crates.io), alongside
+    /// whether each version is yanked. Use
+    /// [`AlternativeRegistry::available_versions`](struct.AlternativeRegistry.html#method.available_versions)
+    /// for other registries.
+    pub fn available_versions(
+        name: &str,
+        workspace: &Workspace,
+    ) -> anyhow::Result<Vec<(String, bool)>> {
+        registry::Registry::CratesIo.available_versions(workspace, name)
+    }
+
+    /// Look up this version of the crate in the registry index, returning its dependencies,
+    /// features, native library linkage and minimum supported Rust version among the other
+    /// metadata. `None` is returned for crates that don't come from a registry.
+    pub fn index_entry(&self, workspace: &Workspace) -> anyhow::Result<Option<IndexEntry>> {
+        match &self.0 {
+            CrateType::Registry(krate) => krate.index_entry(workspace).map(Some),
+            CrateType::Git(_) | CrateType::Local(_) | CrateType::InMemory(_) => Ok(None),
+        }
+    }
+
+    /// Return the minimum supported Rust version declared by the crate, or `None` if the crate
+    /// doesn't declare one.
+    ///
+    /// The version is looked up in the registry index for registry crates, and read from the
+    /// `rust-version` key of `Cargo.toml` for the other crates. Git crates need to be
+    /// [fetched](#method.fetch) first, and `None` is returned if they weren't. Orchestrators can
+    /// compare the version with the toolchain they use to skip crates that can't be built with it.
+    pub fn min_rust_version(&self, workspace: &Workspace) -> anyhow::Result<Option<Version>> {
+        let rust_version = match &self.0 {
+            CrateType::Registry(krate) => krate.index_entry(workspace)?.rust_version,
+            CrateType::Git(repo) => match repo.manifest(workspace)? {
+                Some(manifest) => manifest_rust_version(&manifest)?,
+                None => None,
+            },
+            CrateType::Local(local) => manifest_rust_version(&local.manifest()?)?,
+            CrateType::InMemory(krate) => match krate.manifest()? {
+                Some(manifest) => manifest_rust_version(&manifest)?,
+                None => None,
+            },
+        };
+        rust_version.map(|v| parse_rust_version(&v)).transpose()
+    }
+
+    /// Return the Rust edition declared in the crate's `Cargo.toml`, defaulting to the 2015
+    /// edition when the crate doesn't declare one.
+    ///
+    /// Registry and git crates need to be [fetched](#method.fetch) first, and `None` is returned
+    /// if they weren't. `None` is also returned when the edition is inherited from a parent
+    /// workspace. Orchestrators can compare
+    /// [`Edition::min_rust_version`](enum.Edition.html#method.min_rust_version) with the toolchain
+    /// they use to skip crates that can't be built with it.
+    pub fn edition(&self, workspace: &Workspace) -> anyhow::Result<Option<Edition>> {
+        match self.manifest(workspace)? {
+            Some(manifest) => manifest_edition(&manifest),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the license declared in the crate's `Cargo.toml`. The SPDX expression of the
+    /// `license` field is returned as-is, and if the crate only has a `license-file` field the
+    /// path of the file (relative to the crate's root) is returned instead.
+    ///
+    /// Registry and git crates need to be [fetched](#method.fetch) first, and `None` is returned
+    /// if they weren't. `None` is also returned when the crate doesn't declare a license, or when
+    /// it's inherited from a parent workspace.
+    pub fn license(&self, workspace: &Workspace) -> anyhow::Result<Option<String>> {
+        match self.manifest(workspace)? {
+            Some(manifest) => manifest_license(&manifest),
+            None => Ok(None),
+        }
+    }
+
+    fn manifest(&self, workspace: &Workspace) -> anyhow::Result<Option<String>> {
+        match &self.0 {
+            CrateType::Registry(krate) => krate.manifest(workspace),
+            CrateType::Git(repo) => repo.manifest(workspace),
+            CrateType::Local(local) => local.manifest().map(Some),
+            CrateType::InMemory(krate) => krate.manifest(),
+        }
+    }
+
+    /// Check whether this version of the crate is yanked, by looking it up in the registry index.
+    /// Crates that don't come from a registry can't be yanked, and `None` is returned for them.
+    ///
+    /// This allows skipping yanked crates before building them, instead of having the build fail
+    /// with [`PrepareError::YankedDependencies`](enum.PrepareError.html#variant.YankedDependencies)
+    /// only when the crate itself depends on yanked crates.
+    pub fn is_yanked(&self, workspace: &Workspace) -> anyhow::Result<Option<bool>> {
+        match &self.0 {
+            CrateType::Registry(krate) => krate.is_yanked(workspace).map(Some),
+            CrateType::Git(_) | CrateType::Local(_) | CrateType::InMemory(_) => Ok(None),
+        }
     }
 
     /// Fetch the crate's source code and cache it in the workspace. This method will reach out to
     /// the network for some crate types.
+    ///
+    /// If a [`WorkspaceBuilder::crate_filter`](struct.WorkspaceBuilder.html#method.crate_filter)
+    /// is configured and rejects the crate, its error is returned without fetching anything.
     pub fn fetch(&self, workspace: &Workspace) -> anyhow::Result<()> {
-        self.as_trait().fetch(workspace)
+        workspace.check_crate_filter(self)?;
+        self.timed_fetch(|| {
+            crate::metrics::timed(workspace.metrics(), Operation::CrateFetch, || {
+                self.as_trait().fetch(workspace)
+            })
+        })
+    }
+
+    /// Fetch the crate like [`fetch`](#method.fetch), calling `progress` while registry crates are
+    /// downloaded with the number of bytes downloaded so far and the total size of the download,
+    /// if the server reported it. Returning an error from `progress` aborts the download, for
+    /// example to enforce a maximum crate size.
+    ///
+    /// `progress` is not called for the other crate types, or if the crate is already cached.
+    pub fn fetch_with_progress(
+        &self,
+        workspace: &Workspace,
+        progress: &mut dyn FnMut(u64, Option<u64>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        workspace.check_crate_filter(self)?;
+        self.timed_fetch(|| {
+            crate::metrics::timed(workspace.metrics(), Operation::CrateFetch, || {
+                match &self.0 {
+                    CrateType::Registry(krate) => krate.fetch_with_progress(workspace, progress),
+                    _ => self.as_trait().fetch(workspace),
+                }
+            })
+        })
+    }
+
+    /// Run the fetch, recording how long it took if it succeeded.
+    fn timed_fetch(&self, f: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let start = Instant::now();
+        f()?;
+        *self.1.lock().unwrap() = Some(start.elapsed());
+        Ok(())
+    }
+
+    /// Return how long the last successful fetch of this crate took, if it was fetched.
+    pub(crate) fn last_fetch_duration(&self) -> Option<Duration> {
+        *self.1.lock().unwrap()
     }
 
     /// Remove the cached copy of this crate. The method will do nothing if the crate isn't cached.
@@ -65,12 +496,33 @@ impl Crate {
     }
 
     /// Get this crate's git commit. This method is best-effort, and currently works just for git
-    /// crates. If the commit can't be retrieved `None` will be returned.
+    /// crates and local crates inside a git checkout, returning the commit `HEAD` points to for
+    /// the latter. If the commit can't be retrieved `None` will be returned.
     pub fn git_commit(&self, workspace: &Workspace) -> Option<String> {
-        if let CrateType::Git(repo) = &self.0 {
-            repo.git_commit(workspace)
-        } else {
-            None
+        match &self.0 {
+            CrateType::Git(repo) => repo.git_commit(workspace),
+            CrateType::Local(local) => local.git_commit(workspace),
+            CrateType::Registry(_) | CrateType::InMemory(_) => None,
+        }
+    }
+
+    /// Return the subdirectory of the source code containing the crate, if it's not at the root.
+    pub(crate) fn subdir(&self) -> Option<&Path> {
+        match &self.0 {
+            CrateType::Git(repo) => repo.subdir(),
+            CrateType::Local(local) => local.subdir(),
+            CrateType::Registry(_) | CrateType::InMemory(_) => None,
+        }
+    }
+
+    /// Return the alternative registry this crate comes from, if any.
+    pub(crate) fn alternative_registry(&self) -> Option<&AlternativeRegistry> {
+        match &self.0 {
+            CrateType::Registry(krate) => match krate.registry() {
+                registry::Registry::Alternative(alt) => Some(alt),
+                registry::Registry::CratesIo => None,
+            },
+            _ => None,
         }
     }
 
@@ -90,6 +542,7 @@ impl Crate {
             CrateType::Registry(krate) => krate,
             CrateType::Git(repo) => repo,
             CrateType::Local(local) => local,
+            CrateType::InMemory(krate) => krate,
         }
     }
 }
@@ -99,3 +552,515 @@ impl std::fmt::Display for Crate {
         write!(f, "{}", self.as_trait())
     }
 }
+
+/// Return the `package.rust-version` key of a manifest. Versions inherited from the workspace
+/// can't be resolved, and are ignored.
+fn manifest_rust_version(manifest: &str) -> anyhow::Result<Option<String>> {
+    let manifest: toml::Table = toml::from_str(manifest).context("invalid Cargo.toml")?;
+    Ok(manifest
+        .get("package")
+        .and_then(|package| package.get("rust-version"))
+        .and_then(|version| version.as_str())
+        .map(String::from))
+}
+
+pub(crate) fn manifest_edition(manifest: &str) -> anyhow::Result<Option<Edition>> {
+    let manifest: toml::Table = toml::from_str(manifest).context("invalid Cargo.toml")?;
+    let package = match manifest.get("package") {
+        Some(package) => package,
+        None => return Ok(None),
+    };
+    Ok(match package.get("edition") {
+        Some(edition) => edition.as_str().map(Edition::parse),
+        None => Some(Edition::Edition2015),
+    })
+}
+
+fn manifest_license(manifest: &str) -> anyhow::Result<Option<String>> {
+    let manifest: toml::Table = toml::from_str(manifest).context("invalid Cargo.toml")?;
+    let package = match manifest.get("package") {
+        Some(package) => package,
+        None => return Ok(None),
+    };
+    Ok(["license", "license-file"]
+        .iter()
+        .find_map(|key| package.get(key).and_then(|value| value.as_str()))
+        .map(String::from))
+}
+
+/// Parse a `rust-version`, which is allowed to omit the minor and patch components.
+fn parse_rust_version(version: &str) -> anyhow::Result<Version> {
+    let mut parts = version.split('.');
+    let mut next = || -> anyhow::Result<u64> {
+        parts.next().map_or(Ok(0), |part| {
+            part.parse()
+                .with_context(|| format!("invalid rust-version: {}", version))
+        })
+    };
+    let parsed = Version::new(next()?, next()?, next()?);
+    if parts.next().is_some() {
+        anyhow::bail!("invalid rust-version: {}", version);
+    }
+    Ok(parsed)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+enum CrateRepr<'a> {
+    CratesIo {
+        name: Cow<'a, str>,
+        version: Cow<'a, str>,
+    },
+    CratesIoLatest {
+        name: Cow<'a, str>,
+    },
+    Registry {
+        #[serde(flatten)]
+        registry: Cow<'a, AlternativeRegistry>,
+        name: Cow<'a, str>,
+        version: Cow<'a, str>,
+    },
+    Git {
+        url: Cow<'a, str>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        rev: Option<Cow<'a, str>>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        depth: Option<u32>,
+        #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+        submodules: bool,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        subdir: Option<Cow<'a, Path>>,
+    },
+    Local {
+        path: Cow<'a, Path>,
+        #[serde(skip_serializing_if = "<[_]>::is_empty", default)]
+        ignores: Cow<'a, [String]>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        subdir: Option<Cow<'a, Path>>,
+    },
+    LocalGit {
+        path: Cow<'a, Path>,
+    },
+    InMemory {
+        name: Cow<'a, str>,
+        version: Cow<'a, str>,
+        #[serde(with = "base64_bytes")]
+        bytes: Cow<'a, [u8]>,
+    },
+}
+
+mod base64_bytes {
+    use super::{b64, Engine};
+    use std::borrow::Cow;
+
+    pub(super) fn serialize<S: serde::Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&b64.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'static, [u8]>, D::Error> {
+        let encoded: String = serde::Deserialize::deserialize(deserializer)?;
+        b64.decode(encoded)
+            .map(Cow::Owned)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl serde::Serialize for Crate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match &self.0 {
+            CrateType::Registry(krate) => match krate.registry() {
+                registry::Registry::CratesIo if krate.is_latest() => CrateRepr::CratesIoLatest {
+                    name: krate.name().into(),
+                },
+                registry::Registry::CratesIo => CrateRepr::CratesIo {
+                    name: krate.name().into(),
+                    version: krate.version().unwrap_or_default().into(),
+                },
+                registry::Registry::Alternative(alt) => CrateRepr::Registry {
+                    registry: Cow::Borrowed(alt),
+                    name: krate.name().into(),
+                    version: krate.version().unwrap_or_default().into(),
+                },
+            },
+            CrateType::Git(repo) => CrateRepr::Git {
+                url: repo.url().into(),
+                rev: repo.rev().map(Cow::Borrowed),
+                depth: repo.depth(),
+                submodules: repo.submodules(),
+                subdir: repo.subdir().map(Cow::Borrowed),
+            },
+            CrateType::Local(local) if local.is_git() => CrateRepr::LocalGit {
+                path: local.path().into(),
+            },
+            CrateType::Local(local) => CrateRepr::Local {
+                path: local.path().into(),
+                ignores: local.ignores().into(),
+                subdir: local.subdir().map(Cow::Borrowed),
+            },
+            CrateType::InMemory(krate) => CrateRepr::InMemory {
+                name: krate.name().into(),
+                version: krate.version().into(),
+                bytes: krate.bytes().into(),
+            },
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Crate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match CrateRepr::deserialize(deserializer)? {
+            CrateRepr::CratesIo { name, version } => Crate::crates_io(&name, &version),
+            CrateRepr::CratesIoLatest { name } => Crate::crates_io_latest(&name),
+            CrateRepr::Registry {
+                registry,
+                name,
+                version,
+            } => Crate::registry(registry.into_owned(), &name, &version),
+            CrateRepr::Git {
+                url,
+                rev,
+                depth,
+                submodules,
+                subdir,
+            } => {
+                let mut repo = git::GitRepo::new(&url).with_submodules(submodules);
+                if let Some(rev) = rev {
+                    if rev.starts_with('-') {
+                        return Err(serde::de::Error::custom(CrateError::InvalidRevision(
+                            rev.into_owned(),
+                        )));
+                    }
+                    repo = repo.with_rev(&rev);
+                }
+                if let Some(depth) = depth {
+                    repo = repo.with_depth(depth);
+                }
+                if let Some(subdir) = subdir {
+                    repo = repo.with_subdir(&subdir);
+                }
+                Crate::new(CrateType::Git(repo))
+            }
+            CrateRepr::Local {
+                path,
+                ignores,
+                subdir,
+            } => {
+                let ignores = ignores
+                    .iter()
+                    .map(|ignore| ignore.as_str())
+                    .collect::<Vec<_>>();
+                let mut local = local::Local::new(&path).with_ignores(&ignores);
+                if let Some(subdir) = subdir {
+                    local = local.with_subdir(&subdir);
+                }
+                Crate::new(CrateType::Local(local))
+            }
+            CrateRepr::LocalGit { path } => Crate::local_git(&path),
+            CrateRepr::InMemory {
+                name,
+                version,
+                bytes,
+            } => Crate::from_bytes(&name, &version, bytes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlternativeRegistry, Crate, CrateError, CrateSourceKind};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_rust_version() -> anyhow::Result<()> {
+        use super::parse_rust_version;
+        use semver::Version;
+
+        assert_eq!(parse_rust_version("1")?, Version::new(1, 0, 0));
+        assert_eq!(parse_rust_version("1.70")?, Version::new(1, 70, 0));
+        assert_eq!(parse_rust_version("1.70.1")?, Version::new(1, 70, 1));
+        assert!(parse_rust_version("1.70.1.0").is_err());
+        assert!(parse_rust_version("1.70-beta").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_rust_version() -> anyhow::Result<()> {
+        use super::manifest_rust_version;
+
+        assert_eq!(
+            manifest_rust_version("[package]\nname = \"foo\"\nrust-version = \"1.56\"\n")?
+                .as_deref(),
+            Some("1.56")
+        );
+        assert_eq!(manifest_rust_version("[package]\nname = \"foo\"\n")?, None);
+        assert_eq!(
+            manifest_rust_version("[package]\nrust-version.workspace = true\n")?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_edition() -> anyhow::Result<()> {
+        use super::{manifest_edition, Edition};
+        use semver::Version;
+
+        let edition = manifest_edition("[package]\nname = \"foo\"\nedition = \"2021\"\n")?;
+        assert_eq!(edition, Some(Edition::Edition2021));
+        assert_eq!(
+            edition.unwrap().min_rust_version(),
+            Some(Version::new(1, 56, 0))
+        );
+        assert_eq!(
+            manifest_edition("[package]\nname = \"foo\"\n")?,
+            Some(Edition::Edition2015)
+        );
+        assert_eq!(
+            manifest_edition("[package]\nedition = \"2027\"\n")?,
+            Some(Edition::Unknown("2027".into()))
+        );
+        assert_eq!(
+            manifest_edition("[package]\nedition.workspace = true\n")?,
+            None
+        );
+        assert_eq!(manifest_edition("[workspace]\nmembers = []\n")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_license() -> anyhow::Result<()> {
+        use super::manifest_license;
+
+        assert_eq!(
+            manifest_license("[package]\nlicense = \"MIT OR Apache-2.0\"\n")?.as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(
+            manifest_license("[package]\nlicense-file = \"LICENSE.txt\"\n")?.as_deref(),
+            Some("LICENSE.txt")
+        );
+        assert_eq!(
+            manifest_license("[package]\nlicense = \"MIT\"\nlicense-file = \"LICENSE\"\n")?
+                .as_deref(),
+            Some("MIT")
+        );
+        assert_eq!(manifest_license("[package]\nname = \"foo\"\n")?, None);
+        assert_eq!(
+            manifest_license("[package]\nlicense.workspace = true\n")?,
+            None
+        );
+
+        Ok(())
+    }
+
+    fn assert_round_trip(krate: &Crate, json: &str) -> anyhow::Result<()> {
+        assert_eq!(
+            serde_json::to_value(krate)?,
+            serde_json::from_str::<serde_json::Value>(json)?
+        );
+
+        let deserialized: Crate = serde_json::from_str(json)?;
+        assert_eq!(deserialized.to_string(), krate.to_string());
+        assert_eq!(deserialized.source_kind(), krate.source_kind());
+        assert_eq!(
+            serde_json::to_value(&deserialized)?,
+            serde_json::to_value(krate)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crates_io_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::crates_io("lazy_static", "1.0.0"),
+            r#"{"type": "crates-io", "name": "lazy_static", "version": "1.0.0"}"#,
+        )
+    }
+
+    #[test]
+    fn test_crates_io_latest_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::crates_io_latest("lazy_static"),
+            r#"{"type": "crates-io-latest", "name": "lazy_static"}"#,
+        )
+    }
+
+    #[test]
+    fn test_alternative_registry_serde_repr() -> anyhow::Result<()> {
+        let mut alt = AlternativeRegistry::new("https://example.com/index");
+        alt.authenticate_with_ssh_key("secret");
+        let krate = Crate::registry(alt, "foo", "0.4.0");
+
+        assert_round_trip(
+            &krate,
+            r#"{"type": "registry", "index": "https://example.com/index", "name": "foo", "version": "0.4.0"}"#,
+        )?;
+        assert!(!serde_json::to_string(&krate)?.contains("secret"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::git("https://github.com/rust-lang/rustwide"),
+            r#"{"type": "git", "url": "https://github.com/rust-lang/rustwide"}"#,
+        )
+    }
+
+    #[test]
+    fn test_git_rev_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::git_rev("https://github.com/rust-lang/rustwide", "0.19.0")?,
+            r#"{"type": "git", "url": "https://github.com/rust-lang/rustwide", "rev": "0.19.0"}"#,
+        )?;
+
+        // Revisions that would be parsed as options are rejected.
+        assert!(matches!(
+            Crate::git_rev("file:///repo", "--upload-pack=touch /tmp/pwned"),
+            Err(CrateError::InvalidRevision(_))
+        ));
+        let res = serde_json::from_str::<Crate>(
+            r#"{"type": "git", "url": "file:///repo", "rev": "--upload-pack=touch /tmp/pwned"}"#,
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_shallow_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::git_shallow("https://github.com/rust-lang/rustwide", 1),
+            r#"{"type": "git", "url": "https://github.com/rust-lang/rustwide", "depth": 1}"#,
+        )
+    }
+
+    #[test]
+    fn test_git_with_submodules_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::git_with_submodules("https://github.com/rust-lang/rustwide"),
+            r#"{"type": "git", "url": "https://github.com/rust-lang/rustwide", "submodules": true}"#,
+        )
+    }
+
+    #[test]
+    fn test_git_subdir_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::git_subdir(
+                "https://github.com/rust-lang/rustwide",
+                Path::new("crates/foo"),
+            ),
+            r#"{"type": "git", "url": "https://github.com/rust-lang/rustwide", "subdir": "crates/foo"}"#,
+        )
+    }
+
+    #[test]
+    fn test_local_subdir_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::local_subdir(
+                Path::new("tests/buildtest/crates/cargo-workspace"),
+                Path::new("world"),
+            ),
+            r#"{"type": "local", "path": "tests/buildtest/crates/cargo-workspace", "subdir": "world"}"#,
+        )
+    }
+
+    #[test]
+    fn test_local_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::local(Path::new("tests/buildtest/crates/hello-world")),
+            r#"{"type": "local", "path": "tests/buildtest/crates/hello-world"}"#,
+        )
+    }
+
+    #[test]
+    fn test_local_with_ignores_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::local_with_ignores(
+                Path::new("tests/buildtest/crates/hello-world"),
+                &["node_modules", "/.git"],
+            ),
+            r#"{
+                "type": "local",
+                "path": "tests/buildtest/crates/hello-world",
+                "ignores": ["node_modules", "/.git"]
+            }"#,
+        )
+    }
+
+    #[test]
+    fn test_local_git_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::local_git(Path::new("tests/buildtest/crates/hello-world")),
+            r#"{"type": "local-git", "path": "tests/buildtest/crates/hello-world"}"#,
+        )
+    }
+
+    #[test]
+    fn test_in_memory_serde_repr() -> anyhow::Result<()> {
+        assert_round_trip(
+            &Crate::from_bytes("foo", "0.1.0", b"hello".to_vec()),
+            r#"{"type": "in-memory", "name": "foo", "version": "0.1.0", "bytes": "aGVsbG8="}"#,
+        )
+    }
+
+    #[test]
+    fn test_crates_io_identity() {
+        let krate = Crate::crates_io("lazy_static", "1.0.0");
+        assert_eq!(krate.name(), Some("lazy_static"));
+        assert_eq!(krate.version(), Some("1.0.0"));
+        assert_eq!(krate.source_kind(), CrateSourceKind::CratesIo);
+    }
+
+    #[test]
+    fn test_crates_io_latest_identity() {
+        let krate = Crate::crates_io_latest("lazy_static");
+        assert_eq!(krate.name(), Some("lazy_static"));
+        assert_eq!(krate.version(), None);
+        assert_eq!(krate.resolved_version(), None);
+        assert_eq!(krate.source_kind(), CrateSourceKind::CratesIo);
+    }
+
+    #[test]
+    fn test_alternative_registry_identity() {
+        let alt = AlternativeRegistry::new("https://example.com/index");
+        let krate = Crate::registry(alt, "foo", "0.4.0");
+        assert_eq!(krate.name(), Some("foo"));
+        assert_eq!(krate.version(), Some("0.4.0"));
+        assert_eq!(krate.source_kind(), CrateSourceKind::AlternativeRegistry);
+    }
+
+    #[test]
+    fn test_git_identity() {
+        let krate = Crate::git("https://github.com/rust-lang/rustwide");
+        assert_eq!(krate.name(), None);
+        assert_eq!(krate.version(), None);
+        assert_eq!(krate.source_kind(), CrateSourceKind::Git);
+    }
+
+    #[test]
+    fn test_local_identity() {
+        let krate = Crate::local(Path::new("tests/buildtest/crates/hello-world"));
+        assert_eq!(krate.name(), None);
+        assert_eq!(krate.version(), None);
+        assert_eq!(krate.source_kind(), CrateSourceKind::Local);
+    }
+
+    #[test]
+    fn test_in_memory_identity() {
+        let krate = Crate::from_bytes("foo", "0.1.0", Vec::new());
+        assert_eq!(krate.name(), Some("foo"));
+        assert_eq!(krate.version(), Some("0.1.0"));
+        assert_eq!(krate.source_kind(), CrateSourceKind::InMemory);
+    }
+}
@@ -1,19 +1,52 @@
-use super::CrateTrait;
+use super::index::IndexEntry;
+use super::{CrateError, CrateTrait};
+use crate::metrics::Counter;
 use crate::Workspace;
-use anyhow::Context as _;
+use anyhow::{anyhow, Context as _};
 use flate2::read::GzDecoder;
-use log::info;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 use tar::Archive;
 
 static CRATES_ROOT: &str = "https://static.crates.io/crates";
+static CRATES_IO_INDEX: &str = "https://index.crates.io";
+static DEFAULT_ALTERNATIVE_REGISTRY_NAME: &str = "rustwide-alternative-registry";
+/// Size of the chunks crates are downloaded in, between progress reports.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
 
 /// A type for alternative registry as described in rust-lang/rfcs#2141
+///
+/// When building a crate from an alternative registry, rustwide configures cargo to know about the
+/// registry (see [`name`](struct.AlternativeRegistry.html#method.name)), so that dependencies on
+/// other crates of the same registry can be fetched.
+///
+/// Registries using the sparse protocol are supported by prefixing their index URL with `sparse+`
+/// (for example `sparse+https://example.com/index/`), like in cargo's configuration. Their index
+/// files are then fetched over HTTP when needed, instead of cloning the whole git index.
+///
+/// The registry can be serialized and deserialized with serde. To avoid leaking credentials, the
+/// SSH key provided with
+/// [`authenticate_with_ssh_key`](struct.AlternativeRegistry.html#method.authenticate_with_ssh_key)
+/// and the token provided with
+/// [`authenticate_with_token`](struct.AlternativeRegistry.html#method.authenticate_with_token)
+/// are never serialized, and have to be provided again after deserializing the registry. The `key`
+/// and `token` fields are still accepted when deserializing, for loading credentials from trusted
+/// configuration files.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Clone)]
 pub struct AlternativeRegistry {
+    #[serde(rename = "index")]
     registry_index: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    name: Option<String>,
+    #[serde(skip_serializing, default)]
     key: Option<String>,
+    #[serde(skip_serializing, default)]
+    token: Option<String>,
 }
 
 impl AlternativeRegistry {
@@ -21,7 +54,9 @@ impl AlternativeRegistry {
     pub fn new(registry_index: impl Into<String>) -> AlternativeRegistry {
         AlternativeRegistry {
             registry_index: registry_index.into(),
+            name: None,
             key: None,
+            token: None,
         }
     }
 
@@ -30,13 +65,112 @@ impl AlternativeRegistry {
         self.key = Some(key.into());
     }
 
-    fn index(&self) -> &str {
+    /// Specify the token cargo uses to authenticate with the registry.
+    ///
+    /// The token is also sent by rustwide in the `Authorization` header when fetching files of a
+    /// sparse index, and when downloading crates from registries requiring authentication (with
+    /// `auth-required` set to `true` in their `config.json`, as described in [RFC 3139]). Like
+    /// cargo, rustwide sends the token as-is, so it needs to include any scheme the registry
+    /// expects (for example `Bearer`).
+    ///
+    /// [RFC 3139]: https://rust-lang.github.io/rfcs/3139-cargo-alternative-registry-auth.html
+    pub fn authenticate_with_token(&mut self, token: impl Into<String>) {
+        self.token = Some(token.into());
+    }
+
+    /// Specify the name of the registry in cargo's configuration, which is
+    /// `rustwide-alternative-registry` by default. It needs to match the name used by crates
+    /// depending on the registry with `registry = "name"` in their `Cargo.toml`.
+    pub fn name(&mut self, name: impl Into<String>) {
+        self.name = Some(name.into());
+    }
+
+    fn cargo_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .unwrap_or(DEFAULT_ALTERNATIVE_REGISTRY_NAME)
+    }
+
+    /// Return the cargo configuration defining this registry. The token is not included, as the
+    /// configuration is readable from inside the sandbox: use
+    /// [`cargo_env`](#method.cargo_env) to provide it to cargo instead.
+    pub(crate) fn cargo_config(&self) -> toml::Table {
+        let mut registry = toml::Table::new();
+        registry.insert("index".into(), self.registry_index.clone().into());
+
+        let mut registries = toml::Table::new();
+        registries.insert(self.cargo_name().into(), registry.into());
+
+        let mut config = toml::Table::new();
+        config.insert("registries".into(), registries.into());
+        config
+    }
+
+    /// Return the environment variables providing the token of this registry to cargo.
+    pub(crate) fn cargo_env(&self) -> Vec<(String, String)> {
+        match &self.token {
+            Some(token) => vec![(
+                format!(
+                    "CARGO_REGISTRIES_{}_TOKEN",
+                    self.cargo_name().to_uppercase().replace('-', "_")
+                ),
+                token.clone(),
+            )],
+            None => Vec::new(),
+        }
+    }
+
+    /// List all the versions of a crate published to this registry, alongside whether each
+    /// version is yanked. The registry index will be cloned if it's not present in the workspace.
+    pub fn available_versions(
+        &self,
+        name: &str,
+        workspace: &Workspace,
+    ) -> anyhow::Result<Vec<(String, bool)>> {
+        Registry::Alternative(self.clone()).available_versions(workspace, name)
+    }
+
+    pub(crate) fn index(&self) -> &str {
         self.registry_index.as_str()
     }
 
     fn index_folder(&self) -> String {
         crate::utils::escape_path(self.registry_index.as_bytes())
     }
+
+    /// Return the URL of the index without the `sparse+` prefix, if the registry uses the sparse
+    /// protocol.
+    fn sparse_index_url(&self) -> Option<&str> {
+        self.registry_index
+            .strip_prefix("sparse+")
+            .map(|url| url.trim_end_matches('/'))
+    }
+
+    /// Read a file of the registry index, over HTTP for sparse registries or from the local clone
+    /// of the git index otherwise.
+    fn read_index_file(&self, workspace: &Workspace, path: &str) -> anyhow::Result<String> {
+        match self.sparse_index_url() {
+            Some(url) => {
+                fetch_sparse_index_file(workspace.http_client(), url, self.token.as_deref(), path)
+            }
+            None => Ok(std::fs::read_to_string(
+                alt_index_path(workspace, self)?.join(path),
+            )?),
+        }
+    }
+}
+
+impl std::fmt::Debug for AlternativeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The credentials are redacted, to avoid leaking them in logs.
+        let redact = |secret: &Option<String>| secret.as_ref().map(|_| "[redacted]");
+        f.debug_struct("AlternativeRegistry")
+            .field("registry_index", &self.registry_index)
+            .field("name", &self.name)
+            .field("key", &redact(&self.key))
+            .field("token", &redact(&self.token))
+            .finish()
+    }
 }
 
 pub(crate) enum Registry {
@@ -58,17 +192,62 @@ impl Registry {
             Registry::Alternative(alt) => alt.index().to_string(),
         }
     }
+
+    /// Return the contents of the index file describing all the versions of a crate. The sparse
+    /// index is used for crates.io, while alternative registries use either their sparse index or
+    /// their local git clone.
+    fn index_content(&self, workspace: &Workspace, name: &str) -> anyhow::Result<String> {
+        let index_file = index_file_path(name);
+        match self {
+            Registry::CratesIo => Ok(workspace
+                .http_client()
+                .get(format!("{}/{}", CRATES_IO_INDEX, index_file))
+                .send()?
+                .error_for_status()
+                .with_context(|| format!("failed to find crate {} in crates.io's index", name))?
+                .text()?),
+            Registry::Alternative(alt) => {
+                alt.read_index_file(workspace, &index_file)
+                    .with_context(|| {
+                        format!(
+                            "failed to find crate {} in the index of {}",
+                            name,
+                            alt.index()
+                        )
+                    })
+            }
+        }
+    }
+
+    /// Return all the published versions of a crate, alongside whether they are yanked.
+    pub(super) fn available_versions(
+        &self,
+        workspace: &Workspace,
+        name: &str,
+    ) -> anyhow::Result<Vec<(String, bool)>> {
+        Ok(parse_index(&self.index_content(workspace, name)?)
+            .map(|entry| (entry.vers, entry.yanked))
+            .collect())
+    }
 }
 
 pub(super) struct RegistryCrate {
     registry: Registry,
     name: String,
-    version: String,
+    /// Version of the crate, resolved from the registry index when the latest version is needed.
+    version: OnceLock<String>,
+    latest: bool,
+    /// Checksum of the crate, resolved from the registry index when fetching the crate with
+    /// `cache_crates_by_checksum` enabled.
+    checksum: OnceLock<String>,
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 struct IndexConfig {
     dl: String,
+    #[serde(default)]
+    auth_required: bool,
 }
 
 impl RegistryCrate {
@@ -76,127 +255,310 @@ impl RegistryCrate {
         RegistryCrate {
             registry,
             name: name.into(),
-            version: version.into(),
+            version: OnceLock::from(version.to_string()),
+            latest: false,
+            checksum: OnceLock::new(),
         }
     }
 
-    fn cache_path(&self, workspace: &Workspace) -> PathBuf {
+    /// Create a crate whose version is resolved to the latest one published to the registry.
+    pub(super) fn latest(registry: Registry, name: &str) -> Self {
+        RegistryCrate {
+            registry,
+            name: name.into(),
+            version: OnceLock::new(),
+            latest: true,
+            checksum: OnceLock::new(),
+        }
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the version of the crate, or `None` if it's the latest one and it wasn't resolved
+    /// yet.
+    pub(super) fn version(&self) -> Option<&str> {
+        self.version.get().map(String::as_str)
+    }
+
+    /// Return whether the version of the crate is resolved to the latest one.
+    pub(super) fn is_latest(&self) -> bool {
+        self.latest
+    }
+
+    /// Return the version of the crate, resolving the latest version from the registry index if
+    /// it wasn't resolved yet.
+    fn resolve_version(&self, workspace: &Workspace) -> anyhow::Result<&str> {
+        if let Some(version) = self.version.get() {
+            return Ok(version);
+        }
+        let content = self.registry.index_content(workspace, &self.name)?;
+        let version = latest_version(&content).ok_or_else(|| {
+            anyhow!(
+                "crate {} has no available versions in the index of {}",
+                self.name,
+                self.registry.name()
+            )
+        })?;
+        info!(
+            "resolved the latest version of {} to {}",
+            self.name, version
+        );
+        Ok(self.version.get_or_init(|| version))
+    }
+
+    pub(super) fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn cache_dir(&self, workspace: &Workspace) -> PathBuf {
         workspace
             .cache_dir()
             .join(self.registry.cache_folder())
             .join(&self.name)
-            .join(format!("{}-{}.crate", self.name, self.version))
     }
 
-    fn fetch_url(&self, workspace: &Workspace) -> anyhow::Result<String> {
-        match &self.registry {
-            Registry::CratesIo => Ok(format!(
-                "{0}/{1}/{1}-{2}.crate",
-                CRATES_ROOT, self.name, self.version
-            )),
-            Registry::Alternative(alt) => {
-                let index_path = workspace
-                    .cache_dir()
-                    .join("registry-index")
-                    .join(alt.index_folder());
-                if !index_path.exists() {
-                    let url = alt.index();
-                    let mut fo = git2::FetchOptions::new();
-                    if let Some(key) = alt.key.as_deref() {
-                        fo.remote_callbacks({
-                            let mut callbacks = git2::RemoteCallbacks::new();
-                            callbacks.credentials(
-                                move |_url, username_from_url, _allowed_types| {
-                                    git2::Cred::ssh_key_from_memory(
-                                        username_from_url.unwrap(),
-                                        None,
-                                        key,
-                                        None,
-                                    )
-                                },
-                            );
-                            callbacks
-                        });
-                    }
-
-                    git2::build::RepoBuilder::new()
-                        .fetch_options(fo)
-                        .clone(url, &index_path)
-                        .with_context(|| format!("unable to update_index at {}", url))?;
-                    info!("cloned registry index");
+    /// Return the path `fetch` downloads the crate to. With `cache_crates_by_checksum` enabled
+    /// the checksum is looked up in the registry index, and remembered for the later calls.
+    fn fetch_path(&self, workspace: &Workspace) -> anyhow::Result<PathBuf> {
+        let version = self.resolve_version(workspace)?;
+        let checksum = if workspace.cache_crates_by_checksum() {
+            Some(match self.checksum.get() {
+                Some(checksum) => checksum.as_str(),
+                None => {
+                    let checksum = self.checksum(workspace)?;
+                    self.checksum.get_or_init(|| checksum).as_str()
                 }
-                let config = std::fs::read_to_string(index_path.join("config.json"))?;
-                let template_url = serde_json::from_str::<IndexConfig>(&config)
-                    .context("registry has invalid config.json")?
-                    .dl;
-                let replacements = [("{crate}", &self.name), ("{version}", &self.version)];
+            })
+        } else {
+            None
+        };
+        Ok(self
+            .cache_dir(workspace)
+            .join(self.cache_file_name(version, checksum)))
+    }
 
-                let url = if replacements
-                    .iter()
-                    .any(|(key, _)| template_url.contains(key))
-                {
-                    let mut url = template_url;
-                    for (key, value) in &replacements {
-                        url = url.replace(key, value);
-                    }
-                    url
-                } else {
-                    format!("{}/{}/{}/download", template_url, self.name, self.version)
-                };
+    /// Return the path of the cached crate, or `None` if it wasn't fetched. The registry index is
+    /// not queried for crates with a pinned version: with `cache_crates_by_checksum` enabled, the
+    /// checksum resolved by `fetch` is used, or the most recently fetched file of the version.
+    fn cached_path(&self, workspace: &Workspace) -> anyhow::Result<Option<PathBuf>> {
+        let version = self.resolve_version(workspace)?;
+        let dir = self.cache_dir(workspace);
+        let path = if !workspace.cache_crates_by_checksum() {
+            dir.join(self.cache_file_name(version, None))
+        } else if let Some(checksum) = self.checksum.get() {
+            dir.join(self.cache_file_name(version, Some(checksum)))
+        } else {
+            let mut files = self
+                .checksummed_files(&dir, version)?
+                .into_iter()
+                .map(|path| Ok((std::fs::metadata(&path)?.modified()?, path)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            files.sort();
+            match files.pop() {
+                Some((_, path)) => path,
+                None => return Ok(None),
+            }
+        };
+        Ok(if path.is_file() { Some(path) } else { None })
+    }
 
-                Ok(url)
+    /// Return the cached files of the version named after their checksum.
+    fn checksummed_files(&self, dir: &Path, version: &str) -> anyhow::Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let prefix = format!("{}-{}-", self.name, version);
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let checksum = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|name| name.strip_suffix(".crate"));
+            // Checksums are sha256 hashes, which can't be confused with pre-release versions.
+            if let Some(checksum) = checksum {
+                if checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                    files.push(entry.path());
+                }
             }
         }
+        Ok(files)
     }
-}
 
-impl CrateTrait for RegistryCrate {
-    fn fetch(&self, workspace: &Workspace) -> anyhow::Result<()> {
-        let local = self.cache_path(workspace);
+    /// Remove the cached files of the version whose checksum doesn't match the current one.
+    fn remove_stale_files(&self, dir: &Path, version: &str, current: &Path) -> anyhow::Result<()> {
+        for path in self.checksummed_files(dir, version)? {
+            if path != current {
+                info!("removing stale cached crate {}", path.display());
+                crate::utils::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cache_file_name(&self, version: &str, checksum: Option<&str>) -> String {
+        match checksum {
+            Some(checksum) => format!("{}-{}-{}.crate", self.name, version, checksum),
+            None => format!("{}-{}.crate", self.name, version),
+        }
+    }
+
+    /// Return the contents of the crate's `Cargo.toml`, or `None` if the crate wasn't fetched.
+    pub(super) fn manifest(&self, workspace: &Workspace) -> anyhow::Result<Option<String>> {
+        let Some(path) = self.cached_path(workspace)? else {
+            return Ok(None);
+        };
+        let mut tar = Archive::new(GzDecoder::new(BufReader::new(File::open(&path)?)));
+        manifest_from_tarball(&mut tar)
+    }
+
+    /// Return the checksum of the crate recorded in the registry index.
+    fn checksum(&self, workspace: &Workspace) -> anyhow::Result<String> {
+        Ok(self.index_entry(workspace)?.cksum)
+    }
+
+    /// Return whether this version of the crate is marked as yanked in the registry index.
+    pub(super) fn is_yanked(&self, workspace: &Workspace) -> anyhow::Result<bool> {
+        Ok(self.index_entry(workspace)?.yanked)
+    }
+
+    pub(super) fn index_entry(&self, workspace: &Workspace) -> anyhow::Result<IndexEntry> {
+        let version = self.resolve_version(workspace)?;
+        let content = self.registry.index_content(workspace, &self.name)?;
+        find_entry(&content, version).ok_or_else(|| {
+            anyhow!(
+                "crate {} {} is missing from the index of {}",
+                self.name,
+                version,
+                self.registry.name()
+            )
+        })
+    }
+
+    /// Fetch the crate, calling `progress` with the number of bytes downloaded so far and the
+    /// total size of the download (when known) after each chunk. Returning an error from
+    /// `progress` aborts the download.
+    pub(super) fn fetch_with_progress(
+        &self,
+        workspace: &Workspace,
+        progress: &mut dyn FnMut(u64, Option<u64>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let local = self.fetch_path(workspace)?;
+        let version = self.resolve_version(workspace)?;
         if local.exists() {
-            info!("crate {} {} is already in cache", self.name, self.version);
+            info!("crate {} {} is already in cache", self.name, version);
+            workspace.metrics().increment_counter(Counter::CacheHit);
             return Ok(());
         }
+        workspace.metrics().increment_counter(Counter::CacheMiss);
 
-        info!("fetching crate {} {}...", self.name, self.version);
+        info!("fetching crate {} {}...", self.name, version);
         if let Some(parent) = local.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        workspace
-            .http_client()
-            .get(self.fetch_url(workspace)?)
+        let expected = if workspace.verify_crate_checksums() {
+            Some(self.checksum(workspace)?)
+        } else {
+            None
+        };
+        let (url, token) = self.fetch_url(workspace)?;
+        let resp = authenticated_get(workspace.http_client(), &url, token)
             .send()?
-            .error_for_status()?
-            .write_to(&mut BufWriter::new(File::create(&local)?))?;
-
+            .error_for_status()?;
+        let total = resp
+            .headers()
+            .get(attohttpc::header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse().ok());
+        write_verified(
+            &local,
+            resp,
+            expected.as_deref(),
+            &format!("{} {}", self.name, version),
+            total,
+            progress,
+        )?;
+        if workspace.cache_crates_by_checksum() {
+            let dir = self.cache_dir(workspace);
+            self.remove_stale_files(&dir, version, &local)?;
+        }
         Ok(())
     }
 
+    /// Return the URL to download the crate from, alongside the token to authenticate the
+    /// download with if the registry requires it.
+    fn fetch_url(&self, workspace: &Workspace) -> anyhow::Result<(String, Option<&str>)> {
+        let version = self.resolve_version(workspace)?;
+        match &self.registry {
+            Registry::CratesIo => Ok((
+                format!("{0}/{1}/{1}-{2}.crate", CRATES_ROOT, self.name, version),
+                None,
+            )),
+            Registry::Alternative(alt) => {
+                let config = alt.read_index_file(workspace, "config.json")?;
+                let config = serde_json::from_str::<IndexConfig>(&config)
+                    .context("registry has invalid config.json")?;
+                let token = if config.auth_required {
+                    alt.token.as_deref()
+                } else {
+                    None
+                };
+                Ok((download_url(&config.dl, &self.name, version), token))
+            }
+        }
+    }
+}
+
+impl CrateTrait for RegistryCrate {
+    fn fetch(&self, workspace: &Workspace) -> anyhow::Result<()> {
+        self.fetch_with_progress(workspace, &mut |_, _| Ok(()))
+    }
+
     fn purge_from_cache(&self, workspace: &Workspace) -> anyhow::Result<()> {
-        let path = self.cache_path(workspace);
-        if path.exists() {
-            crate::utils::remove_file(&path)?;
+        // The files cached with and without the checksum are removed, without querying the
+        // registry index.
+        let version = self.resolve_version(workspace)?;
+        let dir = self.cache_dir(workspace);
+        let mut paths = self.checksummed_files(&dir, version)?;
+        paths.push(dir.join(self.cache_file_name(version, None)));
+        for path in paths {
+            if path.exists() {
+                crate::utils::remove_file(&path)?;
+            }
         }
         Ok(())
     }
 
     fn copy_source_to(&self, workspace: &Workspace, dest: &Path) -> anyhow::Result<()> {
-        let cached = self.cache_path(workspace);
-        let mut file = File::open(cached)?;
-        let mut tar = Archive::new(GzDecoder::new(BufReader::new(&mut file)));
+        let version = self.resolve_version(workspace)?;
+        let cached = self.cached_path(workspace)?.ok_or_else(|| {
+            anyhow!(
+                "crate {} {} is missing from the cache, fetch it first",
+                self.name,
+                version
+            )
+        })?;
 
         info!(
             "extracting crate {} {} into {}",
             self.name,
-            self.version,
+            version,
             dest.display()
         );
-        if let Err(err) = unpack_without_first_dir(&mut tar, dest) {
+        if let Err(err) = unpack_crate_file(
+            &cached,
+            dest,
+            workspace.max_extracted_crate_size(),
+            workspace.max_crate_compression_ratio(),
+        ) {
             let _ = crate::utils::remove_dir_all(dest);
             Err(err.context(format!(
                 "unable to download {} version {}",
-                self.name, self.version
+                self.name, version
             )))
         } else {
             Ok(())
@@ -211,15 +573,304 @@ impl std::fmt::Display for RegistryCrate {
             "{} crate {} {}",
             self.registry.name(),
             self.name,
-            self.version
+            self.version().unwrap_or("(latest version)")
         )
     }
 }
 
-fn unpack_without_first_dir<R: Read>(archive: &mut Archive<R>, path: &Path) -> anyhow::Result<()> {
+/// Write the contents of the reader to the path, failing without writing anything if their sha256
+/// doesn't match the expected checksum of the crate.
+fn write_verified(
+    path: &Path,
+    mut reader: impl Read,
+    expected: Option<&str>,
+    krate: &str,
+    total: Option<u64>,
+    progress: &mut dyn FnMut(u64, Option<u64>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    crate::utils::write_atomically(path, |file| {
+        let mut writer = HashingWriter {
+            inner: BufWriter::new(file),
+            hasher: Sha256::new(),
+        };
+        let mut buf = vec![0; DOWNLOAD_CHUNK_SIZE];
+        let mut downloaded = 0;
+        loop {
+            let len = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            writer.write_all(&buf[..len])?;
+            downloaded += len as u64;
+            progress(downloaded, total)?;
+        }
+        writer.inner.flush()?;
+
+        let actual = format!("{:x}", writer.hasher.finalize());
+        match expected {
+            Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+                Err(CrateError::ChecksumMismatch {
+                    krate: krate.into(),
+                    expected: expected.into(),
+                    actual,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Writer computing the sha256 of the data written to it.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Resolve the download URL of a crate from the `dl` template of the registry's `config.json`.
+fn download_url(template_url: &str, name: &str, version: &str) -> String {
+    let replacements = [("{crate}", name), ("{version}", version)];
+
+    if replacements
+        .iter()
+        .any(|(key, _)| template_url.contains(key))
+    {
+        let mut url = template_url.to_string();
+        for (key, value) in &replacements {
+            url = url.replace(key, value);
+        }
+        url
+    } else {
+        format!("{}/{}/{}/download", template_url, name, version)
+    }
+}
+
+/// Fetch a file of a sparse registry index over HTTP, authenticating with the token if provided.
+fn fetch_sparse_index_file(
+    http: &attohttpc::Session,
+    index_url: &str,
+    token: Option<&str>,
+    path: &str,
+) -> anyhow::Result<String> {
+    Ok(
+        authenticated_get(http, &format!("{}/{}", index_url, path), token)
+            .send()?
+            .error_for_status()?
+            .text()?,
+    )
+}
+
+/// Prepare a GET request, sending the token in the `Authorization` header if provided.
+fn authenticated_get(
+    http: &attohttpc::Session,
+    url: &str,
+    token: Option<&str>,
+) -> attohttpc::RequestBuilder {
+    let req = http.get(url);
+    match token {
+        Some(token) => req.header("Authorization", token),
+        None => req,
+    }
+}
+
+/// Return the path of the local clone of an alternative registry's index, cloning it if missing.
+fn alt_index_path(workspace: &Workspace, alt: &AlternativeRegistry) -> anyhow::Result<PathBuf> {
+    let index_path = workspace
+        .cache_dir()
+        .join("registry-index")
+        .join(alt.index_folder());
+    if !index_path.exists() {
+        let url = alt.index();
+        git2::build::RepoBuilder::new()
+            .fetch_options(fetch_options(alt))
+            .clone(url, &index_path)
+            .with_context(|| format!("unable to update_index at {}", url))?;
+        touch_last_updated(&index_path)?;
+        info!("cloned registry index");
+    } else if let Some(interval) = workspace.registry_index_refresh_interval() {
+        if is_stale(&index_path, interval) {
+            if let Err(err) = refresh_index(&index_path, alt) {
+                warn!("failed to update the index of {}: {:?}", alt.index(), err);
+            }
+        }
+    }
+    Ok(index_path)
+}
+
+fn fetch_options(alt: &AlternativeRegistry) -> git2::FetchOptions<'_> {
+    let mut fo = git2::FetchOptions::new();
+    if let Some(key) = alt.key.as_deref() {
+        fo.remote_callbacks({
+            let mut callbacks = git2::RemoteCallbacks::new();
+            callbacks.credentials(move |_url, username_from_url, _allowed_types| {
+                git2::Cred::ssh_key_from_memory(username_from_url.unwrap(), None, key, None)
+            });
+            callbacks
+        });
+    }
+    fo
+}
+
+/// Path of the file whose modification time records when the clone of an index was last updated.
+fn last_updated_path(index_path: &Path) -> PathBuf {
+    index_path.join(".git").join("rustwide-last-updated")
+}
+
+fn touch_last_updated(index_path: &Path) -> anyhow::Result<()> {
+    std::fs::write(last_updated_path(index_path), b"")?;
+    Ok(())
+}
+
+fn is_stale(index_path: &Path, interval: Duration) -> bool {
+    let modified = std::fs::metadata(last_updated_path(index_path)).and_then(|m| m.modified());
+    match modified {
+        Ok(modified) => SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|elapsed| elapsed > interval),
+        // Clones created by older versions of rustwide don't have the file.
+        Err(_) => true,
+    }
+}
+
+/// Fetch the latest changes of the registry index into its local clone.
+fn refresh_index(index_path: &Path, alt: &AlternativeRegistry) -> anyhow::Result<()> {
+    info!("updating the index of {}", alt.index());
+    let repo = git2::Repository::open(index_path)?;
+    repo.find_remote("origin")?
+        .fetch(&["HEAD"], Some(&mut fetch_options(alt)), None)?;
+    let head = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+    repo.reset(head.as_object(), git2::ResetType::Hard, None)?;
+    touch_last_updated(index_path)
+}
+
+/// Return the path of the file describing a crate inside a registry index, following the layout
+/// documented in the Cargo book.
+fn index_file_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+fn parse_index(index_content: &str) -> impl Iterator<Item = IndexEntry> + '_ {
+    index_content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IndexEntry>(line).ok())
+}
+
+fn find_entry(index_content: &str, version: &str) -> Option<IndexEntry> {
+    parse_index(index_content).find(|entry| entry.vers == version)
+}
+
+/// Return the highest version in the index that isn't yanked, preferring stable versions over
+/// pre-releases. Versions that are not valid semver are ignored.
+fn latest_version(index_content: &str) -> Option<String> {
+    parse_index(index_content)
+        .filter(|entry| !entry.yanked)
+        .filter_map(|entry| semver::Version::parse(&entry.vers).ok())
+        .max_by_key(|version| (version.pre.is_empty(), version.clone()))
+        .map(|version| version.to_string())
+}
+
+/// Return the contents of the `Cargo.toml` at the root of a crate tarball, if present.
+pub(super) fn manifest_from_tarball<R: Read>(
+    archive: &mut Archive<R>,
+) -> anyhow::Result<Option<String>> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // Files in the tarball are inside a top-level directory.
+        if entry.path()?.components().count() == 2
+            && entry.path()?.file_name() == Some("Cargo.toml".as_ref())
+        {
+            let mut manifest = String::new();
+            entry.read_to_string(&mut manifest)?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// Unpack a gzipped `.crate` file into `dest`, failing if the total size of the extracted files
+/// exceeds `max_size` or if the decompressed archive is more than `max_ratio` times bigger than
+/// the compressed file.
+fn unpack_crate_file(
+    path: &Path,
+    dest: &Path,
+    max_size: Option<u64>,
+    max_ratio: Option<u64>,
+) -> anyhow::Result<()> {
+    let file = File::open(path)?;
+    let compressed_size = file.metadata()?.len();
+    let mut tar = Archive::new(LimitedReader {
+        inner: GzDecoder::new(BufReader::new(file)),
+        read: 0,
+        limit: max_ratio.map(|ratio| compressed_size.saturating_mul(ratio)),
+        exceeded: false,
+    });
+
+    let res = unpack_without_first_dir(&mut tar, dest, max_size);
+    match (res, max_ratio) {
+        (Err(_), Some(ratio)) if tar.into_inner().exceeded => {
+            Err(CrateError::CompressionRatioExceeded { ratio }.into())
+        }
+        (res, _) => res,
+    }
+}
+
+/// Reader failing once more than `limit` bytes are read from it, recording it in `exceeded`.
+struct LimitedReader<R: Read> {
+    inner: R,
+    read: u64,
+    limit: Option<u64>,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.read += len as u64;
+        if self.limit.is_some_and(|limit| self.read > limit) {
+            self.exceeded = true;
+            return Err(std::io::Error::other("read limit exceeded"));
+        }
+        Ok(len)
+    }
+}
+
+/// Unpack the archive into `path`, failing if the total size of the extracted files exceeds
+/// `max_size`. Callers are responsible for removing the partially extracted files on errors.
+pub(super) fn unpack_without_first_dir<R: Read>(
+    archive: &mut Archive<R>,
+    path: &Path,
+    max_size: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut extracted_size: u64 = 0;
     let entries = archive.entries()?;
     for entry in entries {
         let mut entry = entry?;
+        if let Some(limit) = max_size {
+            extracted_size = extracted_size.saturating_add(entry.size());
+            if extracted_size > limit {
+                return Err(CrateError::ExtractedSizeLimitExceeded { limit }.into());
+            }
+        }
         let relpath = {
             let path = entry.path();
             let path = path?;
@@ -237,3 +888,443 @@ fn unpack_without_first_dir<R: Read>(archive: &mut Archive<R>, path: &Path) -> a
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        download_url, fetch_sparse_index_file, find_entry, index_file_path, latest_version,
+        unpack_crate_file, write_verified, AlternativeRegistry, IndexConfig, Registry,
+        RegistryCrate, DOWNLOAD_CHUNK_SIZE,
+    };
+    use anyhow::anyhow;
+
+    fn find_checksum(index_content: &str, version: &str) -> Option<String> {
+        find_entry(index_content, version).map(|entry| entry.cksum)
+    }
+
+    #[test]
+    fn test_alternative_registry_cargo_config() {
+        let mut alt = AlternativeRegistry::new("https://example.com/index");
+        assert_eq!(
+            alt.cargo_config(),
+            toml::from_str::<toml::Table>(
+                r#"registries.rustwide-alternative-registry.index = "https://example.com/index""#
+            )
+            .unwrap()
+        );
+
+        assert!(alt.cargo_env().is_empty());
+
+        alt.name("my-registry");
+        alt.authenticate_with_token("secret");
+        assert_eq!(
+            alt.cargo_config(),
+            toml::from_str::<toml::Table>(
+                r#"registries.my-registry.index = "https://example.com/index""#
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            alt.cargo_env(),
+            [(
+                "CARGO_REGISTRIES_MY_REGISTRY_TOKEN".to_string(),
+                "secret".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_alternative_registry_serde() -> anyhow::Result<()> {
+        let alt = AlternativeRegistry::new("https://example.com/index");
+        let json = serde_json::to_string(&alt)?;
+        assert_eq!(json, r#"{"index":"https://example.com/index"}"#);
+        assert_eq!(serde_json::from_str::<AlternativeRegistry>(&json)?, alt);
+
+        // The key is never serialized, but it can be deserialized.
+        let mut authenticated = alt.clone();
+        authenticated.authenticate_with_ssh_key("secret");
+        authenticated.authenticate_with_token("secret");
+        assert_eq!(serde_json::to_string(&authenticated)?, json);
+        assert_ne!(authenticated, alt);
+        assert_eq!(
+            serde_json::from_str::<AlternativeRegistry>(
+                r#"{"index": "https://example.com/index", "key": "secret", "token": "secret"}"#
+            )?,
+            authenticated
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alternative_registry_debug() {
+        let mut alt = AlternativeRegistry::new("https://example.com/index");
+        assert_eq!(
+            format!("{:?}", alt),
+            r#"AlternativeRegistry { registry_index: "https://example.com/index", name: None, key: None, token: None }"#
+        );
+
+        alt.authenticate_with_ssh_key("ssh-secret");
+        alt.authenticate_with_token("token-secret");
+        let debug = format!("{:?}", alt);
+        assert!(!debug.contains("secret"), "debug: {}", debug);
+        assert!(
+            debug.contains(r#"token: Some("[redacted]")"#),
+            "debug: {}",
+            debug
+        );
+    }
+
+    #[test]
+    fn test_sparse_index() -> anyhow::Result<()> {
+        assert_eq!(
+            AlternativeRegistry::new("sparse+https://example.com/index/").sparse_index_url(),
+            Some("https://example.com/index")
+        );
+        assert_eq!(
+            AlternativeRegistry::new("https://example.com/index").sparse_index_url(),
+            None
+        );
+
+        let server = tiny_http::Server::http("localhost:0").map_err(|e| anyhow!(e.to_string()))?;
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            while let Ok(req) = server.recv() {
+                let authorized = req
+                    .headers()
+                    .iter()
+                    .any(|h| h.field.equiv("Authorization") && h.value == "secret");
+                let body = match req.url() {
+                    "/index/config.json" => r#"{"dl": "https://example.com/dl"}"#,
+                    "/index/3/f/foo" if authorized => r#"{"name":"foo","vers":"0.1.0"}"#,
+                    _ => {
+                        let _ = req.respond(tiny_http::Response::empty(404));
+                        continue;
+                    }
+                };
+                let _ = req.respond(tiny_http::Response::from_string(body));
+            }
+        });
+
+        let http = attohttpc::Session::new();
+        let url = format!("http://localhost:{}/index", port);
+        let config = fetch_sparse_index_file(&http, &url, None, "config.json")?;
+        assert!(config.contains("https://example.com/dl"));
+        assert_eq!(
+            fetch_sparse_index_file(&http, &url, Some("secret"), &index_file_path("foo"))?,
+            r#"{"name":"foo","vers":"0.1.0"}"#
+        );
+        assert!(fetch_sparse_index_file(&http, &url, None, &index_file_path("foo")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_config() -> anyhow::Result<()> {
+        let config: IndexConfig = serde_json::from_str(r#"{"dl": "https://example.com/dl"}"#)?;
+        assert_eq!(config.dl, "https://example.com/dl");
+        assert!(!config.auth_required);
+
+        let config: IndexConfig = serde_json::from_str(
+            r#"{"dl": "https://example.com/dl", "api": "https://example.com", "auth-required": true}"#,
+        )?;
+        assert!(config.auth_required);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_url() {
+        assert_eq!(
+            download_url("https://example.com/dl", "foo", "0.1.0"),
+            "https://example.com/dl/foo/0.1.0/download"
+        );
+        assert_eq!(
+            download_url(
+                "https://example.com/{crate}/{crate}-{version}.crate",
+                "foo",
+                "0.1.0"
+            ),
+            "https://example.com/foo/foo-0.1.0.crate"
+        );
+    }
+
+    #[test]
+    fn test_refresh_index() -> anyhow::Result<()> {
+        use super::{is_stale, refresh_index, touch_last_updated};
+        use std::time::Duration;
+
+        fn commit(repo: &git2::Repository, file: &str) -> anyhow::Result<()> {
+            std::fs::write(repo.workdir().unwrap().join(file), file)?;
+            let mut index = repo.index()?;
+            index.add_path(std::path::Path::new(file))?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let sig = git2::Signature::now("rustwide", "rustwide@example.com")?;
+            let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+            let parents = parent.iter().collect::<Vec<_>>();
+            repo.commit(Some("HEAD"), &sig, &sig, file, &tree, &parents)?;
+            Ok(())
+        }
+
+        let upstream_dir = tempfile::tempdir()?;
+        let upstream = git2::Repository::init(upstream_dir.path())?;
+        commit(&upstream, "config.json")?;
+
+        let clone_dir = tempfile::tempdir()?;
+        let clone_path = clone_dir.path().join("index");
+        let url = upstream_dir.path().to_str().unwrap();
+        git2::Repository::clone(url, &clone_path)?;
+        assert!(is_stale(&clone_path, Duration::from_secs(60)));
+        touch_last_updated(&clone_path)?;
+        assert!(!is_stale(&clone_path, Duration::from_secs(60)));
+
+        commit(&upstream, "foo")?;
+        assert!(!clone_path.join("foo").exists());
+        refresh_index(&clone_path, &AlternativeRegistry::new(url))?;
+        assert!(clone_path.join("foo").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_verified() -> anyhow::Result<()> {
+        use crate::crates::CrateError;
+
+        // sha256 of "hello"
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let dir = tempfile::tempdir()?;
+
+        let path = dir.path().join("foo-0.1.0.crate");
+        write_verified(
+            &path,
+            &b"hello"[..],
+            Some(checksum),
+            "foo 0.1.0",
+            None,
+            &mut |_, _| Ok(()),
+        )?;
+        assert_eq!(std::fs::read(&path)?, b"hello");
+
+        let path = dir.path().join("bar-0.1.0.crate");
+        let err = write_verified(
+            &path,
+            &b"corrupted"[..],
+            Some(checksum),
+            "bar 0.1.0",
+            None,
+            &mut |_, _| Ok(()),
+        )
+        .unwrap_err();
+        match err.downcast_ref::<CrateError>() {
+            Some(CrateError::ChecksumMismatch {
+                krate, expected, ..
+            }) => {
+                assert_eq!(krate, "bar 0.1.0");
+                assert_eq!(expected, checksum);
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+        assert!(!path.exists());
+
+        // Without a checksum nothing is verified.
+        write_verified(
+            &path,
+            &b"corrupted"[..],
+            None,
+            "bar 0.1.0",
+            None,
+            &mut |_, _| Ok(()),
+        )?;
+        assert!(path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack_crate_file_compression_ratio() -> anyhow::Result<()> {
+        use crate::crates::CrateError;
+        use flate2::{write::GzEncoder, Compression};
+
+        // Zeroes compress extremely well, like the contents of decompression bombs.
+        let zeroes = vec![0u8; 4 * 1024 * 1024];
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::best()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(zeroes.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "foo-0.1.0/zeroes", zeroes.as_slice())?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("foo-0.1.0.crate");
+        std::fs::write(&path, tar.into_inner()?.finish()?)?;
+
+        let err = unpack_crate_file(&path, &dir.path().join("bomb"), None, Some(100)).unwrap_err();
+        match err.downcast_ref::<CrateError>() {
+            Some(CrateError::CompressionRatioExceeded { ratio }) => assert_eq!(*ratio, 100),
+            _ => panic!("unexpected error: {:?}", err),
+        }
+
+        let dest = dir.path().join("unlimited");
+        unpack_crate_file(&path, &dest, None, None)?;
+        assert_eq!(
+            std::fs::metadata(dest.join("zeroes"))?.len(),
+            zeroes.len() as u64
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_verified_progress() -> anyhow::Result<()> {
+        let data = vec![0u8; DOWNLOAD_CHUNK_SIZE * 2 + 1];
+        let dir = tempfile::tempdir()?;
+
+        let mut reports = Vec::new();
+        let path = dir.path().join("foo-0.1.0.crate");
+        write_verified(
+            &path,
+            data.as_slice(),
+            None,
+            "foo 0.1.0",
+            Some(data.len() as u64),
+            &mut |downloaded, total| {
+                reports.push((downloaded, total));
+                Ok(())
+            },
+        )?;
+        assert_eq!(
+            reports.last(),
+            Some(&(data.len() as u64, Some(data.len() as u64)))
+        );
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // Returning an error from the callback aborts the download.
+        let path = dir.path().join("bar-0.1.0.crate");
+        let res = write_verified(
+            &path,
+            data.as_slice(),
+            None,
+            "bar 0.1.0",
+            None,
+            &mut |downloaded, _| {
+                if downloaded > DOWNLOAD_CHUNK_SIZE as u64 {
+                    anyhow::bail!("crate too big");
+                }
+                Ok(())
+            },
+        );
+        assert!(res.is_err());
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_version() {
+        let entry = |version: &str, yanked: bool| {
+            format!(
+                r#"{{"name":"foo","vers":"{}","deps":[],"cksum":"00","features":{{}},"yanked":{}}}"#,
+                version, yanked
+            )
+        };
+
+        let index = [
+            entry("0.9.0", false),
+            entry("0.10.0", false),
+            entry("1.0.0-beta.1", false),
+            entry("0.11.0", true),
+        ]
+        .join("\n");
+        assert_eq!(latest_version(&index).as_deref(), Some("0.10.0"));
+
+        let index = [entry("1.0.0-beta.1", false), entry("1.0.0-beta.2", false)].join("\n");
+        assert_eq!(latest_version(&index).as_deref(), Some("1.0.0-beta.2"));
+
+        assert_eq!(latest_version(&entry("0.1.0", true)), None);
+    }
+
+    #[test]
+    fn test_index_file_path() {
+        assert_eq!(index_file_path("a"), "1/a");
+        assert_eq!(index_file_path("ab"), "2/ab");
+        assert_eq!(index_file_path("abc"), "3/a/abc");
+        assert_eq!(index_file_path("Serde_JSON"), "se/rd/serde_json");
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_checksum_change() {
+        let old_index = concat!(
+            r#"{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aaaa","features":{},"yanked":false}"#,
+            "\n",
+            r#"{"name":"foo","vers":"0.2.0","deps":[],"cksum":"bbbb","features":{},"yanked":false}"#,
+        );
+        let new_index = old_index.replace("bbbb", "cccc");
+
+        assert_eq!(find_checksum(old_index, "0.1.0").as_deref(), Some("aaaa"));
+        assert_eq!(find_checksum(old_index, "0.3.0"), None);
+
+        let krate = RegistryCrate::new(Registry::CratesIo, "foo", "0.2.0");
+        assert_eq!(krate.cache_file_name("0.2.0", None), "foo-0.2.0.crate");
+
+        let old = krate.cache_file_name("0.2.0", find_checksum(old_index, "0.2.0").as_deref());
+        let new = krate.cache_file_name("0.2.0", find_checksum(&new_index, "0.2.0").as_deref());
+        assert_eq!(old, "foo-0.2.0-bbbb.crate");
+        assert_eq!(new, "foo-0.2.0-cccc.crate");
+    }
+
+    #[test]
+    fn test_remove_stale_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let krate = RegistryCrate::new(Registry::CratesIo, "foo", "0.2.0");
+        let old_checksum = "b".repeat(64);
+        let new_checksum = "c".repeat(64);
+
+        let old = dir
+            .path()
+            .join(krate.cache_file_name("0.2.0", Some(&old_checksum)));
+        let new = dir
+            .path()
+            .join(krate.cache_file_name("0.2.0", Some(&new_checksum)));
+        let unchecksummed = dir.path().join(krate.cache_file_name("0.2.0", None));
+        let other_version = dir.path().join(krate.cache_file_name("0.2.0-beta", None));
+        let other_checksummed = dir
+            .path()
+            .join(krate.cache_file_name("0.2.1", Some(&old_checksum)));
+        for path in [
+            &old,
+            &new,
+            &unchecksummed,
+            &other_version,
+            &other_checksummed,
+        ] {
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let mut files = krate.checksummed_files(dir.path(), "0.2.0").unwrap();
+        files.sort();
+        assert_eq!(files, vec![old.clone(), new.clone()]);
+
+        krate.remove_stale_files(dir.path(), "0.2.0", &new).unwrap();
+        assert!(!old.exists());
+        assert!(new.exists());
+        assert!(unchecksummed.exists());
+        assert!(other_version.exists());
+        assert!(other_checksummed.exists());
+
+        assert!(krate
+            .checksummed_files(&dir.path().join("missing"), "0.2.0")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_find_yanked_entry() {
+        let index = concat!(
+            r#"{"name":"foo","vers":"0.1.0","deps":[],"cksum":"aaaa","features":{},"yanked":true}"#,
+            "\n",
+            r#"{"name":"foo","vers":"0.2.0","deps":[],"cksum":"bbbb","features":{}}"#,
+        );
+        assert!(find_entry(index, "0.1.0").unwrap().yanked);
+        assert!(!find_entry(index, "0.2.0").unwrap().yanked);
+    }
+}
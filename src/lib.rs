@@ -16,6 +16,8 @@
 //! * **unstable-toolchain-ci**: allow fetching toolchains from rustc's CI artifacts storage. Support for
 //!   them is **incomplete** (not all methods might work), and there is **no stability guarantee**
 //!   when using them!
+//! * **metrics**: allow configuring a [`MetricsSink`](metrics/trait.MetricsSink.html) receiving
+//!   timings and counters about the operations performed by Rustwide.
 //!
 //! [crater]: https://github.com/rust-lang/crater
 //! [docsrs]: https://github.com/rust-lang/docs.rs
@@ -25,17 +27,33 @@ pub mod cmd;
 mod crates;
 mod inside_docker;
 pub mod logging;
+mod metadata;
+#[cfg(any(feature = "metrics", doc))]
+#[cfg_attr(docs_rs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
+#[cfg(not(any(feature = "metrics", doc)))]
+mod metrics;
 mod native;
 mod prepare;
+mod rustdoc;
+mod timings;
 pub mod toolchain;
 mod tools;
 mod utils;
 mod workspace;
 
-pub use crate::build::{Build, BuildBuilder, BuildDirectory};
-pub use crate::crates::{AlternativeRegistry, Crate};
+#[cfg(any(feature = "unstable", doc))]
+pub use crate::build::BuildStdFetch;
+pub use crate::build::{Build, BuildBuilder, BuildDirectory, BuildReport, BuildSkipped};
+pub use crate::crates::{
+    AlternativeRegistry, Crate, CrateError, CrateSourceKind, DependencyKind, Edition,
+    IndexDependency, IndexEntry,
+};
+pub use crate::metadata::{CargoMetadata, Package, Target};
 pub use crate::prepare::PrepareError;
+pub use crate::rustdoc::RustdocOptions;
+pub use crate::timings::UnitTiming;
 pub use crate::toolchain::Toolchain;
-pub use crate::workspace::{Workspace, WorkspaceBuilder};
+pub use crate::workspace::{HealthReport, Issue, Workspace, WorkspaceBuilder};
 
 pub(crate) static HOST_TARGET: &str = include_str!(concat!(env!("OUT_DIR"), "/target"));
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+/// Metadata of a crate's package, as reported by `cargo metadata --no-deps`.
+///
+/// The metadata is collected once while preparing the build, and it can be retrieved with
+/// [`Build::cargo_metadata`](struct.Build.html#method.cargo_metadata). It describes the crate's
+/// manifest before rustwide tweaks it, and only contains the subset of `cargo metadata`'s output
+/// rustwide guarantees to be stable.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CargoMetadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    workspace_root: PathBuf,
+}
+
+impl CargoMetadata {
+    pub(crate) fn parse(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Packages of the crate's workspace.
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    /// Packages that are members of the workspace.
+    pub fn workspace_members(&self) -> impl Iterator<Item = &Package> {
+        self.packages
+            .iter()
+            .filter(move |package| self.workspace_members.contains(&package.id))
+    }
+
+    /// Root directory of the workspace.
+    pub fn workspace_root(&self) -> &Path {
+        &self.workspace_root
+    }
+}
+
+/// Package of a crate's workspace, part of [`CargoMetadata`](struct.CargoMetadata.html).
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Package {
+    id: String,
+    name: String,
+    version: String,
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+    #[serde(default)]
+    rust_version: Option<String>,
+}
+
+impl Package {
+    /// Opaque identifier of the package.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Name of the package.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the package.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Path to the `Cargo.toml` of the package.
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// Targets (libraries, binaries, examples, tests...) of the package.
+    pub fn targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Minimum supported Rust version declared by the package, if any.
+    pub fn rust_version(&self) -> Option<&str> {
+        self.rust_version.as_deref()
+    }
+}
+
+/// Target of a [`Package`](struct.Package.html).
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Target {
+    name: String,
+    kind: Vec<String>,
+    #[serde(default)]
+    crate_types: Vec<String>,
+    src_path: PathBuf,
+}
+
+impl Target {
+    /// Name of the target.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Kinds of the target, for example `lib`, `bin`, `example` or `custom-build`.
+    pub fn kind(&self) -> &[String] {
+        &self.kind
+    }
+
+    /// Crate types of the target, for example `lib`, `rlib` or `bin`.
+    pub fn crate_types(&self) -> &[String] {
+        &self.crate_types
+    }
+
+    /// Path to the root source file of the target.
+    pub fn src_path(&self) -> &Path {
+        &self.src_path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CargoMetadata;
+    use std::path::Path;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {
+                "name": "foo",
+                "version": "0.1.0",
+                "id": "path+file:///source#foo@0.1.0",
+                "license": null,
+                "dependencies": [],
+                "targets": [
+                    {
+                        "kind": ["bin"],
+                        "crate_types": ["bin"],
+                        "name": "foo",
+                        "src_path": "/source/src/main.rs",
+                        "edition": "2021",
+                        "doc": true,
+                        "doctest": false,
+                        "test": true
+                    },
+                    {
+                        "kind": ["custom-build"],
+                        "crate_types": ["bin"],
+                        "name": "build-script-build",
+                        "src_path": "/source/build.rs",
+                        "edition": "2021",
+                        "doc": false,
+                        "doctest": false,
+                        "test": false
+                    }
+                ],
+                "features": {},
+                "manifest_path": "/source/Cargo.toml",
+                "rust_version": "1.70"
+            },
+            {
+                "name": "bar",
+                "version": "0.2.0",
+                "id": "path+file:///source/bar#0.2.0",
+                "targets": [],
+                "manifest_path": "/source/bar/Cargo.toml"
+            }
+        ],
+        "workspace_members": ["path+file:///source#foo@0.1.0"],
+        "workspace_default_members": ["path+file:///source#foo@0.1.0"],
+        "resolve": null,
+        "target_directory": "/target",
+        "version": 1,
+        "workspace_root": "/source",
+        "metadata": null
+    }"#;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let metadata = CargoMetadata::parse(METADATA)?;
+
+        assert_eq!(metadata.workspace_root(), Path::new("/source"));
+        assert_eq!(metadata.packages().len(), 2);
+        let members = metadata.workspace_members().collect::<Vec<_>>();
+        assert_eq!(members.len(), 1);
+
+        let foo = members[0];
+        assert_eq!(foo.name(), "foo");
+        assert_eq!(foo.version(), "0.1.0");
+        assert_eq!(foo.manifest_path(), Path::new("/source/Cargo.toml"));
+        assert_eq!(foo.rust_version(), Some("1.70"));
+        assert_eq!(foo.targets().len(), 2);
+        assert_eq!(foo.targets()[0].name(), "foo");
+        assert_eq!(foo.targets()[0].kind(), ["bin"]);
+        assert_eq!(foo.targets()[1].kind(), ["custom-build"]);
+        assert_eq!(foo.targets()[1].src_path(), Path::new("/source/build.rs"));
+
+        assert_eq!(metadata.packages()[1].rust_version(), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Metrics about the operations performed by rustwide.
+
+// Without the feature the public API is unreachable, and only the no-op sink is used.
+#![cfg_attr(not(feature = "metrics"), allow(dead_code))]
+
+use std::time::{Duration, Instant};
+
+/// Receiver of the metrics emitted by rustwide, configured with
+/// [`WorkspaceBuilder::metrics_sink`](../struct.WorkspaceBuilder.html#method.metrics_sink).
+///
+/// The sink can forward the metrics to any monitoring system (for example OpenTelemetry or
+/// Prometheus). Both methods do nothing by default, so implementations only need to override the
+/// ones they're interested in. The methods are called synchronously while rustwide performs the
+/// operations, so they should return quickly.
+pub trait MetricsSink: Send + Sync {
+    /// Record how long an operation took. The duration is recorded even if the operation failed.
+    fn record_duration(&self, operation: Operation, duration: Duration) {
+        let _ = (operation, duration);
+    }
+
+    /// Increment a counter by one.
+    fn increment_counter(&self, counter: Counter) {
+        let _ = counter;
+    }
+}
+
+/// Operation whose duration is recorded by a [`MetricsSink`](trait.MetricsSink.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Fetching a crate with [`Crate::fetch`](../struct.Crate.html#method.fetch).
+    CrateFetch,
+    /// Preparing the source code of a crate before a build.
+    Prepare,
+    /// Running the function provided to [`BuildBuilder::run`](../struct.BuildBuilder.html#method.run).
+    Build,
+    /// Creating a sandbox container.
+    ContainerCreate,
+    /// Deleting a sandbox container.
+    ContainerDelete,
+}
+
+impl Operation {
+    /// Name of the operation, suitable as a metric name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::CrateFetch => "crate_fetch",
+            Operation::Prepare => "prepare",
+            Operation::Build => "build",
+            Operation::ContainerCreate => "container_create",
+            Operation::ContainerDelete => "container_delete",
+        }
+    }
+}
+
+/// Counter incremented by a [`MetricsSink`](trait.MetricsSink.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Counter {
+    /// A registry crate was found in the local cache when fetching it.
+    CacheHit,
+    /// A registry crate was missing from the local cache when fetching it, and was downloaded.
+    CacheMiss,
+}
+
+impl Counter {
+    /// Name of the counter, suitable as a metric name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::CacheHit => "cache_hit",
+            Counter::CacheMiss => "cache_miss",
+        }
+    }
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for std::sync::Arc<T> {
+    fn record_duration(&self, operation: Operation, duration: Duration) {
+        (**self).record_duration(operation, duration);
+    }
+
+    fn increment_counter(&self, counter: Counter) {
+        (**self).increment_counter(counter);
+    }
+}
+
+/// Sink used when no other sink is configured, discarding all the metrics.
+pub(crate) struct NoopMetricsSink;
+
+impl MetricsSink for NoopMetricsSink {}
+
+/// Call the function, recording how long it took in the sink.
+pub(crate) fn timed<T>(sink: &dyn MetricsSink, operation: Operation, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+    sink.record_duration(operation, start.elapsed());
+    res
+}
+
+/// Await the future, recording how long it took in the sink.
+pub(crate) async fn timed_async<T>(
+    sink: &dyn MetricsSink,
+    operation: Operation,
+    f: impl std::future::Future<Output = T>,
+) -> T {
+    let start = Instant::now();
+    let res = f.await;
+    sink.record_duration(operation, start.elapsed());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{timed, Counter, MetricsSink, Operation};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct MockSink {
+        durations: Mutex<Vec<(Operation, Duration)>>,
+        counters: Mutex<Vec<Counter>>,
+    }
+
+    impl MetricsSink for MockSink {
+        fn record_duration(&self, operation: Operation, duration: Duration) {
+            self.durations.lock().unwrap().push((operation, duration));
+        }
+
+        fn increment_counter(&self, counter: Counter) {
+            self.counters.lock().unwrap().push(counter);
+        }
+    }
+
+    #[test]
+    fn test_timed() {
+        let sink = MockSink::default();
+
+        let res = timed(&sink, Operation::Prepare, || {
+            std::thread::sleep(Duration::from_millis(50));
+            42
+        });
+        assert_eq!(res, 42);
+        sink.increment_counter(Counter::CacheMiss);
+
+        let durations = sink.durations.lock().unwrap();
+        assert_eq!(durations.len(), 1);
+        assert_eq!(durations[0].0, Operation::Prepare);
+        assert!(durations[0].1 >= Duration::from_millis(50));
+        assert_eq!(*sink.counters.lock().unwrap(), [Counter::CacheMiss]);
+    }
+}
@@ -1,8 +1,10 @@
+use crate::build::{timed_phase, BuildReport};
 use crate::cmd::{Command, CommandError};
-use crate::{build::CratePatch, Crate, Toolchain, Workspace};
+use crate::metadata::CargoMetadata;
+use crate::{build::CratePatch, Crate, Edition, Toolchain, Workspace};
 use anyhow::Context as _;
-use log::info;
-use std::path::Path;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
 use toml::{
     value::{Array, Table},
     Value,
@@ -12,8 +14,12 @@ pub(crate) struct Prepare<'a> {
     workspace: &'a Workspace,
     toolchain: &'a Toolchain,
     krate: &'a Crate,
-    source_dir: &'a Path,
+    /// Directory the source code is copied to.
+    root_dir: &'a Path,
+    /// Directory containing the crate, inside the root directory.
+    source_dir: PathBuf,
     patches: Vec<CratePatch>,
+    skip_fetch_deps: bool,
 }
 
 impl<'a> Prepare<'a> {
@@ -23,63 +29,110 @@ impl<'a> Prepare<'a> {
         krate: &'a Crate,
         source_dir: &'a Path,
         patches: Vec<CratePatch>,
+        skip_fetch_deps: bool,
     ) -> Self {
         Self {
             workspace,
             toolchain,
             krate,
-            source_dir,
+            root_dir: source_dir,
+            source_dir: match krate.subdir() {
+                Some(subdir) => source_dir.join(subdir),
+                None => source_dir.into(),
+            },
             patches,
+            skip_fetch_deps,
         }
     }
 
-    pub(crate) fn prepare(&mut self) -> anyhow::Result<()> {
-        self.krate.copy_source_to(self.workspace, self.source_dir)?;
-        self.validate_manifest()?;
-        self.remove_override_files()?;
-        self.tweak_toml()?;
-        self.capture_lockfile()?;
-        self.fetch_deps()?;
-
-        Ok(())
+    /// Prepare the crate for the build, returning its metadata. The time spent in each phase is
+    /// stored in the report.
+    pub(crate) fn prepare(&mut self, report: &mut BuildReport) -> anyhow::Result<CargoMetadata> {
+        timed_phase(&mut report.copy_source, || {
+            if let Some(subdir) = self.krate.subdir() {
+                crate::build::validate_source_path(subdir)?;
+            }
+            self.krate.copy_source_to(self.workspace, self.root_dir)
+        })?;
+        let metadata = timed_phase(&mut report.validate_manifest, || self.validate_manifest())?;
+        timed_phase(&mut report.tweak, || -> anyhow::Result<()> {
+            self.remove_override_files()?;
+            self.configure_registry()?;
+            self.tweak_toml()
+        })?;
+        timed_phase(&mut report.lockfile, || self.capture_lockfile())?;
+        timed_phase(&mut report.fetch_deps, || self.fetch_deps())?;
+
+        Ok(metadata)
     }
 
-    fn validate_manifest(&self) -> anyhow::Result<()> {
+    /// Validate the manifest by running `cargo metadata` on it. Its output is returned to avoid
+    /// running it again in other parts of rustwide.
+    fn validate_manifest(&self) -> anyhow::Result<CargoMetadata> {
         info!(
             "validating manifest of {} on toolchain {}",
             self.krate, self.toolchain
         );
 
         // Skip crates missing a Cargo.toml
-        if !self.source_dir.join("Cargo.toml").is_file() {
+        let manifest_path = self.source_dir.join("Cargo.toml");
+        if !manifest_path.is_file() {
             return Err(PrepareError::MissingCargoToml.into());
         }
+        // Manifests that can't be read are reported as invalid by `cargo metadata` below.
+        if let Ok(manifest) = std::fs::read_to_string(&manifest_path) {
+            if let Ok(Some(Edition::Unknown(edition))) = crate::crates::manifest_edition(&manifest)
+            {
+                warn!(
+                    "{} uses the unknown edition {}, it might not be supported by {}",
+                    self.krate, edition, self.toolchain
+                );
+            }
+        }
 
         let res = Command::new(self.workspace, self.toolchain.cargo())
-            .args(&["metadata", "--manifest-path", "Cargo.toml", "--no-deps"])
-            .cd(self.source_dir)
+            .args(&[
+                "metadata",
+                "--manifest-path",
+                "Cargo.toml",
+                "--no-deps",
+                "--format-version",
+                "1",
+            ])
+            .cd(&self.source_dir)
             .log_output(false)
-            .run();
-        if res.is_err() {
-            return Err(PrepareError::InvalidCargoTomlSyntax.into());
+            .run_capture();
+        let metadata = match res {
+            Ok(output) => CargoMetadata::parse(&output.stdout_lines().join("\n"))?,
+            Err(_) => return Err(PrepareError::InvalidCargoTomlSyntax.into()),
+        };
+        // Only virtual manifests can have a workspace without members.
+        if metadata.workspace_members().next().is_none() {
+            return Err(PrepareError::VirtualManifest.into());
         }
-
-        Ok(())
+        Ok(metadata)
     }
 
     fn remove_override_files(&self) -> anyhow::Result<()> {
-        let paths = [
-            &Path::new(".cargo").join("config"),
-            &Path::new(".cargo").join("config.toml"),
-            Path::new("rust-toolchain"),
-            Path::new("rust-toolchain.toml"),
-        ];
-        for path in &paths {
-            let path = self.source_dir.join(path);
-            if path.exists() {
-                crate::utils::remove_file(&path)?;
-                info!("removed {}", path.display());
-            }
+        // Cargo also loads the configuration of the parent directories of the crate.
+        remove_files(self.root_dir, self.workspace.override_files_to_remove())?;
+        remove_files(&self.source_dir, self.workspace.override_files_to_remove())
+    }
+
+    /// Let cargo know about the alternative registry the crate comes from, if any. This is done
+    /// after removing the crate's own cargo configuration, which is not trusted.
+    fn configure_registry(&self) -> anyhow::Result<()> {
+        if let Some(alt) = self.krate.alternative_registry() {
+            let dir = self.source_dir.join(".cargo");
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(
+                dir.join("config.toml"),
+                toml::to_string(&alt.cargo_config())?,
+            )?;
+            info!(
+                "configured cargo to use the alternative registry {}",
+                alt.index()
+            );
         }
         Ok(())
     }
@@ -108,6 +161,9 @@ impl<'a> Prepare<'a> {
             "--manifest-path",
             "Cargo.toml",
         ]);
+        for (key, value) in self.registry_env() {
+            cmd = cmd.env(key, value);
+        }
         if !self.workspace.fetch_registry_index_during_builds() {
             cmd = cmd
                 .args(&["-Zno-index-update"])
@@ -115,7 +171,7 @@ impl<'a> Prepare<'a> {
         }
 
         match cmd
-            .cd(self.source_dir)
+            .cd(&self.source_dir)
             .process_lines(&mut |line, _| {
                 if line.contains("failed to select a version for the requirement") {
                     yanked_deps = true;
@@ -128,10 +184,10 @@ impl<'a> Prepare<'a> {
             .run_capture()
         {
             Ok(_) => Ok(()),
-            Err(CommandError::ExecutionFailed { status: _, stderr }) if yanked_deps => {
+            Err(CommandError::ExecutionFailed { stderr, .. }) if yanked_deps => {
                 Err(PrepareError::YankedDependencies(stderr).into())
             }
-            Err(CommandError::ExecutionFailed { status: _, stderr }) if missing_deps => {
+            Err(CommandError::ExecutionFailed { stderr, .. }) if missing_deps => {
                 Err(PrepareError::MissingDependencies(stderr).into())
             }
             Err(err) => Err(err.into()),
@@ -139,46 +195,137 @@ impl<'a> Prepare<'a> {
     }
 
     fn fetch_deps(&mut self) -> anyhow::Result<()> {
-        fetch_deps(self.workspace, self.toolchain, self.source_dir, &[])
+        if self.skip_fetch_deps {
+            info!("skipping fetching the dependencies of {}", self.krate);
+            return Ok(());
+        }
+        fetch_deps(
+            self.workspace,
+            self.toolchain,
+            &self.source_dir,
+            None,
+            &self.registry_env(),
+        )
+    }
+
+    /// Environment variables with the credentials of the alternative registry the crate comes
+    /// from, only provided to the commands run outside the sandbox.
+    fn registry_env(&self) -> Vec<(String, String)> {
+        self.krate
+            .alternative_registry()
+            .map(|alt| alt.cargo_env())
+            .unwrap_or_default()
     }
 }
 
-pub(crate) fn fetch_deps(
+#[cfg(any(feature = "unstable", doc))]
+pub(crate) fn fetch_build_std_deps(
     workspace: &Workspace,
     toolchain: &Toolchain,
     source_dir: &Path,
-    fetch_build_std_targets: &[&str],
+    targets: &[&str],
+    install_rust_src: bool,
+) -> anyhow::Result<crate::build::BuildStdFetch> {
+    if let Some(dist) = toolchain.as_dist() {
+        if !dist.name().starts_with("nightly") {
+            warn!(
+                "toolchain {} is not a nightly toolchain, relying on RUSTC_BOOTSTRAP=1 to use \
+                 -Zbuild-std",
+                toolchain
+            );
+        }
+    }
+
+    // build-std has to have the source for libstd's dependencies available.
+    if install_rust_src {
+        toolchain.add_component(workspace, "rust-src")?;
+    } else if !toolchain
+        .installed_components(workspace)?
+        .iter()
+        .any(|component| component.starts_with("rust-src"))
+    {
+        return Err(PrepareError::MissingRustSrc.into());
+    }
+
+    let mut result = crate::build::BuildStdFetch {
+        fetched: Vec::new(),
+        failed: Vec::new(),
+    };
+    for target in targets {
+        match fetch_deps(workspace, toolchain, source_dir, Some(target), &[]) {
+            Ok(()) => result.fetched.push(target.to_string()),
+            Err(err) => {
+                warn!(
+                    "failed to fetch build-std dependencies for {}: {}",
+                    target, err
+                );
+                result.failed.push((target.to_string(), err));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Remove the files at the provided paths relative to the source directory, if they exist.
+fn remove_files(source_dir: &Path, paths: &[PathBuf]) -> anyhow::Result<()> {
+    for path in paths {
+        let path = source_dir.join(path);
+        if path.exists() {
+            crate::utils::remove_file(&path)?;
+            info!("removed {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn fetch_deps(
+    workspace: &Workspace,
+    toolchain: &Toolchain,
+    source_dir: &Path,
+    build_std_target: Option<&str>,
+    env: &[(String, String)],
 ) -> anyhow::Result<()> {
     let mut missing_deps = false;
+    let mut build_std_unsupported = false;
     let mut cmd = Command::new(workspace, toolchain.cargo())
         .args(&["fetch", "--manifest-path", "Cargo.toml"])
         .cd(source_dir);
-    // Pass `-Zbuild-std` in case a build in the sandbox wants to use it;
-    // build-std has to have the source for libstd's dependencies available.
-    if !fetch_build_std_targets.is_empty() {
-        toolchain.add_component(workspace, "rust-src")?;
-        cmd = cmd.args(&["-Zbuild-std"]).env("RUSTC_BOOTSTRAP", "1");
+    for (key, value) in env {
+        cmd = cmd.env(key, value);
     }
-    for target in fetch_build_std_targets {
-        cmd = cmd.args(&["--target", target]);
+    // Pass `-Zbuild-std` in case a build in the sandbox wants to use it.
+    if let Some(target) = build_std_target {
+        cmd = cmd
+            .args(&["-Zbuild-std", "--target", target])
+            .env("RUSTC_BOOTSTRAP", "1");
     }
 
     match cmd
         .process_lines(&mut |line, _| {
             if line.contains("failed to load source for dependency") {
                 missing_deps = true;
+            } else if is_build_std_unsupported(line) {
+                build_std_unsupported = true;
             }
         })
         .run_capture()
     {
         Ok(_) => Ok(()),
-        Err(CommandError::ExecutionFailed { status: _, stderr }) if missing_deps => {
+        Err(CommandError::ExecutionFailed { stderr, .. }) if missing_deps => {
             Err(PrepareError::MissingDependencies(stderr).into())
         }
+        Err(CommandError::ExecutionFailed { stderr, .. }) if build_std_unsupported => {
+            Err(PrepareError::BuildStdUnsupported(stderr).into())
+        }
         Err(err) => Err(err.into()),
     }
 }
 
+fn is_build_std_unsupported(line: &str) -> bool {
+    (line.contains("unknown `-Z` flag specified") && line.contains("build-std"))
+        || line.contains("is only accepted on the nightly channel")
+}
+
 struct TomlTweaker<'a> {
     krate: &'a Crate,
     table: Table,
@@ -390,11 +537,37 @@ pub enum PrepareError {
     /// Some of the dependencies do not exist anymore.
     #[error("the crate depends on missing dependencies: \n\n{0}")]
     MissingDependencies(String),
+    /// The toolchain doesn't support `-Zbuild-std`, so the dependencies of the standard library
+    /// couldn't be fetched.
+    #[error("the toolchain doesn't support -Zbuild-std: \n\n{0}")]
+    BuildStdUnsupported(String),
+    /// The `rust-src` component needed by `-Zbuild-std` is not installed for the toolchain.
+    #[error("the rust-src component is not installed")]
+    MissingRustSrc,
+    /// The crate's Cargo.toml is a virtual manifest (only containing a `[workspace]` section)
+    /// without any workspace member, so there is no package to build.
+    #[error("the crate's Cargo.toml is a virtual manifest without members")]
+    VirtualManifest,
+    /// The package selected with
+    /// [`BuildBuilder::package`](struct.BuildBuilder.html#method.package) is not a member of the
+    /// crate's workspace.
+    #[error("package {0} is not a member of the crate's workspace")]
+    MissingPackage(String),
+    /// The path of a file added with
+    /// [`BuildBuilder::add_file`](struct.BuildBuilder.html#method.add_file) or removed with
+    /// [`BuildBuilder::remove_file`](struct.BuildBuilder.html#method.remove_file) is absolute or
+    /// contains `..` components or symbolic links, and could point outside of the crate's source.
+    #[error("the path {0} is outside of the crate's source")]
+    InvalidFilePath(PathBuf),
+    /// The validation command configured with
+    /// [`BuildBuilder::validate_with`](struct.BuildBuilder.html#method.validate_with) failed.
+    #[error("the validation of the crate failed: \n\n{0}")]
+    ValidationFailed(String),
 }
 
 #[cfg(test)]
 mod tests {
-    use super::TomlTweaker;
+    use super::{is_build_std_unsupported, remove_files, TomlTweaker};
     use crate::build::{CratePatch, GitCratePatch, PathCratePatch};
     use crate::crates::Crate;
     use toml::toml;
@@ -511,4 +684,42 @@ mod tests {
 
         assert_eq!(tweaker.table, result);
     }
+
+    #[test]
+    fn test_remove_files() -> anyhow::Result<()> {
+        use std::path::{Path, PathBuf};
+
+        let source = tempfile::tempdir()?;
+        std::fs::create_dir_all(source.path().join(".cargo"))?;
+        for file in &["deny.toml", ".cargo/audit.toml", "rust-toolchain"] {
+            std::fs::write(source.path().join(file), b"")?;
+        }
+
+        let paths = [
+            PathBuf::from("deny.toml"),
+            Path::new(".cargo").join("audit.toml"),
+            Path::new(".cargo").join("config.toml"),
+        ];
+        remove_files(source.path(), &paths)?;
+        assert!(!source.path().join("deny.toml").exists());
+        assert!(!source.path().join(".cargo").join("audit.toml").exists());
+        // Files not in the list are kept.
+        assert!(source.path().join("rust-toolchain").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_std_unsupported() {
+        assert!(is_build_std_unsupported(
+            "error: unknown `-Z` flag specified: build-std"
+        ));
+        assert!(is_build_std_unsupported(
+            "error: the `-Z` flag is only accepted on the nightly channel of Cargo, but this is \
+             the `stable` channel"
+        ));
+        assert!(!is_build_std_unsupported(
+            "error: failed to load source for dependency `foo`"
+        ));
+    }
 }
@@ -0,0 +1,141 @@
+/// Options of the documentation built by
+/// [`Build::rustdoc_build`](struct.Build.html#method.rustdoc_build), following the recipe used by
+/// [docs.rs].
+///
+/// Only the documentation of the crate itself is built, passing `--no-deps` to cargo. Some of the
+/// options rely on unstable features of cargo and rustdoc, and they require a nightly toolchain:
+/// building the documentation with them on other toolchains fails.
+///
+/// [docs.rs]: https://docs.rs
+#[derive(Debug, Clone, Default)]
+pub struct RustdocOptions {
+    target: Option<String>,
+    document_private_items: bool,
+    rustdoc_map: bool,
+    extern_html_root_urls: Vec<(String, String)>,
+    rustdoc_args: Vec<String>,
+}
+
+impl RustdocOptions {
+    /// Create the default options, building the documentation of the public items for the host
+    /// target.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the documentation for the provided target instead of the host target. The
+    /// documentation is then placed in a directory named after the target.
+    pub fn target(mut self, target: &str) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Document private items too, passing `--document-private-items` to rustdoc. This is
+    /// available on all toolchains.
+    pub fn document_private_items(mut self, enable: bool) -> Self {
+        self.document_private_items = enable;
+        self
+    }
+
+    /// Link to the documentation of dependencies hosted on docs.rs instead of documenting them
+    /// locally, passing `-Zrustdoc-map` to cargo. This requires a nightly toolchain.
+    pub fn rustdoc_map(mut self, enable: bool) -> Self {
+        self.rustdoc_map = enable;
+        self
+    }
+
+    /// Link the items of the crate named `krate` to the documentation hosted at `url`, passing
+    /// `--extern-html-root-url` to rustdoc. This requires a nightly toolchain.
+    pub fn extern_html_root_url(mut self, krate: &str, url: &str) -> Self {
+        self.extern_html_root_urls.push((krate.into(), url.into()));
+        self
+    }
+
+    /// Pass additional arguments to rustdoc, for example `--cfg docsrs`.
+    pub fn rustdoc_args(mut self, args: &[&str]) -> Self {
+        self.rustdoc_args
+            .extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    pub(crate) fn target_name(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Arguments passed to `cargo doc`.
+    pub(crate) fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec!["doc".to_string(), "--no-deps".to_string()];
+        args.extend(self.cargo_flags());
+        args
+    }
+
+    /// Flags passed to both `cargo doc` and `cargo rustdoc`.
+    pub(crate) fn cargo_flags(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.rustdoc_map {
+            args.push("-Zrustdoc-map".into());
+        }
+        if let Some(target) = &self.target {
+            args.push("--target".into());
+            args.push(target.clone());
+        }
+        args
+    }
+
+    /// Flags passed to rustdoc through `CARGO_ENCODED_RUSTDOCFLAGS`.
+    pub(crate) fn rustdoc_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.document_private_items {
+            flags.push("--document-private-items".into());
+        }
+        if !self.extern_html_root_urls.is_empty() {
+            flags.push("-Zunstable-options".into());
+            for (krate, url) in &self.extern_html_root_urls {
+                flags.push("--extern-html-root-url".into());
+                flags.push(format!("{}={}", krate, url));
+            }
+        }
+        flags.extend(self.rustdoc_args.iter().cloned());
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustdocOptions;
+
+    #[test]
+    fn test_rustdoc_options() {
+        let options = RustdocOptions::new();
+        assert_eq!(options.cargo_args(), ["doc", "--no-deps"]);
+        assert!(options.rustdoc_flags().is_empty());
+
+        let options = RustdocOptions::new()
+            .target("x86_64-unknown-linux-gnu")
+            .document_private_items(true)
+            .rustdoc_map(true)
+            .extern_html_root_url("serde", "https://docs.rs/serde/1.0.0")
+            .rustdoc_args(&["--cfg", "docsrs"]);
+        assert_eq!(
+            options.cargo_args(),
+            [
+                "doc",
+                "--no-deps",
+                "-Zrustdoc-map",
+                "--target",
+                "x86_64-unknown-linux-gnu"
+            ]
+        );
+        assert_eq!(
+            options.rustdoc_flags(),
+            [
+                "--document-private-items",
+                "-Zunstable-options",
+                "--extern-html-root-url",
+                "serde=https://docs.rs/serde/1.0.0",
+                "--cfg",
+                "docsrs"
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::metadata::Target;
+use std::time::Duration;
+
+/// Time spent compiling a unit of a build (a target of a package, compiled in a specific mode),
+/// as reported by cargo's `--timings=json` flag.
+///
+/// The timings are returned by
+/// [`Build::run_with_timings`](struct.Build.html#method.run_with_timings).
+#[derive(Debug, Clone)]
+pub struct UnitTiming {
+    package_id: String,
+    target: Target,
+    mode: String,
+    duration: Duration,
+    rmeta_time: Option<Duration>,
+}
+
+impl UnitTiming {
+    /// Opaque identifier of the package the unit belongs to.
+    pub fn package_id(&self) -> &str {
+        &self.package_id
+    }
+
+    /// Target of the package compiled by the unit.
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Mode the unit was compiled in, for example `build`, `check`, `test`, `doc` or
+    /// `run-custom-build`.
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    /// Time spent compiling the unit.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Time spent before the metadata of the unit was generated, allowing the units depending on
+    /// it to start compiling. Only reported for units generating metadata.
+    pub fn rmeta_time(&self) -> Option<Duration> {
+        self.rmeta_time
+    }
+}
+
+/// Parse the `timing-info` messages printed by cargo with `--timings=json`, ignoring the other
+/// lines of its output.
+pub(crate) fn parse_timings<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<Vec<UnitTiming>> {
+    #[derive(serde::Deserialize)]
+    struct Reason {
+        reason: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct TimingInfo {
+        package_id: String,
+        target: Target,
+        mode: String,
+        duration: f64,
+        #[serde(default)]
+        rmeta_time: Option<f64>,
+    }
+
+    let mut timings = Vec::new();
+    for line in lines {
+        match serde_json::from_str::<Reason>(line) {
+            Ok(Reason { reason }) if reason == "timing-info" => {}
+            _ => continue,
+        }
+        let info: TimingInfo = serde_json::from_str(line)?;
+        timings.push(UnitTiming {
+            package_id: info.package_id,
+            target: info.target,
+            mode: info.mode,
+            duration: Duration::try_from_secs_f64(info.duration)?,
+            rmeta_time: info
+                .rmeta_time
+                .map(Duration::try_from_secs_f64)
+                .transpose()?,
+        });
+    }
+    Ok(timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_timings;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_timings() -> anyhow::Result<()> {
+        let output = [
+            "Hello, world!",
+            r#"{"reason":"compiler-artifact","package_id":"path+file:///source#foo@0.1.0"}"#,
+            concat!(
+                r#"{"reason":"timing-info","package_id":"path+file:///source#foo@0.1.0","#,
+                r#""target":{"kind":["lib"],"crate_types":["lib"],"name":"foo","#,
+                r#""src_path":"/source/src/lib.rs","edition":"2021","doc":true,"#,
+                r#""doctest":true,"test":true},"mode":"build","duration":1.5,"rmeta_time":0.25}"#,
+            ),
+            concat!(
+                r#"{"reason":"timing-info","package_id":"path+file:///source#foo@0.1.0","#,
+                r#""target":{"kind":["custom-build"],"crate_types":["bin"],"#,
+                r#""name":"build-script-build","src_path":"/source/build.rs","edition":"2021","#,
+                r#""doc":false,"doctest":false,"test":false},"mode":"run-custom-build","#,
+                r#""duration":0.5}"#,
+            ),
+        ];
+
+        let timings = parse_timings(output.iter().copied())?;
+        assert_eq!(timings.len(), 2);
+
+        assert_eq!(timings[0].package_id(), "path+file:///source#foo@0.1.0");
+        assert_eq!(timings[0].target().name(), "foo");
+        assert_eq!(timings[0].mode(), "build");
+        assert_eq!(timings[0].duration(), Duration::from_millis(1500));
+        assert_eq!(timings[0].rmeta_time(), Some(Duration::from_millis(250)));
+
+        assert_eq!(timings[1].target().kind(), ["custom-build"]);
+        assert_eq!(timings[1].mode(), "run-custom-build");
+        assert_eq!(timings[1].duration(), Duration::from_millis(500));
+        assert_eq!(timings[1].rmeta_time(), None);
+
+        let invalid = r#"{"reason":"timing-info","package_id":"foo","duration":1.0}"#;
+        assert!(parse_timings([invalid]).is_err());
+
+        Ok(())
+    }
+}
@@ -8,10 +8,14 @@ use crate::Workspace;
 use anyhow::{anyhow, Context as _};
 use log::info;
 use std::borrow::Cow;
+use std::env::consts::EXE_SUFFIX;
 use std::path::Path;
 
 pub(crate) const MAIN_TOOLCHAIN_NAME: &str = "stable";
 
+#[cfg(feature = "unstable-toolchain-ci")]
+static GITHUB_API_BASE_URL: &str = "https://api.github.com";
+
 /// Error caused by methods in the `toolchain` moodule.
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
@@ -26,6 +30,11 @@ pub enum ToolchainError {
     /// use with it.
     #[error("unsupported operation on this toolchain")]
     UnsupportedOperation,
+    /// The toolchain can't be installed, as it doesn't exist or it's not available anymore (for
+    /// example because the name is wrong, or the nightly was purged). Retrying the installation
+    /// will not help.
+    #[error("the toolchain is not available")]
+    NotAvailable,
 }
 
 /// Metadata of a dist toolchain. See [`Toolchain`](struct.Toolchain.html) to create and get it.
@@ -40,9 +49,14 @@ impl DistToolchain {
         self.name.as_ref()
     }
 
-    fn init(&self, workspace: &Workspace) -> anyhow::Result<()> {
+    fn init(
+        &self,
+        workspace: &Workspace,
+        progress: &mut dyn FnMut(InstallProgress),
+    ) -> anyhow::Result<()> {
         info!("installing toolchain {}", self.name());
-        Command::new(workspace, &RUSTUP)
+        let mut not_available = false;
+        let result = Command::new(workspace, &RUSTUP)
             .args(&[
                 "toolchain",
                 "install",
@@ -50,10 +64,73 @@ impl DistToolchain {
                 "--profile",
                 workspace.rustup_profile(),
             ])
-            .run()
-            .with_context(|| format!("unable to install toolchain {} via rustup", self.name()))?;
+            .process_lines(&mut |line, _| {
+                if is_toolchain_not_available(line) {
+                    not_available = true;
+                }
+                if let Some(event) = InstallProgress::parse(line) {
+                    progress(event);
+                }
+            })
+            .run();
 
-        Ok(())
+        match result {
+            Ok(()) => Ok(()),
+            Err(_) if not_available => Err(ToolchainError::NotAvailable.into()),
+            Err(err) => Err(anyhow!(err).context(format!(
+                "unable to install toolchain {} via rustup",
+                self.name()
+            ))),
+        }
+    }
+}
+
+fn is_toolchain_not_available(line: &str) -> bool {
+    line.starts_with("error: no release found")
+        || (line.starts_with("error: ") && line.contains("is not installable"))
+        || line.starts_with("error: invalid toolchain name")
+}
+
+/// Progress of a toolchain installation, reported by
+/// [`Toolchain::install_with_progress`](struct.Toolchain.html#method.install_with_progress).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InstallProgress {
+    /// The download of a component started.
+    Downloading {
+        /// Name of the component being downloaded.
+        component: String,
+    },
+    /// The installation of a downloaded component started.
+    Installing {
+        /// Name of the component being installed.
+        component: String,
+    },
+    /// Percentage of the current download that was completed.
+    Percent(u8),
+}
+
+impl InstallProgress {
+    fn parse(line: &str) -> Option<Self> {
+        let component = |rest: &str| rest.trim().trim_matches('\'').to_string();
+        if let Some(rest) = line.strip_prefix("info: downloading component ") {
+            Some(InstallProgress::Downloading {
+                component: component(rest),
+            })
+        } else if let Some(rest) = line.strip_prefix("info: installing component ") {
+            Some(InstallProgress::Installing {
+                component: component(rest),
+            })
+        } else {
+            // Progress bars look like `3.5 MiB / 14.8 MiB ( 24 %) 3.5 MiB/s in 3s ETA: 3s`.
+            let start = line.find('(')?;
+            let end = start + line[start..].find("%)")?;
+            line[start + 1..end]
+                .trim()
+                .parse()
+                .ok()
+                .map(InstallProgress::Percent)
+        }
     }
 }
 
@@ -96,7 +173,11 @@ impl std::fmt::Display for RustupThing {
 }
 
 /// Metadata of a CI toolchain. See [`Toolchain`](struct.Toolchain.html) to create and get it.
-#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+///
+/// The components are not part of the identity of the toolchain: CI toolchains built from the
+/// same commit are equal even if they install different components, as rustup installs them in
+/// the same toolchain.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 #[cfg(any(feature = "unstable-toolchain-ci", doc))]
 #[cfg_attr(docs_rs, doc(cfg(feature = "unstable-toolchain-ci")))]
 pub struct CiToolchain {
@@ -105,6 +186,40 @@ pub struct CiToolchain {
     /// Whether you want to download a standard or "alt" build. "alt" builds have extra
     /// compiler assertions enabled.
     alt: bool,
+    /// Components installed alongside the compiler.
+    #[serde(
+        default = "default_ci_components",
+        skip_serializing_if = "is_default_ci_components"
+    )]
+    components: Vec<String>,
+}
+
+#[cfg(any(feature = "unstable-toolchain-ci", doc))]
+fn default_ci_components() -> Vec<String> {
+    vec!["cargo".into()]
+}
+
+#[cfg(any(feature = "unstable-toolchain-ci", doc))]
+fn is_default_ci_components(components: &Vec<String>) -> bool {
+    *components == default_ci_components()
+}
+
+#[cfg(any(feature = "unstable-toolchain-ci", doc))]
+impl PartialEq for CiToolchain {
+    fn eq(&self, other: &Self) -> bool {
+        self.sha == other.sha && self.alt == other.alt
+    }
+}
+
+#[cfg(any(feature = "unstable-toolchain-ci", doc))]
+impl Eq for CiToolchain {}
+
+#[cfg(any(feature = "unstable-toolchain-ci", doc))]
+impl std::hash::Hash for CiToolchain {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.sha.hash(state);
+        self.alt.hash(state);
+    }
 }
 
 #[cfg(any(feature = "unstable-toolchain-ci", doc))]
@@ -123,20 +238,40 @@ impl CiToolchain {
         self.alt
     }
 
-    fn init(&self, workspace: &Workspace) -> anyhow::Result<()> {
+    /// Get the components installed alongside the compiler.
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+
+    fn install_args(&self) -> Vec<&str> {
+        let mut args = vec![self.sha()];
+        for component in &self.components {
+            args.extend(["-c", component]);
+        }
         if self.alt {
-            info!("installing toolchain {}-alt", self.sha);
-        } else {
-            info!("installing toolchain {}", self.sha);
+            args.push("--alt");
         }
+        args
+    }
 
-        let mut args = vec![self.sha(), "-c", "cargo"];
+    fn init(
+        &self,
+        workspace: &Workspace,
+        progress: &mut dyn FnMut(InstallProgress),
+    ) -> anyhow::Result<()> {
         if self.alt {
-            args.push("--alt");
+            info!("installing toolchain {}-alt", self.sha);
+        } else {
+            info!("installing toolchain {}", self.sha);
         }
 
         Command::new(workspace, &RUSTUP_TOOLCHAIN_INSTALL_MASTER)
-            .args(&args)
+            .args(&self.install_args())
+            .process_lines(&mut |line, _| {
+                if let Some(event) = InstallProgress::parse(line) {
+                    progress(event);
+                }
+            })
             .run()
             .with_context(|| {
                 format!(
@@ -149,6 +284,19 @@ impl CiToolchain {
     }
 }
 
+/// Kind of a [`Toolchain`](struct.Toolchain.html), returned by
+/// [`Toolchain::kind`](struct.Toolchain.html#method.kind).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ToolchainKind {
+    /// A toolchain distributed through rustup.
+    Dist,
+    /// A toolchain built by the CI of the rust-lang/rust repository.
+    #[cfg(any(feature = "unstable-toolchain-ci", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "unstable-toolchain-ci")))]
+    CI,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
 #[serde(rename_all = "kebab-case", tag = "type")]
 enum ToolchainInner {
@@ -229,10 +377,68 @@ impl Toolchain {
             inner: ToolchainInner::CI(CiToolchain {
                 sha: sha.to_string(),
                 alt,
+                components: default_ci_components(),
             }),
         }
     }
 
+    /// Create a new CI toolchain, installing the provided components alongside the compiler.
+    ///
+    /// [`Toolchain::ci`](#method.ci) only installs `cargo`, which should be included in the list
+    /// too if you need it. Not every component is built by CI: `cargo`, `rust-src`, `rustc-dev`,
+    /// `clippy`, `rustfmt` and `llvm-tools` are generally available, but there is no guarantee.
+    ///
+    /// **There is no availability or stability guarantee for these builds!**
+    #[cfg(any(feature = "unstable-toolchain-ci", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "unstable-toolchain-ci")))]
+    pub fn ci_with_components(sha: &str, alt: bool, components: &[&str]) -> Self {
+        Toolchain {
+            inner: ToolchainInner::CI(CiToolchain {
+                sha: sha.to_string(),
+                alt,
+                components: components.iter().map(|c| c.to_string()).collect(),
+            }),
+        }
+    }
+
+    /// Return the kind of this toolchain.
+    pub fn kind(&self) -> ToolchainKind {
+        match &self.inner {
+            ToolchainInner::Dist(_) => ToolchainKind::Dist,
+            #[cfg(feature = "unstable-toolchain-ci")]
+            ToolchainInner::CI(_) => ToolchainKind::CI,
+        }
+    }
+
+    /// Check whether this toolchain is a dist toolchain.
+    pub fn is_dist(&self) -> bool {
+        self.kind() == ToolchainKind::Dist
+    }
+
+    /// Check whether this toolchain is a CI toolchain.
+    #[cfg(any(feature = "unstable-toolchain-ci", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "unstable-toolchain-ci")))]
+    pub fn is_ci(&self) -> bool {
+        self.kind() == ToolchainKind::CI
+    }
+
+    /// Create a new CI toolchain from the number of a merged [rust-lang/rust][repo] PR.
+    ///
+    /// The SHA of the PR's merge commit will be resolved through the GitHub API, which requires
+    /// network access. Unauthenticated requests to the GitHub API are rate limited (at the time of
+    /// writing to 60 requests per hour per IP address): if you need to resolve a lot of PRs
+    /// consider caching the SHAs or calling [`Toolchain::ci`](#method.ci) directly.
+    ///
+    /// **There is no availability or stability guarantee for these builds!**
+    ///
+    /// [repo]: https://github.com/rust-lang/rust
+    #[cfg(any(feature = "unstable-toolchain-ci", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "unstable-toolchain-ci")))]
+    pub fn ci_from_pr(workspace: &Workspace, pr: u64, alt: bool) -> anyhow::Result<Self> {
+        let sha = pr_merge_commit(workspace.http_client(), GITHUB_API_BASE_URL, pr)?;
+        Ok(Self::ci(&sha, alt))
+    }
+
     /// If this toolchain is a dist toolchain, return its metadata.
     #[allow(irrefutable_let_patterns)]
     pub fn as_dist(&self) -> Option<&DistToolchain> {
@@ -256,10 +462,24 @@ impl Toolchain {
 
     /// Download and install the toolchain.
     pub fn install(&self, workspace: &Workspace) -> anyhow::Result<()> {
+        self.install_with_progress(workspace, |_| {})
+    }
+
+    /// Download and install the toolchain, calling the provided function each time the
+    /// installation makes progress.
+    ///
+    /// The progress is parsed from the output of the installer, so not every event might be
+    /// reported: for example download percentages are only available when the installer shows a
+    /// progress bar.
+    pub fn install_with_progress(
+        &self,
+        workspace: &Workspace,
+        mut progress: impl FnMut(InstallProgress),
+    ) -> anyhow::Result<()> {
         match &self.inner {
-            ToolchainInner::Dist(dist) => dist.init(workspace)?,
+            ToolchainInner::Dist(dist) => dist.init(workspace, &mut progress)?,
             #[cfg(feature = "unstable-toolchain-ci")]
-            ToolchainInner::CI(ci) => ci.init(workspace)?,
+            ToolchainInner::CI(ci) => ci.init(workspace, &mut progress)?,
         }
 
         Ok(())
@@ -303,6 +523,14 @@ impl Toolchain {
         self.list_rustup_things(workspace, RustupThing::Target)
     }
 
+    /// Return a list of installed components for this toolchain.
+    ///
+    /// If the toolchain is not installed an error will be returned. This is only supported for
+    /// dist toolchains.
+    pub fn installed_components(&self, workspace: &Workspace) -> anyhow::Result<Vec<String>> {
+        self.list_rustup_things(workspace, RustupThing::Component)
+    }
+
     fn change_rustup_thing(
         &self,
         workspace: &Workspace,
@@ -488,6 +716,49 @@ impl Toolchain {
         }
     }
 
+    /// Return the `rustc` binary inside the toolchain's installation directory, bypassing the
+    /// rustup proxy. This method is intended to be used with
+    /// [`rustwide::cmd::Command`](cmd/struct.Command.html) outside of the sandbox.
+    ///
+    /// Running [`rustc`](#method.rustc) goes through rustup, which only recognizes the toolchains
+    /// it installed itself: commands like `rustc --version` fail for CI toolchains, as rustup
+    /// considers them not installed. Running the binary directly works for all the toolchains
+    /// installed in the workspace.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use rustwide::{WorkspaceBuilder, Toolchain, cmd::Command};
+    /// # use std::error::Error;
+    /// # fn main() -> anyhow::Result<(), Box<dyn Error>> {
+    /// # let workspace = WorkspaceBuilder::new("".as_ref(), "").init()?;
+    /// let toolchain = Toolchain::dist("beta");
+    /// Command::new(&workspace, toolchain.rustc_direct(&workspace)?)
+    ///     .args(&["--version"])
+    ///     .run()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rustc_direct(&self, workspace: &Workspace) -> anyhow::Result<Binary> {
+        let toolchains = workspace.rustup_home().join("toolchains");
+        // Toolchains installed from the dist server have the host target in their directory name.
+        let name = self.rustup_name();
+        let dir = [
+            name.clone(),
+            format!("{}-{}", name, workspace.host_target()),
+        ]
+        .iter()
+        .map(|dir| toolchains.join(dir))
+        .find(|dir| dir.is_dir())
+        .ok_or_else(|| anyhow!("toolchain {} is not installed", self))?;
+
+        let binary = dir.join("bin").join(format!("rustc{}", EXE_SUFFIX));
+        if !binary.is_file() {
+            anyhow::bail!("toolchain {} doesn't contain rustc", self);
+        }
+        Ok(Binary::Global(crate::utils::normalize_path(&binary)))
+    }
+
     fn rustup_name(&self) -> String {
         match &self.inner {
             ToolchainInner::Dist(dist) => dist.name.to_string(),
@@ -520,6 +791,31 @@ impl Runnable for RustupProxy<'_> {
     }
 }
 
+#[cfg(feature = "unstable-toolchain-ci")]
+fn pr_merge_commit(client: &attohttpc::Session, api_base: &str, pr: u64) -> anyhow::Result<String> {
+    #[derive(serde::Deserialize)]
+    struct PullRequest {
+        merged: bool,
+        merge_commit_sha: Option<String>,
+    }
+
+    info!("resolving the merge commit of rust-lang/rust#{}", pr);
+    let url = format!("{}/repos/rust-lang/rust/pulls/{}", api_base, pr);
+    let resp = client
+        .get(&url)
+        .header(http::header::ACCEPT, "application/vnd.github+json")
+        .send()?
+        .error_for_status()
+        .with_context(|| format!("failed to fetch rust-lang/rust#{} from the GitHub API", pr))?
+        .text()?;
+    let resp: PullRequest = serde_json::from_str(&resp)?;
+
+    match resp.merge_commit_sha {
+        Some(sha) if resp.merged => Ok(sha),
+        _ => Err(anyhow!("rust-lang/rust#{} is not merged", pr)),
+    }
+}
+
 pub(crate) fn list_installed_toolchains(rustup_home: &Path) -> anyhow::Result<Vec<Toolchain>> {
     let update_hashes = rustup_home.join("update-hashes");
 
@@ -552,7 +848,9 @@ pub(crate) fn list_installed_toolchains(rustup_home: &Path) -> anyhow::Result<Ve
 
 #[cfg(test)]
 mod tests {
-    use super::Toolchain;
+    use super::{is_toolchain_not_available, InstallProgress, Toolchain, ToolchainKind};
+    #[cfg(feature = "unstable-toolchain-ci")]
+    use anyhow::anyhow;
 
     #[test]
     fn test_dist_serde_repr() -> anyhow::Result<()> {
@@ -578,9 +876,162 @@ mod tests {
             serde_json::from_str(CI_ALT)?
         );
 
+        // The default components are not serialized, keeping the representation unchanged.
+        assert_eq!(
+            serde_json::to_value(Toolchain::ci("0000000", false))?,
+            serde_json::from_str::<serde_json::Value>(CI_NORMAL)?
+        );
+        let with_components = Toolchain::ci_with_components("0000000", false, &["rust-src"]);
+        let deserialized: Toolchain =
+            serde_json::from_str(&serde_json::to_string(&with_components)?)?;
+        assert_eq!(
+            deserialized.as_ci().unwrap().components(),
+            ["rust-src".to_string()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-toolchain-ci")]
+    fn test_ci_components_identity() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |toolchain: &Toolchain| {
+            let mut hasher = DefaultHasher::new();
+            toolchain.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let default = Toolchain::ci("0000000", false);
+        let with_components = Toolchain::ci_with_components("0000000", false, &["rust-src"]);
+        assert_eq!(default, with_components);
+        assert_eq!(hash(&default), hash(&with_components));
+        assert_ne!(default, Toolchain::ci("0000000", true));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-toolchain-ci")]
+    fn test_ci_install_args() {
+        let install_args = |toolchain: &Toolchain| -> Vec<String> {
+            let ci = toolchain.as_ci().unwrap();
+            ci.install_args().into_iter().map(String::from).collect()
+        };
+
+        assert_eq!(
+            install_args(&Toolchain::ci("0000000", false)),
+            ["0000000", "-c", "cargo"]
+        );
+        assert_eq!(
+            install_args(&Toolchain::ci_with_components(
+                "0000000",
+                true,
+                &["cargo", "rust-src"]
+            )),
+            ["0000000", "-c", "cargo", "-c", "rust-src", "--alt"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-toolchain-ci")]
+    fn test_pr_merge_commit() -> anyhow::Result<()> {
+        let server = tiny_http::Server::http("localhost:0").map_err(|e| anyhow!(e.to_string()))?;
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            while let Ok(req) = server.recv() {
+                let body = match req.url() {
+                    "/repos/rust-lang/rust/pulls/1" => {
+                        r#"{"merged": true, "merge_commit_sha": "0000000"}"#
+                    }
+                    "/repos/rust-lang/rust/pulls/2" => {
+                        r#"{"merged": false, "merge_commit_sha": "1111111"}"#
+                    }
+                    _ => {
+                        let _ = req.respond(tiny_http::Response::empty(404));
+                        continue;
+                    }
+                };
+                let _ = req.respond(tiny_http::Response::from_string(body));
+            }
+        });
+
+        let http = attohttpc::Session::new();
+        let base = format!("http://localhost:{}", port);
+        assert_eq!(super::pr_merge_commit(&http, &base, 1)?, "0000000");
+        assert!(super::pr_merge_commit(&http, &base, 2).is_err());
+        assert!(super::pr_merge_commit(&http, &base, 3).is_err());
+
         Ok(())
     }
 
+    #[test]
+    fn test_kind() {
+        let dist = Toolchain::dist("stable");
+        assert_eq!(dist.kind(), ToolchainKind::Dist);
+        assert!(dist.is_dist());
+
+        #[cfg(feature = "unstable-toolchain-ci")]
+        {
+            assert!(!dist.is_ci());
+
+            let ci = Toolchain::ci("0000000", false);
+            assert_eq!(ci.kind(), ToolchainKind::CI);
+            assert!(ci.is_ci());
+            assert!(!ci.is_dist());
+        }
+    }
+
+    #[test]
+    fn test_parse_install_progress() {
+        const OUTPUT: &str = "\
+info: syncing channel updates for 'stable-x86_64-unknown-linux-gnu'
+info: latest update on 2024-11-28, rust version 1.83.0 (90b35a623 2024-11-26)
+info: downloading component 'cargo'
+  3.5 MiB /   8.6 MiB ( 41 %)   3.5 MiB/s in  1s ETA:  1s
+  8.6 MiB /   8.6 MiB (100 %)   4.1 MiB/s in  2s ETA:  0s
+info: installing component 'cargo'
+";
+
+        let mut events = Vec::new();
+        for line in OUTPUT.lines() {
+            events.extend(InstallProgress::parse(line));
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                InstallProgress::Downloading {
+                    component: "cargo".into()
+                },
+                InstallProgress::Percent(41),
+                InstallProgress::Percent(100),
+                InstallProgress::Installing {
+                    component: "cargo".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toolchain_not_available() {
+        assert!(is_toolchain_not_available(
+            "error: no release found for 'nightly-2015-01-01'"
+        ));
+        assert!(is_toolchain_not_available(
+            "error: toolchain 'nightly-2015-01-01-x86_64-unknown-linux-gnu' is not installable"
+        ));
+        assert!(is_toolchain_not_available(
+            "error: invalid toolchain name: 'stabel'"
+        ));
+        assert!(!is_toolchain_not_available(
+            "error: could not download file from 'https://static.rust-lang.org'"
+        ));
+        assert!(!is_toolchain_not_available(
+            "info: downloading component 'cargo'"
+        ));
+    }
+
     #[test]
     fn test_list_installed() -> anyhow::Result<()> {
         const DIST_NAME: &str = "stable-x86_64-unknown-linux-gnu";
@@ -1,18 +1,158 @@
 use crate::cmd::{Binary, Command, Runnable};
 use crate::tools::Tool;
 use crate::{Toolchain, Workspace};
-use std::path::PathBuf;
+use anyhow::Context as _;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::env::consts::EXE_SUFFIX;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// File in the cargo home recording the versions of the tools installed from prebuilt binaries,
+/// as they're missing from cargo's own list of installed crates.
+const PREBUILT_RECORD: &str = ".rustwide-prebuilt.toml";
 
 pub(crate) struct BinaryCrate {
-    pub(super) crate_name: &'static str,
-    pub(super) binary: &'static str,
+    pub(super) crate_name: Cow<'static, str>,
+    pub(super) binary: Cow<'static, str>,
+    /// Version passed to `cargo install --version`. If it's `None` the latest version of the
+    /// crate will be installed.
+    pub(super) version: Option<Cow<'static, str>>,
+    /// Whether to pass `--locked` to `cargo install`. Disable it only for crates that don't
+    /// publish a lockfile.
+    pub(super) locked: bool,
+    /// URL of a prebuilt binary of the pinned version, used instead of `cargo install` when
+    /// prebuilt tools are enabled. The `{version}`, `{target}` and `{exe}` placeholders are
+    /// replaced with the pinned version, the host target and the executable suffix. The sha256
+    /// of the binary must be published at the same URL with the `.sha256` suffix.
+    pub(super) prebuilt_url: Option<&'static str>,
     pub(super) cargo_subcommand: Option<&'static str>,
 }
 
 impl BinaryCrate {
+    /// Create a tool registered by the user of the library, installing the latest version of it.
+    pub(crate) fn custom(crate_name: &str, binary: &str) -> Self {
+        BinaryCrate {
+            crate_name: Cow::Owned(crate_name.into()),
+            binary: Cow::Owned(binary.into()),
+            version: None,
+            locked: true,
+            prebuilt_url: None,
+            cargo_subcommand: None,
+        }
+    }
+
     pub(crate) fn binary_path(&self, workspace: &Workspace) -> PathBuf {
         Tool::binary_path(self, workspace)
     }
+
+    /// Return the version of this crate installed from a prebuilt binary, or recorded in cargo's
+    /// list of installed crates.
+    fn installed_version(&self, cargo_home: &Path) -> anyhow::Result<Option<String>> {
+        if let Some(toml::Value::String(version)) = read_prebuilt_record(cargo_home)?
+            .get(&*self.crate_name)
+            .cloned()
+        {
+            return Ok(Some(version));
+        }
+
+        let path = cargo_home.join(".crates.toml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        // The keys of the table are formatted as `{name} {version} ({source})`.
+        let content: toml::Table = toml::from_str(&std::fs::read_to_string(&path)?)?;
+        let Some(toml::Value::Table(installed)) = content.get("v1") else {
+            return Ok(None);
+        };
+        Ok(installed.keys().find_map(|key| {
+            let mut parts = key.split(' ');
+            if parts.next() == Some(&*self.crate_name) {
+                parts.next().map(String::from)
+            } else {
+                None
+            }
+        }))
+    }
+
+    /// Return whether updating the tool is needed. Tools without a pinned version always need to
+    /// be updated, as the latest version could have changed.
+    fn needs_update(&self, cargo_home: &Path) -> anyhow::Result<bool> {
+        match &self.version {
+            Some(version) => Ok(self.installed_version(cargo_home)?.as_deref() != Some(version)),
+            None => Ok(true),
+        }
+    }
+
+    fn prebuilt_url(&self, host_target: &str) -> Option<String> {
+        let template = self.prebuilt_url?;
+        let version = self.version.as_ref()?;
+        Some(
+            template
+                .replace("{version}", version)
+                .replace("{target}", host_target)
+                .replace("{exe}", EXE_SUFFIX),
+        )
+    }
+
+    fn install_prebuilt(&self, workspace: &Workspace, url: &str) -> anyhow::Result<()> {
+        info!("downloading prebuilt {} from {}", self.binary, url);
+        download_prebuilt(workspace.http_client(), url, &self.binary_path(workspace))?;
+        self.record_prebuilt(&workspace.cargo_home(), self.version.as_deref())
+    }
+
+    /// Record the version installed from a prebuilt binary, or remove it from the record when the
+    /// tool is installed with `cargo install` (passing `None`).
+    fn record_prebuilt(&self, cargo_home: &Path, version: Option<&str>) -> anyhow::Result<()> {
+        let mut record = read_prebuilt_record(cargo_home)?;
+        let changed = match version {
+            Some(version) => {
+                record.insert(self.crate_name.to_string(), version.into()) != Some(version.into())
+            }
+            None => record.remove(&*self.crate_name).is_some(),
+        };
+        if changed {
+            std::fs::create_dir_all(cargo_home)?;
+            std::fs::write(cargo_home.join(PREBUILT_RECORD), toml::to_string(&record)?)?;
+        }
+        Ok(())
+    }
+
+    /// Install the tool from the prebuilt binary at `url`, if any, falling back to
+    /// `cargo install` when there is no prebuilt binary or it can't be installed.
+    fn install_with_fallback(
+        &self,
+        url: Option<&str>,
+        install_prebuilt: impl FnOnce(&str) -> anyhow::Result<()>,
+        cargo_install: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if let Some(url) = url {
+            match install_prebuilt(url) {
+                Ok(()) => return Ok(()),
+                Err(err) => warn!(
+                    "failed to download prebuilt {}, falling back to cargo install: {:?}",
+                    self.binary, err
+                ),
+            }
+        }
+        cargo_install()
+    }
+
+    fn install_args(&self, fast_install: bool) -> Vec<&str> {
+        let mut args = vec!["install", &self.crate_name];
+        if let Some(version) = &self.version {
+            args.extend(["--version", version]);
+        }
+        if self.locked {
+            args.push("--locked");
+        }
+        if fast_install {
+            args.push("--debug");
+        }
+        args
+    }
 }
 
 impl Runnable for BinaryCrate {
@@ -20,7 +160,7 @@ impl Runnable for BinaryCrate {
         Binary::ManagedByRustwide(if self.cargo_subcommand.is_some() {
             "cargo".into()
         } else {
-            self.binary.into()
+            self.binary.as_ref().into()
         })
     }
 
@@ -33,8 +173,8 @@ impl Runnable for BinaryCrate {
 }
 
 impl Tool for BinaryCrate {
-    fn name(&self) -> &'static str {
-        self.binary
+    fn name(&self) -> &str {
+        &self.binary
     }
 
     fn is_installed(&self, workspace: &Workspace) -> anyhow::Result<bool> {
@@ -47,17 +187,277 @@ impl Tool for BinaryCrate {
     }
 
     fn install(&self, workspace: &Workspace, fast_install: bool) -> anyhow::Result<()> {
-        let mut cmd = Command::new(workspace, Toolchain::MAIN.cargo())
-            .args(&["install", self.crate_name])
-            .timeout(None);
-        if fast_install {
-            cmd = cmd.args(&["--debug"]);
-        }
-        cmd.run()?;
-        Ok(())
+        let url = if workspace.prebuilt_tools() {
+            self.prebuilt_url(workspace.host_target())
+        } else {
+            None
+        };
+        self.install_with_fallback(
+            url.as_deref(),
+            |url| self.install_prebuilt(workspace, url),
+            || {
+                Command::new(workspace, Toolchain::MAIN.cargo())
+                    .args(&self.install_args(fast_install))
+                    .timeout(None)
+                    .run()?;
+                self.record_prebuilt(&workspace.cargo_home(), None)
+            },
+        )
     }
 
     fn update(&self, workspace: &Workspace, fast_install: bool) -> anyhow::Result<()> {
+        if !self.needs_update(&workspace.cargo_home())? {
+            info!("tool {} is already up to date", self.binary);
+            return Ok(());
+        }
         self.install(workspace, fast_install)
     }
 }
+
+fn read_prebuilt_record(cargo_home: &Path) -> anyhow::Result<toml::Table> {
+    let path = cargo_home.join(PREBUILT_RECORD);
+    if path.is_file() {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    } else {
+        Ok(toml::Table::new())
+    }
+}
+
+/// Download the binary at `url` to `dest`, verifying it against the sha256 published at the same
+/// URL with the `.sha256` suffix before making it executable.
+fn download_prebuilt(client: &attohttpc::Session, url: &str, dest: &Path) -> anyhow::Result<()> {
+    let expected = client
+        .get(format!("{}.sha256", url))
+        .send()?
+        .error_for_status()?
+        .text()?;
+    // The file can contain the name of the binary after the checksum, like `sha256sum` outputs.
+    let expected = expected
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty checksum published for {}", url))?
+        .to_string();
+
+    let parent = dest.parent().expect("binary path without a parent");
+    std::fs::create_dir_all(parent)?;
+
+    // Download to a temporary file first to avoid leaving a broken binary around.
+    let mut resp = client.get(url).send()?.error_for_status()?;
+    let mut tempfile = tempfile::NamedTempFile::new_in(parent)?;
+    let file = tempfile.as_file_mut();
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let len = resp.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        file.write_all(&buf[..len])?;
+    }
+    file.flush()?;
+
+    let actual = format!("{:x}", hasher.finalize());
+    if !actual.eq_ignore_ascii_case(&expected) {
+        anyhow::bail!(
+            "checksum mismatch for {}: expected {}, got {}",
+            url,
+            expected,
+            actual
+        );
+    }
+
+    crate::native::make_executable(tempfile.path())?;
+    tempfile
+        .persist(dest)
+        .with_context(|| format!("failed to install {}", dest.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{download_prebuilt, BinaryCrate};
+    use std::borrow::Cow;
+
+    const TOOL: BinaryCrate = BinaryCrate {
+        crate_name: Cow::Borrowed("foo-cli"),
+        binary: Cow::Borrowed("foo"),
+        version: Some(Cow::Borrowed("1.2.3")),
+        locked: true,
+        prebuilt_url: Some("https://example.com/{version}/foo-{target}{exe}"),
+        cargo_subcommand: None,
+    };
+
+    #[test]
+    fn test_install_args() {
+        assert_eq!(
+            TOOL.install_args(false),
+            ["install", "foo-cli", "--version", "1.2.3", "--locked"]
+        );
+        assert_eq!(
+            TOOL.install_args(true),
+            [
+                "install",
+                "foo-cli",
+                "--version",
+                "1.2.3",
+                "--locked",
+                "--debug"
+            ]
+        );
+
+        let unlocked = BinaryCrate {
+            locked: false,
+            ..TOOL
+        };
+        assert_eq!(
+            unlocked.install_args(false),
+            ["install", "foo-cli", "--version", "1.2.3"]
+        );
+    }
+
+    #[test]
+    fn test_needs_update() -> anyhow::Result<()> {
+        let cargo_home = tempfile::tempdir()?;
+        let crates_toml = cargo_home.path().join(".crates.toml");
+
+        // Nothing was installed with cargo yet
+        assert!(TOOL.needs_update(cargo_home.path())?);
+
+        std::fs::write(
+            &crates_toml,
+            r#"
+                [v1]
+                "foo-cli 1.2.3 (registry+https://github.com/rust-lang/crates.io-index)" = ["foo"]
+                "other 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)" = ["other"]
+            "#,
+        )?;
+        assert_eq!(
+            TOOL.installed_version(cargo_home.path())?.as_deref(),
+            Some("1.2.3")
+        );
+        assert!(!TOOL.needs_update(cargo_home.path())?);
+        assert!(BinaryCrate::custom("foo-cli", "foo").needs_update(cargo_home.path())?);
+
+        std::fs::write(
+            &crates_toml,
+            r#"
+                [v1]
+                "foo-cli 1.2.2 (registry+https://github.com/rust-lang/crates.io-index)" = ["foo"]
+            "#,
+        )?;
+        assert!(TOOL.needs_update(cargo_home.path())?);
+
+        // Versions installed from prebuilt binaries are recorded separately.
+        TOOL.record_prebuilt(cargo_home.path(), Some("1.2.3"))?;
+        assert!(!TOOL.needs_update(cargo_home.path())?);
+        TOOL.record_prebuilt(cargo_home.path(), None)?;
+        assert!(TOOL.needs_update(cargo_home.path())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_with_fallback() -> anyhow::Result<()> {
+        let mut installed_with_cargo = false;
+        TOOL.install_with_fallback(
+            Some("https://example.com/foo"),
+            |_| anyhow::bail!("download failed"),
+            || {
+                installed_with_cargo = true;
+                Ok(())
+            },
+        )?;
+        assert!(installed_with_cargo);
+
+        let mut installed_with_cargo = false;
+        TOOL.install_with_fallback(
+            Some("https://example.com/foo"),
+            |_| Ok(()),
+            || {
+                installed_with_cargo = true;
+                Ok(())
+            },
+        )?;
+        assert!(!installed_with_cargo);
+
+        let mut installed_with_cargo = false;
+        TOOL.install_with_fallback(
+            None,
+            |_| panic!("no prebuilt binary"),
+            || {
+                installed_with_cargo = true;
+                Ok(())
+            },
+        )?;
+        assert!(installed_with_cargo);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_prebuilt() -> anyhow::Result<()> {
+        // sha256 of "hello"
+        const CHECKSUM: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        let server = tiny_http::Server::http("localhost:0").unwrap();
+        let base = format!("http://{}", server.server_addr());
+        std::thread::spawn(move || {
+            while let Ok(req) = server.recv() {
+                let body = match req.url() {
+                    "/good" | "/bad" => "hello".to_string(),
+                    "/good.sha256" => format!("{}  good\n", CHECKSUM),
+                    "/bad.sha256" => "0".repeat(64),
+                    _ => {
+                        let _ = req.respond(tiny_http::Response::empty(404));
+                        continue;
+                    }
+                };
+                let _ = req.respond(tiny_http::Response::from_string(body));
+            }
+        });
+
+        let client = attohttpc::Session::new();
+        let dir = tempfile::tempdir()?;
+
+        let good = dir.path().join("good");
+        download_prebuilt(&client, &format!("{}/good", base), &good)?;
+        assert_eq!(std::fs::read(&good)?, b"hello");
+        assert!(crate::native::is_executable(&good)?);
+
+        let bad = dir.path().join("bad");
+        assert!(download_prebuilt(&client, &format!("{}/bad", base), &bad).is_err());
+        assert!(!bad.exists());
+
+        let missing = dir.path().join("missing");
+        assert!(download_prebuilt(&client, &format!("{}/missing", base), &missing).is_err());
+        assert!(!missing.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prebuilt_url() {
+        assert_eq!(
+            TOOL.prebuilt_url("aarch64-unknown-linux-gnu").as_deref(),
+            Some(
+                format!(
+                    "https://example.com/1.2.3/foo-aarch64-unknown-linux-gnu{}",
+                    std::env::consts::EXE_SUFFIX
+                )
+                .as_str()
+            )
+        );
+        assert!(BinaryCrate::custom("foo-cli", "foo")
+            .prebuilt_url(crate::HOST_TARGET)
+            .is_none());
+    }
+
+    #[test]
+    fn test_custom_install_args() {
+        assert_eq!(
+            BinaryCrate::custom("bar-cli", "bar").install_args(false),
+            ["install", "bar-cli", "--locked"]
+        );
+    }
+}
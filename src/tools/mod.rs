@@ -1,26 +1,42 @@
 mod binary_crates;
 mod rustup;
 
+pub(crate) use binary_crates::BinaryCrate;
+
+use crate::cmd::{Command, CommandError};
 use crate::workspace::Workspace;
 use anyhow::bail;
-use binary_crates::BinaryCrate;
-use log::info;
+use log::{info, warn};
 use rustup::Rustup;
+use std::borrow::Cow;
 use std::env::consts::EXE_SUFFIX;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub(crate) static RUSTUP: Rustup = Rustup;
 
+// The versions of the tools installed with `cargo install` are pinned, to prevent a new release of
+// one of them from changing rustwide's behavior unexpectedly. Bump them manually when needed.
+
 #[cfg(feature = "unstable-toolchain-ci")]
 pub(crate) static RUSTUP_TOOLCHAIN_INSTALL_MASTER: BinaryCrate = BinaryCrate {
-    crate_name: "rustup-toolchain-install-master",
-    binary: "rustup-toolchain-install-master",
+    crate_name: Cow::Borrowed("rustup-toolchain-install-master"),
+    binary: Cow::Borrowed("rustup-toolchain-install-master"),
+    version: Some(Cow::Borrowed("1.7.3")),
+    locked: true,
+    prebuilt_url: Some(
+        "https://github.com/kennytm/rustup-toolchain-install-master/releases/download/\
+         v{version}/rustup-toolchain-install-master-{target}{exe}",
+    ),
     cargo_subcommand: None,
 };
 
 pub(crate) static GIT_CREDENTIAL_NULL: BinaryCrate = BinaryCrate {
-    crate_name: "git-credential-null",
-    binary: "git-credential-null",
+    crate_name: Cow::Borrowed("git-credential-null"),
+    binary: Cow::Borrowed("git-credential-null"),
+    version: Some(Cow::Borrowed("1.0.1")),
+    locked: true,
+    prebuilt_url: None,
     cargo_subcommand: None,
 };
 
@@ -32,7 +48,7 @@ static INSTALLABLE_TOOLS: &[&dyn Tool] = &[
 ];
 
 trait Tool: Send + Sync {
-    fn name(&self) -> &'static str;
+    fn name(&self) -> &str;
     fn is_installed(&self, workspace: &Workspace) -> anyhow::Result<bool>;
     fn install(&self, workspace: &Workspace, fast_install: bool) -> anyhow::Result<()>;
     fn update(&self, workspace: &Workspace, fast_install: bool) -> anyhow::Result<()>;
@@ -46,8 +62,108 @@ trait Tool: Send + Sync {
     }
 }
 
+fn all_tools(workspace: &Workspace) -> impl Iterator<Item = &dyn Tool> {
+    let custom_tools = workspace
+        .custom_tools()
+        .iter()
+        .map(|tool| tool as &dyn Tool);
+    INSTALLABLE_TOOLS.iter().copied().chain(custom_tools)
+}
+
+pub(crate) fn installed_binary_path(workspace: &Workspace, name: &str) -> Option<PathBuf> {
+    let tool = all_tools(workspace).find(|tool| tool.name() == name)?;
+    match tool.is_installed(workspace) {
+        Ok(true) => Some(tool.binary_path(workspace)),
+        Ok(false) => None,
+        Err(err) => {
+            warn!(
+                "failed to check whether tool {} is installed: {}",
+                name, err
+            );
+            None
+        }
+    }
+}
+
+/// Return the names of the tools that are not installed in the workspace. Tools that can't be
+/// checked are considered missing.
+pub(crate) fn missing_tools(workspace: &Workspace) -> Vec<String> {
+    all_tools(workspace)
+        .filter(|tool| match tool.is_installed(workspace) {
+            Ok(installed) => !installed,
+            Err(err) => {
+                warn!(
+                    "failed to check whether tool {} is installed: {}",
+                    tool.name(),
+                    err
+                );
+                true
+            }
+        })
+        .map(|tool| tool.name().to_string())
+        .collect()
+}
+
+pub(crate) fn is_rustup_executable(workspace: &Workspace) -> anyhow::Result<bool> {
+    RUSTUP.is_installed(workspace)
+}
+
+/// Return the names of the tools (except rustup) that can't be executed, for example because
+/// they're missing or their binary is corrupted.
+pub(crate) fn unrunnable_tools(workspace: &Workspace) -> Vec<String> {
+    all_tools(workspace)
+        .filter(|tool| tool.name() != Tool::name(&RUSTUP))
+        .filter(|tool| !is_runnable(workspace, &tool.binary_path(workspace)))
+        .map(|tool| tool.name().to_string())
+        .collect()
+}
+
+fn is_runnable(workspace: &Workspace, binary: &Path) -> bool {
+    if !binary.is_file() {
+        return false;
+    }
+
+    // Not all tools support `--version`: only failing to start the process is considered an
+    // error, while the exit status is ignored.
+    let res = Command::new(workspace, binary.to_string_lossy().into_owned())
+        .args(&["--version"])
+        .stdin(Vec::new())
+        .timeout(Some(Duration::from_secs(60)))
+        .log_output(false)
+        .log_command(false)
+        .run();
+    match res {
+        Ok(()) | Err(CommandError::ExecutionFailed { .. }) => true,
+        Err(err) => {
+            warn!("failed to run {}: {}", binary.display(), err);
+            false
+        }
+    }
+}
+
+/// Remove the binary of a tool and install it again.
+pub(crate) fn reinstall(workspace: &Workspace, name: &str) -> anyhow::Result<()> {
+    let tool = match all_tools(workspace).find(|tool| tool.name() == name) {
+        Some(tool) => tool,
+        None => bail!("unknown tool {}", name),
+    };
+
+    let binary = tool.binary_path(workspace);
+    if binary.exists() {
+        std::fs::remove_file(&binary)?;
+    }
+
+    info!("reinstalling tool {}", name);
+    tool.install(workspace, false)?;
+    if !tool.is_installed(workspace)? {
+        bail!("tool {} is still missing after install", name);
+    }
+
+    Ok(())
+}
+
 pub(crate) fn install(workspace: &Workspace, fast_install: bool) -> anyhow::Result<()> {
-    for tool in INSTALLABLE_TOOLS {
+    for tool in all_tools(workspace) {
         if tool.is_installed(workspace)? {
             info!("tool {} is installed, trying to update it", tool.name());
             tool.update(workspace, fast_install)?;
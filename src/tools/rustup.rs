@@ -2,16 +2,42 @@ use crate::cmd::{Binary, Command, Runnable};
 use crate::toolchain::MAIN_TOOLCHAIN_NAME;
 use crate::tools::{Tool, RUSTUP};
 use crate::workspace::Workspace;
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use std::env::consts::EXE_SUFFIX;
 use std::fs::{self, File};
 use std::io;
+use std::path::Path;
 use tempfile::tempdir;
 
 static RUSTUP_BASE_URL: &str = "https://static.rust-lang.org/rustup/dist";
 
 pub(crate) struct Rustup;
 
+fn rustup_init_url(base_url: &str, host_target: &str) -> String {
+    format!("{}/{}/rustup-init{}", base_url, host_target, EXE_SUFFIX)
+}
+
+fn download_rustup_init(
+    client: &attohttpc::Session,
+    base_url: &str,
+    host_target: &str,
+    dest: &Path,
+) -> anyhow::Result<()> {
+    let resp = client.get(rustup_init_url(base_url, host_target)).send()?;
+    if resp.status() == http::StatusCode::NOT_FOUND {
+        bail!(
+            "rustup doesn't provide an installer for the host target {}",
+            host_target
+        );
+    }
+    let mut resp = resp.error_for_status()?;
+
+    let mut file = File::create(dest)?;
+    io::copy(&mut resp, &mut file)?;
+    crate::native::make_executable(dest)?;
+    Ok(())
+}
+
 impl Runnable for Rustup {
     fn name(&self) -> Binary {
         Binary::ManagedByRustwide("rustup".into())
@@ -19,7 +45,7 @@ impl Runnable for Rustup {
 }
 
 impl Tool for Rustup {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         "rustup"
     }
 
@@ -36,25 +62,14 @@ impl Tool for Rustup {
         fs::create_dir_all(workspace.cargo_home())?;
         fs::create_dir_all(workspace.rustup_home())?;
 
-        let url = format!(
-            "{}/{}/rustup-init{}",
-            RUSTUP_BASE_URL,
-            crate::HOST_TARGET,
-            EXE_SUFFIX
-        );
-        let mut resp = workspace
-            .http_client()
-            .get(url)
-            .send()?
-            .error_for_status()?;
-
         let tempdir = tempdir()?;
         let installer = &tempdir.path().join(format!("rustup-init{}", EXE_SUFFIX));
-        {
-            let mut file = File::create(installer)?;
-            io::copy(&mut resp, &mut file)?;
-            crate::native::make_executable(installer)?;
-        }
+        download_rustup_init(
+            workspace.http_client(),
+            RUSTUP_BASE_URL,
+            workspace.host_target(),
+            installer,
+        )?;
 
         Command::new(workspace, installer.to_string_lossy().as_ref())
             .args(&[
@@ -85,3 +100,79 @@ impl Tool for Rustup {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{download_rustup_init, rustup_init_url, RUSTUP_BASE_URL};
+    use anyhow::anyhow;
+    use std::env::consts::EXE_SUFFIX;
+
+    #[test]
+    fn test_rustup_init_url() {
+        assert_eq!(
+            rustup_init_url(RUSTUP_BASE_URL, "aarch64-unknown-linux-gnu"),
+            format!(
+                "https://static.rust-lang.org/rustup/dist/aarch64-unknown-linux-gnu/rustup-init{}",
+                EXE_SUFFIX
+            )
+        );
+    }
+
+    #[test]
+    fn test_download_rustup_init() -> anyhow::Result<()> {
+        let server = tiny_http::Server::http("localhost:0").map_err(|e| anyhow!(e.to_string()))?;
+        let port = server.server_addr().to_ip().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let req = server.recv().unwrap();
+            let url = req.url().to_string();
+            req.respond(tiny_http::Response::from_string("rustup-init"))
+                .unwrap();
+            url
+        });
+
+        let tempdir = tempfile::tempdir()?;
+        let dest = tempdir.path().join("rustup-init");
+        download_rustup_init(
+            &attohttpc::Session::new(),
+            &format!("http://localhost:{}", port),
+            "aarch64-unknown-linux-gnu",
+            &dest,
+        )?;
+
+        // The installer of the configured host target is the one requested.
+        assert_eq!(
+            handle.join().unwrap(),
+            format!("/aarch64-unknown-linux-gnu/rustup-init{}", EXE_SUFFIX)
+        );
+        assert_eq!(std::fs::read_to_string(&dest)?, "rustup-init");
+        assert!(crate::native::is_executable(&dest)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_rustup_init_missing_target() -> anyhow::Result<()> {
+        let server = tiny_http::Server::http("localhost:0").map_err(|e| anyhow!(e.to_string()))?;
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            while let Ok(req) = server.recv() {
+                let _ = req.respond(tiny_http::Response::empty(404));
+            }
+        });
+
+        let tempdir = tempfile::tempdir()?;
+        let err = download_rustup_init(
+            &attohttpc::Session::new(),
+            &format!("http://localhost:{}", port),
+            "foo-unknown-bar",
+            &tempdir.path().join("rustup-init"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rustup doesn't provide an installer for the host target foo-unknown-bar"
+        );
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
 use fs2::FileExt;
 use log::warn;
 use percent_encoding::{AsciiSet, CONTROLS};
-use std::fs::OpenOptions;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
 use std::path::{Component, Path, PathBuf, Prefix, PrefixComponent};
 
 const ENCODE_SET: AsciiSet = CONTROLS
@@ -108,6 +109,30 @@ fn improve_remove_error(error: std::io::Error, path: &Path) -> std::io::Error {
     )
 }
 
+/// Write a file by calling `f` on `<path>.tmp` and renaming it to `path` only after `f` succeeds,
+/// to avoid leaving partially written files around if the write is interrupted.
+pub(crate) fn write_atomically(
+    path: &Path,
+    f: impl FnOnce(&mut File) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut tmp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = File::create(&tmp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            f(&mut file)?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(std::fs::rename(&tmp, path)?));
+    if result.is_err() && tmp.exists() {
+        let _ = remove_file(&tmp);
+    }
+    result
+}
+
 pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     let mut p = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
 
@@ -161,6 +186,33 @@ mod tests {
         );
         assert_eq!(expected, tested);
     }
+
+    #[test]
+    fn test_write_atomically() -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("foo.crate");
+
+        // An interrupted write must not leave anything at the destination.
+        let err = write_atomically(&path, |file| {
+            file.write_all(b"partial")?;
+            anyhow::bail!("interrupted");
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "interrupted");
+        assert!(!path.exists());
+        assert!(!dir.path().join("foo.crate.tmp").exists());
+
+        write_atomically(&path, |file| {
+            file.write_all(b"complete")?;
+            Ok(())
+        })?;
+        assert_eq!(std::fs::read(&path)?, b"complete");
+        assert!(!dir.path().join("foo.crate.tmp").exists());
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
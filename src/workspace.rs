@@ -1,10 +1,15 @@
 use crate::build::BuildDirectory;
-use crate::cmd::{Command, SandboxImage};
+use crate::cmd::{Command, SandboxImage, DEFAULT_CONTAINER_RUNTIME};
 use crate::inside_docker::CurrentContainer;
-use crate::Toolchain;
+#[cfg(any(feature = "metrics", doc))]
+use crate::metrics::MetricsSink;
+use crate::metrics::NoopMetricsSink;
+use crate::tools::BinaryCrate;
+use crate::{Crate, Toolchain};
 use anyhow::Context as _;
 use log::info;
 use std::fs;
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
@@ -19,6 +24,9 @@ const DEFAULT_COMMAND_TIMEOUT: Option<Duration> = Some(Duration::from_secs(15 *
 const DEFAULT_COMMAND_NO_OUTPUT_TIMEOUT: Option<Duration> = None;
 
 static DEFAULT_RUSTUP_PROFILE: &str = "minimal";
+/// File in the cargo home recording the configuration rustwide merged into its `config.toml`.
+const MANAGED_CARGO_CONFIG: &str = ".rustwide-cargo-config.toml";
+const DEFAULT_REGISTRY_INDEX_REFRESH_INTERVAL: Option<Duration> = Some(Duration::from_secs(5 * 60));
 
 /// Builder of a [`Workspace`](struct.Workspace.html).
 pub struct WorkspaceBuilder {
@@ -30,9 +38,30 @@ pub struct WorkspaceBuilder {
     fetch_registry_index_during_builds: bool,
     running_inside_docker: bool,
     fast_init: bool,
+    prebuilt_tools: bool,
     rustup_profile: String,
+    custom_tools: Vec<BinaryCrate>,
+    host_target: Option<String>,
+    cache_crates_by_checksum: bool,
+    registry_index_refresh_interval: Option<Duration>,
+    override_files_to_remove: Vec<PathBuf>,
+    verify_crate_checksums: bool,
+    max_extracted_crate_size: Option<u64>,
+    max_crate_compression_ratio: Option<u64>,
+    cargo_config: toml::Table,
+    container_runtime: String,
+    // The sink and the hooks are only stored during initialization, so a panic can't leave them
+    // in an inconsistent state observed by rustwide.
+    metrics_sink: AssertUnwindSafe<Arc<dyn crate::metrics::MetricsSink>>,
+    container_created_hook: AssertUnwindSafe<Option<ContainerHook>>,
+    container_deleted_hook: AssertUnwindSafe<Option<ContainerHook>>,
+    crate_filter: AssertUnwindSafe<Option<CrateFilter>>,
+    tokio_handle: Option<tokio::runtime::Handle>,
 }
 
+type ContainerHook = Box<dyn Fn(&str) + Send + Sync>;
+type CrateFilter = Box<dyn Fn(&Crate) -> anyhow::Result<()> + Send + Sync>;
+
 impl WorkspaceBuilder {
     /// Create a new builder.
     ///
@@ -48,7 +77,23 @@ impl WorkspaceBuilder {
             fetch_registry_index_during_builds: true,
             running_inside_docker: false,
             fast_init: false,
+            prebuilt_tools: false,
             rustup_profile: DEFAULT_RUSTUP_PROFILE.into(),
+            custom_tools: Vec::new(),
+            host_target: None,
+            cache_crates_by_checksum: false,
+            registry_index_refresh_interval: DEFAULT_REGISTRY_INDEX_REFRESH_INTERVAL,
+            override_files_to_remove: default_override_files(),
+            verify_crate_checksums: true,
+            max_extracted_crate_size: None,
+            max_crate_compression_ratio: None,
+            cargo_config: toml::Table::new(),
+            container_runtime: DEFAULT_CONTAINER_RUNTIME.into(),
+            metrics_sink: AssertUnwindSafe(Arc::new(NoopMetricsSink)),
+            container_created_hook: AssertUnwindSafe(None),
+            container_deleted_hook: AssertUnwindSafe(None),
+            crate_filter: AssertUnwindSafe(None),
+            tokio_handle: None,
         }
     }
 
@@ -94,6 +139,19 @@ impl WorkspaceBuilder {
         self
     }
 
+    /// Enable or disable downloading prebuilt binaries of the tools rustwide needs (disabled by
+    /// default).
+    ///
+    /// When enabled, rustwide will try to download a prebuilt binary of a tool before falling back
+    /// to building it with `cargo install`, speeding up the initialization. Only
+    /// `rustup-toolchain-install-master` (used by CI toolchains) has prebuilt binaries available,
+    /// other tools will always be built from source. The binaries are only used if their sha256
+    /// is published alongside them, in a file with the `.sha256` suffix, and matches.
+    pub fn prebuilt_tools(mut self, enable: bool) -> Self {
+        self.prebuilt_tools = enable;
+        self
+    }
+
     /// Enable or disable fetching the registry's index during each build (enabled by default).
     ///
     /// When this option is disabled the index will only be fetched when the workspace is
@@ -108,6 +166,75 @@ impl WorkspaceBuilder {
         self
     }
 
+    /// Set the sink receiving the metrics about the operations performed in the workspace, like
+    /// how long fetching crates and running builds takes. By default all the metrics are
+    /// discarded.
+    ///
+    /// To keep access to the sink after the workspace is created, pass an `Arc` of it.
+    #[cfg(any(feature = "metrics", doc))]
+    #[cfg_attr(docs_rs, doc(cfg(feature = "metrics")))]
+    pub fn metrics_sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.metrics_sink = AssertUnwindSafe(Arc::new(sink));
+        self
+    }
+
+    /// Set a function called with the ID of each sandbox container right after it's created.
+    ///
+    /// Together with [`on_container_deleted`](#method.on_container_deleted) this allows tracking
+    /// which containers are alive, for example to detect and remove leaked ones.
+    pub fn on_container_created(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.container_created_hook = AssertUnwindSafe(Some(Box::new(f)));
+        self
+    }
+
+    /// Set a function called with the ID of each sandbox container after it's successfully
+    /// deleted. The function is called even when the container is deleted because the command
+    /// running in it failed or panicked.
+    pub fn on_container_deleted(mut self, f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.container_deleted_hook = AssertUnwindSafe(Some(Box::new(f)));
+        self
+    }
+
+    /// Set a function deciding whether a crate is allowed to be used in the workspace, for example
+    /// to enforce a list of banned crates. The function is called with the crate before it's
+    /// fetched by [`Crate::fetch`](struct.Crate.html#method.fetch) and before it's built by
+    /// [`BuildBuilder::run`](struct.BuildBuilder.html#method.run), and returning an error aborts
+    /// the operation with that error.
+    pub fn crate_filter(
+        mut self,
+        f: impl Fn(&Crate) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.crate_filter = AssertUnwindSafe(Some(Box::new(f)));
+        self
+    }
+
+    /// Set the name or path of the container runtime CLI used to run the sandboxes (by default
+    /// `docker`).
+    ///
+    /// Any runtime whose CLI is compatible with the subset of Docker's used by rustwide can be
+    /// used, for example `podman`. The output of `podman inspect` matches Docker's for the fields
+    /// rustwide reads (`OOMKilled` and `Mounts`). The default sandbox image is pulled with the
+    /// configured runtime, while images passed to [`sandbox_image`](#method.sandbox_image) need to
+    /// be loaded with the `_with_runtime` methods of
+    /// [`SandboxImage`](cmd/struct.SandboxImage.html).
+    pub fn container_runtime(mut self, runtime: &str) -> Self {
+        self.container_runtime = runtime.into();
+        self
+    }
+
+    /// Run the commands of this workspace on the Tokio runtime behind the provided handle, instead
+    /// of the runtime rustwide creates internally and shares between all the workspaces.
+    ///
+    /// The runtime must be multi-threaded, as blocking on a handle of a current-thread runtime
+    /// can't drive its I/O and time drivers. Blocking methods like
+    /// [`Command::run`](cmd/struct.Command.html#method.run) still can't be called inside an async
+    /// context with either runtime: use
+    /// [`Command::run_async`](cmd/struct.Command.html#method.run_async) there instead.
+    pub fn tokio_handle(mut self, handle: tokio::runtime::Handle) -> Self {
+        self.tokio_handle = Some(handle);
+        self
+    }
+
     /// Enable or disable support for running Rustwide itself inside Docker (disabled by default).
     ///
     /// When support is enabled Rustwide will try to detect whether it's actually running inside a
@@ -133,9 +260,136 @@ impl WorkspaceBuilder {
         self
     }
 
+    /// Install an additional tool in the workspace with `cargo install`.
+    ///
+    /// The latest version of the `crate_name` crate will be installed when the workspace is
+    /// initialized, and it will be updated alongside the tools rustwide needs. The `binary` is the
+    /// name of the executable provided by the crate. This method can be called multiple times to
+    /// install multiple tools.
+    pub fn install_tool(mut self, crate_name: &str, binary: &str) -> Self {
+        self.custom_tools
+            .push(BinaryCrate::custom(crate_name, binary));
+        self
+    }
+
+    /// Override the target triple of the host system (by default the target rustwide was compiled
+    /// for).
+    ///
+    /// The host target is used to pick which rustup and prebuilt tool binaries to download. It
+    /// only needs to be changed when the binaries rustwide runs differ from the ones rustwide itself
+    /// was compiled for, for example when an x86_64 build of rustwide runs under emulation on an
+    /// aarch64 system.
+    pub fn host_target(mut self, triple: &str) -> Self {
+        self.host_target = Some(triple.into());
+        self
+    }
+
+    /// Enable or disable keying the cache of registry crates by their checksum (disabled by
+    /// default).
+    ///
+    /// By default cached crates are identified by their name and version, so if a registry serves
+    /// different contents for the same version (for example a mirror drifting from the upstream
+    /// registry) the stale cached copy is reused. When this option is enabled the checksum
+    /// recorded in the registry index is part of the cache key, and a changed checksum causes the
+    /// crate to be downloaded again.
+    ///
+    /// Enabling this option requires looking up the crate in the registry index when the crate is
+    /// fetched: crates.io's index is queried over HTTP, while alternative registries use their
+    /// local clone of the index. Older copies of the same version are removed from the cache
+    /// after a new one is downloaded, and purging a crate from the cache doesn't need the index.
+    pub fn cache_crates_by_checksum(mut self, enable: bool) -> Self {
+        self.cache_crates_by_checksum = enable;
+        self
+    }
+
+    /// Set how often the local clones of the git indexes of alternative registries are updated.
+    /// When a clone is used and it was last updated longer than the interval ago, the latest
+    /// changes are fetched from the registry before reading it. To never update the clones set
+    /// the interval to `None`. By default the interval is 5 minutes.
+    ///
+    /// Updating the clones is best-effort: if fetching the changes fails, the stale clone is used.
+    /// The clones are not updated if
+    /// [`fetch_registry_index_during_builds`](#method.fetch_registry_index_during_builds) is
+    /// disabled. Registries using the sparse protocol always fetch the latest index files.
+    pub fn registry_index_refresh_interval(mut self, interval: Option<Duration>) -> Self {
+        self.registry_index_refresh_interval = interval;
+        self
+    }
+
+    /// Enable or disable verifying the checksum of the registry crates downloaded by
+    /// [`Crate::fetch`](struct.Crate.html#method.fetch) (enabled by default).
+    ///
+    /// When enabled, the sha256 of each downloaded crate is compared with the checksum recorded
+    /// in the registry index, and a mismatch (for example a corrupted download or a tampered
+    /// mirror) fails the fetch with
+    /// [`CrateError::ChecksumMismatch`](enum.CrateError.html#variant.ChecksumMismatch) without
+    /// caching the crate. Verifying requires looking up the crate in the registry index, so it
+    /// can be disabled for registries not recording checksums.
+    pub fn verify_crate_checksums(mut self, enable: bool) -> Self {
+        self.verify_crate_checksums = enable;
+        self
+    }
+
+    /// Limit the total size of the files extracted from the archives of registry and in-memory
+    /// crates when their source code is copied into a build (unlimited by default).
+    ///
+    /// The limit protects the disk from huge crates and from decompression bombs, archives
+    /// whose contents are much bigger than the archive itself. Extracting a crate over the limit
+    /// fails with
+    /// [`CrateError::ExtractedSizeLimitExceeded`](enum.CrateError.html#variant.ExtractedSizeLimitExceeded),
+    /// and the files extracted so far are removed.
+    pub fn max_extracted_crate_size(mut self, size: Option<u64>) -> Self {
+        self.max_extracted_crate_size = size;
+        self
+    }
+
+    /// Limit the ratio between the decompressed and the compressed size of the registry crates'
+    /// archives when their source code is copied into a build (unlimited by default).
+    ///
+    /// Decompression bombs (archives whose contents are much bigger than the archive itself) are
+    /// detected while decompressing them, before much data is extracted. Extracting a crate over
+    /// the limit fails with
+    /// [`CrateError::CompressionRatioExceeded`](enum.CrateError.html#variant.CompressionRatioExceeded),
+    /// and the files extracted so far are removed. As the archives include the padding of the
+    /// tar format, which compresses well, small crates can have high ratios: the limit should
+    /// be generous, for example `100`.
+    pub fn max_crate_compression_ratio(mut self, ratio: Option<u64>) -> Self {
+        self.max_crate_compression_ratio = ratio;
+        self
+    }
+
+    /// Set the files removed from the source code of the crates before building them, as paths
+    /// relative to the root of the source. Files missing from a crate are ignored.
+    ///
+    /// Those files could override the configuration rustwide provides to the build, and by
+    /// default the list contains `.cargo/config`, `.cargo/config.toml`, `rust-toolchain` and
+    /// `rust-toolchain.toml`. The provided list replaces the default one, so keeping some of the
+    /// defaults requires including them in the list. Initializing the workspace fails if any of
+    /// the paths is absolute or contains `..` components.
+    pub fn override_files_to_remove(mut self, paths: Vec<PathBuf>) -> Self {
+        self.override_files_to_remove = paths;
+        self
+    }
+
+    /// Set a cargo configuration key for all the cargo invocations in the workspace.
+    ///
+    /// The `key` is split at each `.` into nested tables, for example `net.retry` or
+    /// `registries.my-registry.index`. The configuration is written to the `config.toml` of the
+    /// workspace's cargo home when the workspace is initialized, so it's also used by the cargo
+    /// invocations rustwide does while preparing builds. This is needed for example to let cargo
+    /// fetch dependencies from alternative registries. The keys set by a previous initialization
+    /// of the workspace and not set anymore are removed from the file.
+    ///
+    /// This method can be called multiple times to set multiple keys.
+    pub fn cargo_config_env(mut self, key: &str, value: impl Into<toml::Value>) -> Self {
+        set_config_key(&mut self.cargo_config, key, value.into());
+        self
+    }
+
     /// Initialize the workspace. This will create all the necessary local files and fetch the rest from the network. It's
     /// not unexpected for this method to take minutes to run on slower network connections.
     pub fn init(self) -> anyhow::Result<Workspace> {
+        validate_override_files(&self.override_files_to_remove)?;
         std::fs::create_dir_all(&self.path).with_context(|| {
             format!(
                 "failed to create workspace directory: {}",
@@ -147,7 +401,7 @@ impl WorkspaceBuilder {
             let sandbox_image = if let Some(img) = self.sandbox_image {
                 img
             } else {
-                SandboxImage::remote(DEFAULT_SANDBOX_IMAGE)?
+                SandboxImage::remote_with_runtime(DEFAULT_SANDBOX_IMAGE, &self.container_runtime)?
             };
 
             let mut agent = attohttpc::Session::new();
@@ -162,7 +416,24 @@ impl WorkspaceBuilder {
                     command_no_output_timeout: self.command_no_output_timeout,
                     fetch_registry_index_during_builds: self.fetch_registry_index_during_builds,
                     current_container: None,
+                    prebuilt_tools: self.prebuilt_tools,
                     rustup_profile: self.rustup_profile,
+                    custom_tools: self.custom_tools,
+                    host_target: self
+                        .host_target
+                        .unwrap_or_else(|| crate::HOST_TARGET.into()),
+                    cache_crates_by_checksum: self.cache_crates_by_checksum,
+                    registry_index_refresh_interval: self.registry_index_refresh_interval,
+                    override_files_to_remove: self.override_files_to_remove,
+                    verify_crate_checksums: self.verify_crate_checksums,
+                    max_extracted_crate_size: self.max_extracted_crate_size,
+                    max_crate_compression_ratio: self.max_crate_compression_ratio,
+                    container_runtime: self.container_runtime,
+                    metrics_sink: self.metrics_sink.0,
+                    container_created_hook: self.container_created_hook.0,
+                    container_deleted_hook: self.container_deleted_hook.0,
+                    crate_filter: self.crate_filter.0,
+                    tokio_handle: self.tokio_handle,
                 }),
             };
 
@@ -171,6 +442,7 @@ impl WorkspaceBuilder {
                 Arc::get_mut(&mut ws.inner).unwrap().current_container = container;
             }
 
+            ws.write_cargo_config(&self.cargo_config)?;
             ws.init(self.fast_init)?;
             Ok(ws)
         })
@@ -185,7 +457,95 @@ struct WorkspaceInner {
     command_no_output_timeout: Option<Duration>,
     fetch_registry_index_during_builds: bool,
     current_container: Option<CurrentContainer>,
+    prebuilt_tools: bool,
     rustup_profile: String,
+    custom_tools: Vec<BinaryCrate>,
+    host_target: String,
+    cache_crates_by_checksum: bool,
+    registry_index_refresh_interval: Option<Duration>,
+    override_files_to_remove: Vec<PathBuf>,
+    verify_crate_checksums: bool,
+    max_extracted_crate_size: Option<u64>,
+    max_crate_compression_ratio: Option<u64>,
+    container_runtime: String,
+    metrics_sink: Arc<dyn crate::metrics::MetricsSink>,
+    container_created_hook: Option<ContainerHook>,
+    container_deleted_hook: Option<ContainerHook>,
+    crate_filter: Option<CrateFilter>,
+    tokio_handle: Option<tokio::runtime::Handle>,
+}
+
+/// Result of the checks done by [`Workspace::health_check`](struct.Workspace.html#method.health_check).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    docker_available: bool,
+    rustup_installed: bool,
+    missing_tools: Vec<String>,
+    main_toolchain_installed: bool,
+}
+
+impl HealthReport {
+    /// Whether the container runtime CLI (`docker` by default) is available and able to reach its
+    /// daemon.
+    pub fn docker_available(&self) -> bool {
+        self.docker_available
+    }
+
+    /// Whether rustup is installed in the workspace.
+    pub fn rustup_installed(&self) -> bool {
+        self.rustup_installed
+    }
+
+    /// Whether all the tools needed by rustwide, and the ones added with
+    /// [`WorkspaceBuilder::install_tool`](struct.WorkspaceBuilder.html#method.install_tool), are
+    /// installed in the workspace.
+    pub fn tools_installed(&self) -> bool {
+        self.missing_tools.is_empty()
+    }
+
+    /// Names of the tools missing from the workspace.
+    pub fn missing_tools(&self) -> &[String] {
+        &self.missing_tools
+    }
+
+    /// Whether the toolchain rustwide uses internally (to install tools, for example) is
+    /// installed.
+    pub fn main_toolchain_installed(&self) -> bool {
+        self.main_toolchain_installed
+    }
+
+    /// Whether all the checks succeeded.
+    pub fn is_healthy(&self) -> bool {
+        self.docker_available
+            && self.rustup_installed
+            && self.tools_installed()
+            && self.main_toolchain_installed
+    }
+}
+
+/// Problem found in a workspace by [`Workspace::verify`](struct.Workspace.html#method.verify).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Issue {
+    /// The rustup binary is missing or it's not executable.
+    RustupNotExecutable,
+    /// The toolchain rustwide uses internally is not installed.
+    MainToolchainMissing,
+    /// The binary of a tool is missing or it can't be executed.
+    ToolNotRunnable {
+        /// Name of the tool's binary.
+        name: String,
+    },
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Issue::RustupNotExecutable => write!(f, "rustup is not executable"),
+            Issue::MainToolchainMissing => write!(f, "the main toolchain is not installed"),
+            Issue::ToolNotRunnable { name } => write!(f, "tool {} can't be executed", name),
+        }
+    }
 }
 
 /// Directory on the filesystem containing rustwide's state and caches.
@@ -206,6 +566,28 @@ impl Workspace {
         )
     }
 
+    /// Run the function with a new, uniquely named build directory, removing the directory
+    /// afterwards regardless of the outcome of the function.
+    ///
+    /// This is useful for one-off builds, where the build artifacts don't need to be reused by
+    /// later builds.
+    pub fn with_temp_build_dir<R>(
+        &self,
+        f: impl FnOnce(&mut BuildDirectory) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let mut suffix = [0u8; 8];
+        getrandom::getrandom(&mut suffix)?;
+        let suffix = suffix
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+
+        let mut dir = self
+            .build_dir(&format!("tmp-{}", suffix))
+            .purge_on_drop(true);
+        f(&mut dir)
+    }
+
     /// Remove all the contents of all the build directories, freeing disk space.
     pub fn purge_all_build_dirs(&self) -> anyhow::Result<()> {
         let dir = self.builds_dir();
@@ -269,6 +651,132 @@ impl Workspace {
         crate::toolchain::list_installed_toolchains(&self.rustup_home())
     }
 
+    /// Check the state of the workspace and of its dependencies, to help diagnosing setup issues.
+    ///
+    /// Each check is independent from the others, and a failed check doesn't prevent the
+    /// following ones from running. See [`HealthReport`](struct.HealthReport.html) for the list of
+    /// checks.
+    pub fn health_check(&self) -> HealthReport {
+        let docker_available = Command::new(self, self.container_runtime())
+            .args(&["version"])
+            .log_output(false)
+            .run()
+            .is_ok();
+        let missing_tools = crate::tools::missing_tools(self);
+        let main_toolchain_installed = self
+            .installed_toolchains()
+            .map(|installed| installed.iter().any(|tc| tc.is_needed_by_rustwide()))
+            .unwrap_or(false);
+
+        HealthReport {
+            docker_available,
+            rustup_installed: !missing_tools.iter().any(|tool| tool == "rustup"),
+            missing_tools,
+            main_toolchain_installed,
+        }
+    }
+
+    /// Check whether the rustup installation and the tools in the workspace are in a consistent
+    /// state, returning the list of problems found. An empty list means no problem was found.
+    ///
+    /// Unlike [`health_check`](#method.health_check), this method also tries to execute the
+    /// installed tools, detecting for example binaries corrupted by an interrupted install. Most
+    /// issues can be repaired by removing the broken files and initializing the workspace again
+    /// with [`WorkspaceBuilder::init`](struct.WorkspaceBuilder.html#method.init).
+    pub fn verify(&self) -> anyhow::Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+
+        if !crate::tools::is_rustup_executable(self)? {
+            issues.push(Issue::RustupNotExecutable);
+        }
+        if !self
+            .installed_toolchains()?
+            .iter()
+            .any(|tc| tc.is_needed_by_rustwide())
+        {
+            issues.push(Issue::MainToolchainMissing);
+        }
+        for name in crate::tools::unrunnable_tools(self) {
+            issues.push(Issue::ToolNotRunnable { name });
+        }
+
+        Ok(issues)
+    }
+
+    /// Repair the problems found by [`verify`](#method.verify), reinstalling rustup, the main
+    /// toolchain and the tools when they're broken or missing, and return the list of repaired
+    /// issues.
+    ///
+    /// Only the broken parts of the workspace are reinstalled, so calling this method on a
+    /// healthy workspace doesn't do anything, and the other installed toolchains are preserved.
+    /// An error is returned if some issues are still present after the repair.
+    pub fn repair(&self) -> anyhow::Result<Vec<Issue>> {
+        let issues = self.verify()?;
+        // The issues are returned by `verify` in dependency order: rustup is needed to install
+        // the main toolchain, which is needed to install the tools.
+        for issue in &issues {
+            info!("repairing workspace issue: {}", issue);
+            match issue {
+                Issue::RustupNotExecutable => crate::tools::reinstall(self, "rustup")?,
+                Issue::MainToolchainMissing => Toolchain::MAIN.install(self)?,
+                Issue::ToolNotRunnable { name } => crate::tools::reinstall(self, name)?,
+            }
+        }
+
+        let remaining = self.verify()?;
+        if !remaining.is_empty() {
+            anyhow::bail!(
+                "issues still present after repairing the workspace: {}",
+                remaining
+                    .iter()
+                    .map(|issue| issue.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(issues)
+    }
+
+    /// Return the path of an installed tool's binary, or `None` if the tool is unknown or missing.
+    ///
+    /// The name is the one of the binary, and it can refer both to the tools rustwide installs on
+    /// its own (like `rustup` or `git-credential-null`) and to the ones registered with
+    /// [`WorkspaceBuilder::install_tool`](struct.WorkspaceBuilder.html#method.install_tool).
+    pub fn tool_path(&self, name: &str) -> Option<PathBuf> {
+        crate::tools::installed_binary_path(self, name)
+    }
+
+    /// Return the target triple of the host system rustwide is running on, for example
+    /// `x86_64-unknown-linux-gnu`. It can be overridden with
+    /// [`WorkspaceBuilder::host_target`](struct.WorkspaceBuilder.html#method.host_target).
+    pub fn host_target(&self) -> &str {
+        &self.inner.host_target
+    }
+
+    pub(crate) fn metrics(&self) -> &dyn crate::metrics::MetricsSink {
+        &*self.inner.metrics_sink
+    }
+
+    pub(crate) fn container_created(&self, id: &str) {
+        if let Some(hook) = &self.inner.container_created_hook {
+            hook(id);
+        }
+    }
+
+    pub(crate) fn container_deleted(&self, id: &str) {
+        if let Some(hook) = &self.inner.container_deleted_hook {
+            hook(id);
+        }
+    }
+
+    pub(crate) fn check_crate_filter(&self, krate: &Crate) -> anyhow::Result<()> {
+        match &self.inner.crate_filter {
+            Some(filter) => filter(krate),
+            None => Ok(()),
+        }
+    }
+
     pub(crate) fn http_client(&self) -> &attohttpc::Session {
         &self.inner.http
     }
@@ -293,6 +801,14 @@ impl Workspace {
         &self.inner.sandbox_image
     }
 
+    pub(crate) fn container_runtime(&self) -> &str {
+        &self.inner.container_runtime
+    }
+
+    pub(crate) fn tokio_handle(&self) -> Option<&tokio::runtime::Handle> {
+        self.inner.tokio_handle.as_ref()
+    }
+
     pub(crate) fn default_command_timeout(&self) -> Option<Duration> {
         self.inner.command_timeout
     }
@@ -309,10 +825,72 @@ impl Workspace {
         self.inner.current_container.as_ref()
     }
 
+    pub(crate) fn prebuilt_tools(&self) -> bool {
+        self.inner.prebuilt_tools
+    }
+
     pub(crate) fn rustup_profile(&self) -> &str {
         &self.inner.rustup_profile
     }
 
+    pub(crate) fn custom_tools(&self) -> &[BinaryCrate] {
+        &self.inner.custom_tools
+    }
+
+    pub(crate) fn override_files_to_remove(&self) -> &[PathBuf] {
+        &self.inner.override_files_to_remove
+    }
+
+    pub(crate) fn verify_crate_checksums(&self) -> bool {
+        self.inner.verify_crate_checksums
+    }
+
+    pub(crate) fn max_extracted_crate_size(&self) -> Option<u64> {
+        self.inner.max_extracted_crate_size
+    }
+
+    pub(crate) fn max_crate_compression_ratio(&self) -> Option<u64> {
+        self.inner.max_crate_compression_ratio
+    }
+
+    pub(crate) fn cache_crates_by_checksum(&self) -> bool {
+        self.inner.cache_crates_by_checksum
+    }
+
+    /// Return how often the clones of the alternative registries' indexes are updated, or `None`
+    /// if they must not be updated.
+    pub(crate) fn registry_index_refresh_interval(&self) -> Option<Duration> {
+        if self.fetch_registry_index_during_builds() {
+            self.inner.registry_index_refresh_interval
+        } else {
+            None
+        }
+    }
+
+    /// Merge the configuration into the `config.toml` of the workspace's cargo home, replacing
+    /// the configuration merged by the previous initialization of the workspace.
+    fn write_cargo_config(&self, config: &toml::Table) -> anyhow::Result<()> {
+        let managed_path = self.cargo_home().join(MANAGED_CARGO_CONFIG);
+        let previous = read_toml(&managed_path)?;
+        if config.is_empty() && previous.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.cargo_home().join("config.toml");
+        let mut current = read_toml(&path)?;
+        remove_tables(&mut current, &previous);
+        merge_tables(&mut current, config);
+
+        fs::create_dir_all(self.cargo_home())?;
+        fs::write(&path, toml::to_string(&current)?)?;
+        if config.is_empty() {
+            fs::remove_file(&managed_path)?;
+        } else {
+            fs::write(&managed_path, toml::to_string(config)?)?;
+        }
+        Ok(())
+    }
+
     fn init(&self, fast_init: bool) -> anyhow::Result<()> {
         info!("installing tools required by rustwide");
         crate::tools::install(self, fast_init)?;
@@ -338,3 +916,188 @@ impl Workspace {
         Ok(())
     }
 }
+
+fn set_config_key(table: &mut toml::Table, key: &str, value: toml::Value) {
+    let mut parts = key.split('.').peekable();
+    let mut current = table;
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            current.insert(part.into(), value);
+            return;
+        }
+        let entry = current
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        current = entry.as_table_mut().unwrap();
+    }
+}
+
+fn merge_tables(dest: &mut toml::Table, src: &toml::Table) {
+    for (key, value) in src {
+        match (dest.get_mut(key), value) {
+            (Some(toml::Value::Table(dest)), toml::Value::Table(src)) => merge_tables(dest, src),
+            _ => {
+                dest.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Remove the keys of `src` from `dest`, unless their value was changed.
+fn remove_tables(dest: &mut toml::Table, src: &toml::Table) {
+    for (key, value) in src {
+        match (dest.get_mut(key), value) {
+            (Some(toml::Value::Table(dest_table)), toml::Value::Table(src)) => {
+                remove_tables(dest_table, src);
+                if dest_table.is_empty() {
+                    dest.remove(key);
+                }
+            }
+            (Some(dest_value), value) if dest_value == value => {
+                dest.remove(key);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_toml(path: &Path) -> anyhow::Result<toml::Table> {
+    if path.is_file() {
+        toml::from_str(&fs::read_to_string(path)?)
+            .with_context(|| format!("failed to parse {}", path.display()))
+    } else {
+        Ok(toml::Table::new())
+    }
+}
+
+fn default_override_files() -> Vec<PathBuf> {
+    vec![
+        Path::new(".cargo").join("config"),
+        Path::new(".cargo").join("config.toml"),
+        PathBuf::from("rust-toolchain"),
+        PathBuf::from("rust-toolchain.toml"),
+    ]
+}
+
+fn validate_override_files(paths: &[PathBuf]) -> anyhow::Result<()> {
+    for path in paths {
+        crate::build::validate_source_path(path)
+            .context("invalid path in the override files to remove")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_override_files, merge_tables, remove_tables, set_config_key,
+        validate_override_files,
+    };
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_set_config_key() {
+        let mut config = toml::Table::new();
+        set_config_key(&mut config, "net.retry", 5.into());
+        set_config_key(
+            &mut config,
+            "registries.foo.index",
+            "https://example.com".into(),
+        );
+        set_config_key(
+            &mut config,
+            "registries.bar.index",
+            "https://example.org".into(),
+        );
+
+        assert_eq!(
+            config,
+            toml::from_str::<toml::Table>(
+                r#"
+                    net.retry = 5
+                    registries.foo.index = "https://example.com"
+                    registries.bar.index = "https://example.org"
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_tables() {
+        let mut dest = toml::from_str::<toml::Table>(
+            r#"
+                net.retry = 2
+                net.git-fetch-with-cli = true
+                registries.foo.index = "https://example.com"
+            "#,
+        )
+        .unwrap();
+        let src = toml::from_str::<toml::Table>(
+            r#"
+                net.retry = 5
+                registries.bar.index = "https://example.org"
+            "#,
+        )
+        .unwrap();
+        merge_tables(&mut dest, &src);
+
+        assert_eq!(
+            dest,
+            toml::from_str::<toml::Table>(
+                r#"
+                    net.retry = 5
+                    net.git-fetch-with-cli = true
+                    registries.foo.index = "https://example.com"
+                    registries.bar.index = "https://example.org"
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_remove_tables() {
+        let mut dest = toml::from_str::<toml::Table>(
+            r#"
+                net.retry = 5
+                net.git-fetch-with-cli = true
+                registries.foo.index = "https://example.com"
+                registries.bar.index = "https://example.net"
+            "#,
+        )
+        .unwrap();
+        let src = toml::from_str::<toml::Table>(
+            r#"
+                net.retry = 5
+                registries.foo.index = "https://example.com"
+                registries.bar.index = "https://example.org"
+            "#,
+        )
+        .unwrap();
+        remove_tables(&mut dest, &src);
+
+        // Keys changed since they were merged are kept, and empty tables are removed.
+        assert_eq!(
+            dest,
+            toml::from_str::<toml::Table>(
+                r#"
+                    net.git-fetch-with-cli = true
+                    registries.bar.index = "https://example.net"
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_validate_override_files() {
+        assert!(validate_override_files(&default_override_files()).is_ok());
+        assert!(validate_override_files(&[PathBuf::from("deny.toml")]).is_ok());
+        assert!(validate_override_files(&[Path::new("..").join("deny.toml")]).is_err());
+        assert!(validate_override_files(&[PathBuf::from("/etc/passwd")]).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 use log::LevelFilter;
-use rustwide::cmd::{ProcessLinesActions, SandboxBuilder};
+use rustwide::cmd::{CommandError, ProcessLinesActions, SandboxBuilder};
+use std::path::Path;
+use std::time::Duration;
 
 #[macro_use]
 mod runner;
@@ -25,17 +27,550 @@ fn test_hello_world() {
     });
 }
 
+#[test]
+fn test_cargo_metadata() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let metadata = build.cargo_metadata();
+            let members = metadata.workspace_members().collect::<Vec<_>>();
+            assert_eq!(members.len(), 1);
+            assert_eq!(members[0].name(), "hello-world");
+            assert_eq!(members[0].version(), "0.1.0");
+            assert!(members[0]
+                .targets()
+                .iter()
+                .any(|target| target.kind() == ["bin"]));
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_effective_env() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let env = build.effective_env();
+            for var in &[
+                "CARGO_HOME",
+                "RUSTUP_HOME",
+                "CARGO_TARGET_DIR",
+                "SOURCE_DIR",
+            ] {
+                assert!(
+                    env.iter().any(|(key, _)| key == var),
+                    "{} missing from {:?}",
+                    var,
+                    env
+                );
+            }
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_entrypoint() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .entrypoint(Some("/bin/echo".into()));
+        run.run(sandbox, |build| {
+            let output = build.cmd("hello").args(&["world"]).run_capture()?;
+            assert_eq!(output.stdout_lines(), ["hello world"]);
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_hostname() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .hostname("rustwide-test");
+        run.run(sandbox, |build| {
+            let output = build.cmd("cat").args(&["/etc/hostname"]).run_capture()?;
+            assert_eq!(output.stdout_lines(), ["rustwide-test"]);
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_stdin() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let output = build
+                .cmd("cat")
+                .stdin(b"hello\nworld\n".to_vec())
+                .run_capture()?;
+            assert_eq!(output.stdout_lines(), ["hello", "world"]);
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_env_clear() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let output = build
+                .cmd("env")
+                .env("RUSTWIDE_TEST", "foo")
+                .env_clear()
+                .run_capture()?;
+            let env = output.stdout_lines();
+            assert!(env.iter().any(|var| var == "RUSTWIDE_TEST=foo"));
+            assert!(env.iter().any(|var| var.starts_with("CARGO_HOME=")));
+            assert!(!env.iter().any(|var| var.starts_with("SOURCE_DIR=")));
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_cargo_registry_cache() {
+    runner::run("hello-world", |run| {
+        let cache = tempfile::tempdir()?;
+        let sandbox = || SandboxBuilder::new().cargo_registry_cache(cache.path());
+
+        run.run(sandbox(), |build| {
+            build
+                .cmd("sh")
+                .args(&["-c", r#"echo cached > "$CARGO_HOME/registry/probe""#])
+                .run()?;
+            Ok(())
+        })?;
+        assert!(cache.path().join("registry").join("probe").exists());
+        // Cargo's locks of the cache are shared between the sandboxes using it.
+        assert!(cache.path().join(".package-cache").is_file());
+
+        // The second build sees the contents of the cache written by the first one.
+        run.run(sandbox(), |build| {
+            let output = build
+                .cmd("cat")
+                .args(&["/opt/rustwide/cargo-home/registry/probe"])
+                .run_capture()?;
+            assert_eq!(output.stdout_lines(), ["cached"]);
+            build
+                .cmd("sh")
+                .args(&["-c", r#"test -w "$CARGO_HOME/.package-cache""#])
+                .run()?;
+            build.cargo().args(&["build"]).run()?;
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_extra_docker_args() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .extra_docker_args(vec!["--env".into(), "RUSTWIDE_TEST=extra".into()]);
+        run.run(sandbox, |build| {
+            let output = build.cmd("env").run_capture()?;
+            assert!(output
+                .stdout_lines()
+                .iter()
+                .any(|var| var == "RUSTWIDE_TEST=extra"));
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_pids_limit() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .pids_limit(Some(8));
+        run.run(sandbox, |build| {
+            // Spawning more processes than the limit fails.
+            let res = build
+                .cmd("sh")
+                .args(&["-c", "for i in $(seq 16); do sleep 5 & done; wait"])
+                .run_capture();
+            assert!(res.is_err());
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sandbox_network() {
+    runner::run("hello-world", |run| {
+        let interfaces = |build: &rustwide::Build| -> anyhow::Result<Vec<String>> {
+            Ok(build
+                .cmd("ls")
+                .args(&["/sys/class/net"])
+                .run_capture()?
+                .stdout_lines()
+                .to_vec())
+        };
+
+        run.run(
+            SandboxBuilder::new().network(Some("none".into())),
+            |build| {
+                assert_eq!(interfaces(build)?, ["lo"]);
+                Ok(())
+            },
+        )?;
+
+        // The last call between `enable_networking` and `network` wins.
+        run.run(
+            SandboxBuilder::new().enable_networking(false).network(None),
+            |build| {
+                assert!(interfaces(build)?.len() > 1);
+                Ok(())
+            },
+        )?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_read_only_rootfs() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .read_only_rootfs(true)
+            .tmpfs(std::path::Path::new("/tmp"), Some(64 * 1024 * 1024));
+        run.run(sandbox, |build| {
+            build
+                .cmd("sh")
+                .args(&["-c", "! touch /probe && touch /tmp/probe"])
+                .run()?;
+            // The target directory is still writable.
+            build.cargo().args(&["build"]).run()?;
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_run_as_user() {
+    runner::run("hello-world", |run| {
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .run_as_user(Some((1234, 5678)));
+        run.run(sandbox, |build| {
+            let uid = build.cmd("id").args(&["-u"]).run_capture()?;
+            assert_eq!(uid.stdout_lines(), ["1234"]);
+            let gid = build.cmd("id").args(&["-g"]).run_capture()?;
+            assert_eq!(gid.stdout_lines(), ["5678"]);
+            Ok(())
+        })?;
+
+        // Without a user the default user of the image is used.
+        let sandbox = SandboxBuilder::new()
+            .enable_networking(false)
+            .run_as_user(None);
+        run.run(sandbox, |build| {
+            let uid = build.cmd("id").args(&["-u"]).run_capture()?;
+            assert!(uid.stdout_lines()[0].parse::<u32>().is_ok());
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_jobs() {
+    runner::run("hello-world", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.jobs(2).run(|build| {
+                assert!(build
+                    .effective_env()
+                    .contains(&("CARGO_BUILD_JOBS".into(), "2".into())));
+                build.cargo().args(&["build"]).run()?;
+                Ok(())
+            })
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_fast_compile() {
+    runner::run("hello-world", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.fast_compile().run(|build| {
+                let env = build.effective_env();
+                for var in &[
+                    "CARGO_PROFILE_DEV_CODEGEN_UNITS",
+                    "CARGO_PROFILE_RELEASE_CODEGEN_UNITS",
+                ] {
+                    assert!(env.contains(&(var.to_string(), "256".into())));
+                }
+                assert!(env.contains(&("CARGO_PROFILE_RELEASE_LTO".into(), "off".into())));
+                build.cargo().args(&["build", "--release"]).run()?;
+                Ok(())
+            })
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_incremental() {
+    runner::run("hello-world", |run| {
+        for incremental in &[true, false] {
+            run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+                builder.incremental(*incremental).run(|build| {
+                    let expected = if *incremental { "1" } else { "0" };
+                    assert!(build
+                        .effective_env()
+                        .contains(&("CARGO_INCREMENTAL".into(), expected.into())));
+                    build.cargo().args(&["build"]).run()?;
+
+                    let has_incremental_data = build
+                        .host_target_dir()
+                        .join("debug")
+                        .join("incremental")
+                        .read_dir()
+                        .map(|mut entries| entries.next().is_some())
+                        .unwrap_or(false);
+                    assert_eq!(has_incremental_data, *incremental);
+                    Ok(())
+                })
+            })?;
+        }
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_incremental_read_only_cargo_home() {
+    runner::run("hello-world", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.incremental(true).run(|build| {
+                // Both the cargo home and the source are read-only inside the sandbox.
+                build
+                    .cmd("sh")
+                    .args(&["-c", r#"! touch "$CARGO_HOME/probe" && ! touch probe"#])
+                    .run()?;
+
+                build.cargo().args(&["build"]).run()?;
+
+                let incremental = build.host_target_dir().join("debug").join("incremental");
+                assert!(incremental.read_dir()?.next().is_some());
+                Ok(())
+            })
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_archive_target_dir() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            build.cargo().args(&["build"]).run()?;
+
+            let dest = tempfile::tempdir()?;
+            let tarball = dest.path().join("target.tar.gz");
+            build.archive_target_dir(&tarball, true)?;
+
+            let mut archive =
+                tar::Archive::new(flate2::read::GzDecoder::new(std::fs::File::open(&tarball)?));
+            let mut paths = Vec::new();
+            for entry in archive.entries()? {
+                paths.push(entry?.path()?.into_owned());
+            }
+            assert!(!paths.is_empty());
+            assert!(paths.iter().all(|path| path.starts_with("target")));
+            assert!(paths
+                .iter()
+                .any(|path| path.starts_with(std::path::Path::new("target").join("debug"))));
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_seed_target_dir() {
+    runner::run("hello-world", |run| {
+        let archives = tempfile::tempdir()?;
+        let seed = archives.path().join("seed.tar.gz");
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            build.cargo().args(&["build"]).run()?;
+            build.archive_target_dir(&seed, true)?;
+            Ok(())
+        })?;
+
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.seed_target_dir(&seed).run(|build| {
+                let target = build.host_target_dir();
+                assert!(target.join(".rustc_info.json").is_file());
+                assert!(target.join("debug").join("deps").is_dir());
+                build.cargo().args(&["build"]).run()?;
+                Ok(())
+            })
+        })?;
+
+        // Seeds built by a different rustc are rejected.
+        let incompatible = archives.path().join("incompatible");
+        std::fs::create_dir(&incompatible)?;
+        std::fs::write(
+            incompatible.join(".rustc_info.json"),
+            r#"{"rustc_fingerprint":0,"outputs":{"0":{"success":true,"status":"","code":0,"stdout":"rustc 0.0.0\n","stderr":""}},"successes":{}}"#,
+        )?;
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.seed_target_dir(&incompatible).run(|_| Ok(()))
+        });
+        assert!(res.is_err());
+        Ok(())
+    });
+}
+
+#[test]
+fn test_clean_target_before() {
+    runner::run("hello-world", |run| {
+        let mut dir = run.build_dir()?;
+        let sandbox = || SandboxBuilder::new().enable_networking(false);
+        run.build_in(&mut dir, sandbox(), |builder| {
+            builder.run(|build| {
+                build.cargo().args(&["build"]).run()?;
+                Ok(())
+            })
+        })?;
+
+        // The target directory is shared between builds by default.
+        run.build_in(&mut dir, sandbox(), |builder| {
+            builder.run(|build| {
+                assert!(build.host_target_dir().join("debug").is_dir());
+                Ok(())
+            })
+        })?;
+
+        run.build_in(&mut dir, sandbox(), |builder| {
+            builder.clean_target_before(true).run(|build| {
+                assert_eq!(std::fs::read_dir(build.host_target_dir())?.count(), 0);
+                build.cargo().args(&["build"]).run()?;
+                Ok(())
+            })
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_skip_if_unchanged() {
+    runner::run("hello-world", |run| {
+        let mut dir = run.build_dir()?;
+        let mut runs = 0;
+        for _ in 0..2 {
+            let res = run.build_in(
+                &mut dir,
+                SandboxBuilder::new().enable_networking(false),
+                |builder| {
+                    builder.skip_if_unchanged(true).run(|build| {
+                        runs += 1;
+                        build.cargo().args(&["build"]).run()?;
+                        Ok(())
+                    })
+                },
+            );
+            if let Err(err) = res {
+                assert!(err.downcast_ref::<rustwide::BuildSkipped>().is_some());
+            }
+        }
+        assert_eq!(runs, 1);
+
+        // Changing the options of the build runs it again.
+        run.build_in(
+            &mut dir,
+            SandboxBuilder::new().enable_networking(false),
+            |builder| builder.skip_if_unchanged(true).jobs(1).run(|_| Ok(())),
+        )?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_rustdoc_build_private_items() {
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let options = rustwide::RustdocOptions::new().document_private_items(true);
+            let doc_dir = build.rustdoc_build(&options)?;
+            assert!(doc_dir.starts_with(build.host_target_dir()));
+            // `main` is private, so it's only documented with `--document-private-items`.
+            assert!(doc_dir.join("hello_world").join("fn.main.html").is_file());
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+fn test_hello_world_from_bytes() -> anyhow::Result<()> {
+    use flate2::{write::GzEncoder, Compression};
+    use rustwide::{Crate, Toolchain};
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    tar.append_dir_all(
+        "hello-world-0.1.0",
+        Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    )?;
+    let krate = Crate::from_bytes("hello-world", "0.1.0", tar.into_inner()?.finish()?);
+
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    let mut dir = workspace.build_dir("hello-world-from-bytes");
+    dir.purge()?;
+    dir.build(
+        &toolchain,
+        &krate,
+        SandboxBuilder::new().enable_networking(false),
+    )
+    .run(|build| {
+        let storage = rustwide::logging::LogStorage::new(LevelFilter::Info);
+        rustwide::logging::capture(&storage, || -> anyhow::Result<_> {
+            build.cargo().args(&["run"]).run()?;
+            Ok(())
+        })?;
+
+        assert!(storage.to_string().contains("[stdout] Hello, world!\n"));
+        Ok(())
+    })
+}
+
 #[test]
 #[cfg(feature = "unstable")]
 fn test_fetch_build_std() {
-    use std::path::Path;
-
     let target_file = Path::new(env!("OUT_DIR")).join("target");
     let target = std::fs::read_to_string(target_file).unwrap();
 
     runner::run("hello-world", |run| {
         run.run(SandboxBuilder::new().enable_networking(false), |build| {
-            build.fetch_build_std_dependencies(&[target.as_str()])?;
+            let fetch = build.fetch_build_std_dependencies(&[target.as_str()])?;
+            assert_eq!(fetch.fetched(), [target.as_str()]);
+            assert!(fetch.failed().is_empty());
             let storage = rustwide::logging::LogStorage::new(LevelFilter::Info);
             rustwide::logging::capture(&storage, || -> anyhow::Result<_> {
                 build
@@ -56,6 +591,25 @@ fn test_fetch_build_std() {
     });
 }
 
+#[test]
+#[cfg(feature = "unstable")]
+fn test_fetch_build_std_invalid_target() {
+    let target_file = Path::new(env!("OUT_DIR")).join("target");
+    let target = std::fs::read_to_string(target_file).unwrap();
+
+    runner::run("hello-world", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let fetch =
+                build.fetch_build_std_dependencies(&[target.as_str(), "not-a-real-target"])?;
+            assert_eq!(fetch.fetched(), [target.as_str()]);
+            assert_eq!(fetch.failed().len(), 1);
+            assert_eq!(fetch.failed()[0].0, "not-a-real-target");
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
 #[test]
 fn path_based_patch() {
     runner::run("path-based-patch", |run| {
@@ -115,8 +669,6 @@ fn test_process_lines() {
 #[test]
 #[cfg(not(windows))]
 fn test_sandbox_oom() {
-    use rustwide::cmd::CommandError;
-
     runner::run("out-of-memory", |run| {
         let res = run.run(
             SandboxBuilder::new()
@@ -170,6 +722,363 @@ fn test_cargo_workspace() {
     });
 }
 
+#[test]
+#[cfg(not(windows))]
+fn test_sandbox_workdir() {
+    runner::run("cargo-workspace", |run| {
+        run.run(SandboxBuilder::new().enable_networking(false), |build| {
+            let output = build.cmd("pwd").sandbox_workdir("world").run_capture()?;
+            assert!(output.stdout_lines()[0].ends_with("/workdir/world"));
+
+            let err = build
+                .cmd("pwd")
+                .sandbox_workdir("world/../..")
+                .run()
+                .unwrap_err();
+            assert!(matches!(err, CommandError::InvalidSandboxWorkdir(_)));
+            Ok(())
+        })?;
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_package() {
+    runner::run("cargo-workspace", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.package("world").run(|build| {
+                let output = build.cmd("pwd").run_capture()?;
+                assert!(output.stdout_lines()[0].ends_with("/workdir/world"));
+                build.cargo().args(&["build"]).run()?;
+                Ok(())
+            })
+        })?;
+
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.package("missing").run(|_| Ok(()))
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::MissingPackage(name)) => assert_eq!(name, "missing"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn test_validate_with() {
+    runner::run("hello-world", |run| {
+        let mut called = false;
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .validate_with(|build| build.cargo().args(&["verify-project"]))
+                .run(|_| {
+                    called = true;
+                    Ok(())
+                })
+        })?;
+        assert!(called);
+
+        let mut called = false;
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .validate_with(|build| {
+                    build
+                        .cargo()
+                        .args(&["check", "--features", "missing-feature"])
+                })
+                .run(|_| {
+                    called = true;
+                    Ok(())
+                })
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::ValidationFailed(stderr)) => {
+                assert!(stderr.contains("missing-feature"), "stderr: {}", stderr)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!called);
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_validate_with_cmd() {
+    runner::run("hello-world", |run| {
+        // Validation commands are not limited to cargo.
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .validate_with(|build| {
+                    build
+                        .cmd("sh")
+                        .args(&["-c", "echo missing-license >&2; test -f LICENSE"])
+                })
+                .run(|_| Ok(()))
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::ValidationFailed(stderr)) => {
+                assert!(stderr.contains("missing-license"), "stderr: {}", stderr)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn test_add_file() {
+    runner::run("hello-world", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .add_file(Path::new("config/injected.txt"), b"injected contents")
+                .run(|build| {
+                    let output = build
+                        .cmd("cat")
+                        .args(&["config/injected.txt"])
+                        .run_capture()?;
+                    assert_eq!(output.stdout_lines(), ["injected contents"]);
+                    Ok(())
+                })
+        })?;
+
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .add_file(Path::new("../escape.txt"), b"")
+                .run(|_| Ok(()))
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::InvalidFilePath(path)) => {
+                assert_eq!(path, Path::new("../escape.txt"))
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        Ok(())
+    });
+}
+
+/// Create a crate containing the `escape` symbolic link, pointing to `outside` on the host.
+#[cfg(unix)]
+fn crate_with_escaping_symlink(outside: &Path) -> anyhow::Result<rustwide::Crate> {
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    let files: &[(&str, &[u8])] = &[
+        (
+            "escape-0.1.0/Cargo.toml",
+            b"[package]\nname = \"escape\"\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+        ),
+        ("escape-0.1.0/src/main.rs", b"fn main() {}\n"),
+    ];
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, path, *contents)?;
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    tar.append_link(&mut header, "escape-0.1.0/escape", outside)?;
+
+    Ok(rustwide::Crate::from_bytes(
+        "escape",
+        "0.1.0",
+        tar.into_inner()?.finish()?,
+    ))
+}
+
+#[test]
+#[cfg(unix)]
+fn test_add_file_through_symlink() {
+    let outside = tempfile::tempdir().unwrap();
+    let krate = crate_with_escaping_symlink(outside.path()).unwrap();
+    runner::run_crate("escaping-symlink", krate, |run| {
+        for path in ["escape/injected.txt", "escape/dir/injected.txt", "escape"] {
+            let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+                builder
+                    .add_file(Path::new(path), b"injected contents")
+                    .run(|_| Ok(()))
+            });
+            match res.err().and_then(|err| err.downcast().ok()) {
+                Some(rustwide::PrepareError::InvalidFilePath(invalid)) => {
+                    assert_eq!(invalid, Path::new(path))
+                }
+                other => panic!("unexpected error: {:?}", other),
+            }
+        }
+        // Nothing was written outside of the source.
+        assert_eq!(std::fs::read_dir(outside.path())?.count(), 0);
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn test_remove_file_through_symlink() {
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::write(outside.path().join("victim.txt"), b"").unwrap();
+    let krate = crate_with_escaping_symlink(outside.path()).unwrap();
+    runner::run_crate("escaping-symlink", krate, |run| {
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .remove_file(Path::new("escape/victim.txt"))
+                .run(|_| Ok(()))
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::InvalidFilePath(path)) => {
+                assert_eq!(path, Path::new("escape/victim.txt"))
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(outside.path().join("victim.txt").is_file());
+
+        // Removing the link itself leaves its target alone.
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.remove_file(Path::new("escape")).run(|build| {
+                assert!(build
+                    .host_source_dir()
+                    .join("escape")
+                    .symlink_metadata()
+                    .is_err());
+                Ok(())
+            })
+        })?;
+        assert!(outside.path().join("victim.txt").is_file());
+        Ok(())
+    });
+}
+
+#[test]
+fn test_retry() {
+    runner::run("hello-world", |run| {
+        let mut attempts = 0;
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.run_with_retry(
+                3,
+                |err| err.to_string().contains("spurious"),
+                |build| {
+                    attempts += 1;
+                    // Each attempt starts from a freshly prepared source.
+                    let marker = build.host_source_dir().join("marker");
+                    assert!(!marker.exists());
+                    std::fs::write(&marker, b"")?;
+                    if attempts == 1 {
+                        anyhow::bail!("spurious failure");
+                    }
+                    Ok(())
+                },
+            )
+        })?;
+        assert_eq!(attempts, 2);
+
+        // Errors rejected by the predicate are not retried.
+        let mut attempts = 0;
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder.run_with_retry(
+                3,
+                |err| err.to_string().contains("spurious"),
+                |_| -> anyhow::Result<()> {
+                    attempts += 1;
+                    anyhow::bail!("genuine failure")
+                },
+            )
+        });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+        Ok(())
+    });
+}
+
+#[test]
+fn test_build_report() {
+    runner::run("hello-world", |run| {
+        let ((), report) = run
+            .build(SandboxBuilder::new().enable_networking(false), |builder| {
+                builder.run_with_report(|_| Ok(()))
+            })?;
+        assert_eq!(report.fetch(), None);
+
+        run.fetch()?;
+        let ((), report) =
+            run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+                builder.run_with_report(|build| {
+                    build.cargo().args(&["run"]).run()?;
+                    Ok(())
+                })
+            })?;
+
+        assert!(report.fetch().is_some());
+        assert!(report.validate_manifest() > Duration::ZERO);
+        assert!(report.lockfile() > Duration::ZERO);
+        assert!(report.build() > Duration::ZERO);
+        let phases = report.copy_source()
+            + report.validate_manifest()
+            + report.tweak()
+            + report.lockfile()
+            + report.fetch_deps()
+            + report.build();
+        assert!(phases <= report.total());
+        // Only cleaning up and the bookkeeping between the phases are not broken down.
+        assert!(report.total() - phases < Duration::from_secs(5));
+        Ok(())
+    });
+}
+
+#[test]
+fn test_skip_fetch_deps() {
+    runner::run("hello-world", |run| {
+        let ((), report) =
+            run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+                builder.skip_fetch_deps(true).run_with_report(|build| {
+                    build.cargo().args(&["run", "--offline"]).run()?;
+                    Ok(())
+                })
+            })?;
+        assert_eq!(report.fetch_deps(), Duration::ZERO);
+        Ok(())
+    });
+}
+
+#[test]
+fn test_remove_file() {
+    runner::run("hello-world", |run| {
+        run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .remove_file(Path::new("src/main.rs"))
+                .remove_file(Path::new("missing.txt"))
+                .run(|build| {
+                    assert!(!build.host_source_dir().join("src/main.rs").exists());
+                    // Cargo doesn't find the removed binary anymore.
+                    assert!(build.cargo().args(&["build"]).run().is_err());
+                    Ok(())
+                })
+        })?;
+
+        let res = run.build(SandboxBuilder::new().enable_networking(false), |builder| {
+            builder
+                .remove_file(Path::new("/etc/passwd"))
+                .run(|_| Ok(()))
+        });
+        match res.err().and_then(|err| err.downcast().ok()) {
+            Some(rustwide::PrepareError::InvalidFilePath(path)) => {
+                assert_eq!(path, Path::new("/etc/passwd"))
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        Ok(())
+    });
+}
+
+test_prepare_error!(
+    test_virtual_manifest_without_members,
+    "virtual-manifest-empty",
+    VirtualManifest
+);
+
 test_prepare_error!(
     test_missing_cargotoml,
     "missing-cargotoml",
@@ -182,6 +1091,12 @@ test_prepare_error!(
     InvalidCargoTomlSyntax
 );
 
+test_prepare_error!(
+    test_invalid_cargotoml_encoding,
+    "invalid-cargotoml-encoding",
+    InvalidCargoTomlSyntax
+);
+
 test_prepare_error!(
     test_invalid_cargotoml_content,
     "invalid-cargotoml-content",
@@ -1,9 +1,26 @@
 use rand::{distributions::Alphanumeric, Rng};
-use rustwide::{cmd::SandboxBuilder, Build, BuildBuilder, Crate, Toolchain, Workspace};
+use rustwide::{
+    cmd::SandboxBuilder, Build, BuildBuilder, BuildDirectory, Crate, Toolchain, Workspace,
+};
 use std::path::Path;
 
 pub(crate) fn run(crate_name: &str, f: impl FnOnce(&mut Runner) -> anyhow::Result<()>) {
-    let mut runner = Runner::new(crate_name).unwrap();
+    let krate = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join(crate_name),
+    );
+    run_crate(crate_name, krate, f);
+}
+
+/// Run the test with a crate not stored in the `crates` directory, named `crate_name`.
+pub(crate) fn run_crate(
+    crate_name: &str,
+    krate: Crate,
+    f: impl FnOnce(&mut Runner) -> anyhow::Result<()>,
+) {
+    let mut runner = Runner::new(crate_name, krate).unwrap();
     f(&mut runner).unwrap();
 }
 
@@ -15,14 +32,8 @@ pub(crate) struct Runner {
 }
 
 impl Runner {
-    fn new(crate_name: &str) -> anyhow::Result<Self> {
+    fn new(crate_name: &str, krate: Crate) -> anyhow::Result<Self> {
         let workspace = crate::utils::init_workspace()?;
-        let krate = Crate::local(
-            &Path::new("tests")
-                .join("buildtest")
-                .join("crates")
-                .join(crate_name),
-        );
         Ok(Runner {
             crate_name: if std::env::var("RUSTWIDE_TEST_INSIDE_DOCKER").is_ok() {
                 format!("{}-inside-docker", crate_name)
@@ -35,11 +46,11 @@ impl Runner {
         })
     }
 
-    pub(crate) fn build<T>(
-        &self,
-        sandbox: SandboxBuilder,
-        f: impl FnOnce(BuildBuilder) -> anyhow::Result<T>,
-    ) -> anyhow::Result<T> {
+    pub(crate) fn fetch(&self) -> anyhow::Result<()> {
+        self.krate.fetch(&self.workspace)
+    }
+
+    pub(crate) fn build_dir(&self) -> anyhow::Result<BuildDirectory> {
         // Use a random string at the end to avoid conflicts if multiple tests use the same source crate.
         let suffix: String = rand::thread_rng()
             .sample_iter(&Alphanumeric)
@@ -51,13 +62,30 @@ impl Runner {
             .workspace
             .build_dir(&format!("{}-{suffix}", &self.crate_name));
         dir.purge()?;
+        Ok(dir)
+    }
+
+    pub(crate) fn build_in<T>(
+        &self,
+        dir: &mut BuildDirectory,
+        sandbox: SandboxBuilder,
+        f: impl FnOnce(BuildBuilder) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
         f(dir.build(&self.toolchain, &self.krate, sandbox))
     }
 
+    pub(crate) fn build<T>(
+        &self,
+        sandbox: SandboxBuilder,
+        f: impl FnOnce(BuildBuilder) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        self.build_in(&mut self.build_dir()?, sandbox, f)
+    }
+
     pub(crate) fn run<T>(
         &self,
         sandbox: SandboxBuilder,
-        f: impl FnOnce(&Build) -> anyhow::Result<T>,
+        f: impl FnMut(&Build) -> anyhow::Result<T>,
     ) -> anyhow::Result<T> {
         self.build(sandbox, |builder| builder.run(f))
     }
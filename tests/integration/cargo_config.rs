@@ -0,0 +1,34 @@
+const WORKSPACE_NAME: &str = "cargo-config";
+const INDEX_URL: &str = "https://github.com/rust-lang/staging.crates.io-index";
+
+#[test]
+fn test_cargo_config_alt_registry() -> anyhow::Result<()> {
+    let _workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?
+        .cargo_config_env("registries.staging.index", INDEX_URL)
+        .cargo_config_env("net.retry", 5)
+        .init()?;
+
+    let config_path = crate::utils::workspace_path(WORKSPACE_NAME)
+        .join("cargo-home")
+        .join("config.toml");
+    let config: toml::Table = toml::from_str(&std::fs::read_to_string(&config_path)?)?;
+    assert_eq!(
+        config["registries"]["staging"]["index"].as_str(),
+        Some(INDEX_URL)
+    );
+    assert_eq!(config["net"]["retry"].as_integer(), Some(5));
+
+    // The keys set by the previous initialization are replaced.
+    let _workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?
+        .cargo_config_env("net.retry", 3)
+        .init()?;
+    let config: toml::Table = toml::from_str(&std::fs::read_to_string(&config_path)?)?;
+    assert!(!config.contains_key("registries"));
+    assert_eq!(config["net"]["retry"].as_integer(), Some(3));
+
+    let _workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?.init()?;
+    let config: toml::Table = toml::from_str(&std::fs::read_to_string(&config_path)?)?;
+    assert!(config.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{Crate, Toolchain};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_container_hooks() -> anyhow::Result<()> {
+    let created = Arc::new(Mutex::new(Vec::new()));
+    let deleted = Arc::new(Mutex::new(Vec::new()));
+
+    let workspace = {
+        let created = created.clone();
+        let deleted = deleted.clone();
+        crate::utils::named_workspace_builder("container-hooks")?
+            .on_container_created(move |id| created.lock().unwrap().push(id.to_string()))
+            .on_container_deleted(move |id| deleted.lock().unwrap().push(id.to_string()))
+            .init()?
+    };
+
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+    let krate = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    );
+
+    let mut build_dir = workspace.build_dir("container-hooks");
+    build_dir.purge()?;
+    build_dir
+        .build(
+            &toolchain,
+            &krate,
+            SandboxBuilder::new().enable_networking(false),
+        )
+        .run(|build| {
+            build.cargo().args(&["--version"]).run()?;
+            // The container is deleted even if the command fails.
+            assert!(build.cmd("false").run().is_err());
+            Ok(())
+        })?;
+
+    let created = created.lock().unwrap();
+    assert_eq!(created.len(), 2);
+    assert_eq!(*created, *deleted.lock().unwrap());
+
+    Ok(())
+}
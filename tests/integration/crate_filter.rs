@@ -0,0 +1,39 @@
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{Crate, Toolchain};
+use std::path::Path;
+
+#[test]
+fn test_crate_filter() -> anyhow::Result<()> {
+    let workspace = crate::utils::named_workspace_builder("crate-filter")?
+        .crate_filter(|krate| {
+            if krate.to_string().contains("lazy_static") {
+                anyhow::bail!("crate {} is banned", krate);
+            }
+            Ok(())
+        })
+        .init()?;
+
+    let banned = Crate::crates_io("lazy_static", "1.4.0");
+    let err = banned.fetch(&workspace).unwrap_err();
+    assert!(err.to_string().contains("is banned"));
+
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+    let mut build_dir = workspace.build_dir("crate-filter");
+    build_dir.purge()?;
+    let err = build_dir
+        .build(&toolchain, &banned, SandboxBuilder::new())
+        .run(|_| Ok(()))
+        .unwrap_err();
+    assert!(err.to_string().contains("is banned"));
+
+    let allowed = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    );
+    allowed.fetch(&workspace)?;
+
+    Ok(())
+}
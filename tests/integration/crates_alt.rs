@@ -1,4 +1,5 @@
-use rustwide::{AlternativeRegistry, Crate};
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{AlternativeRegistry, Crate, Toolchain};
 
 const INDEX_URL: &str = "https://github.com/rust-lang/staging.crates.io-index";
 
@@ -12,3 +13,62 @@ fn test_fetch() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_is_yanked() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+
+    let alt = AlternativeRegistry::new(INDEX_URL);
+    let krate = Crate::registry(alt, "foo", "0.4.0");
+    assert!(krate.is_yanked(&workspace)?.is_some());
+
+    let local = Crate::local("tests/buildtest/crates/hello-world".as_ref());
+    assert_eq!(local.is_yanked(&workspace)?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_available_versions() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+
+    let alt = AlternativeRegistry::new(INDEX_URL);
+    let versions = alt.available_versions("foo", &workspace)?;
+    assert!(versions.iter().any(|(version, _)| version == "0.4.0"));
+
+    assert!(alt
+        .available_versions("this-crate-does-not-exist", &workspace)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_build_configures_registry() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let mut alt = AlternativeRegistry::new(INDEX_URL);
+    alt.name("staging");
+    let krate = Crate::registry(alt, "foo", "0.4.0");
+    krate.fetch(&workspace)?;
+
+    let mut dir = workspace.build_dir("integration-crates_alt-test_build_configures_registry");
+    dir.purge()?;
+    dir.build(&toolchain, &krate, SandboxBuilder::new())
+        .run(|build| {
+            let config =
+                std::fs::read_to_string(build.host_source_dir().join(".cargo/config.toml"))?;
+            let config: toml::Table = toml::from_str(&config)?;
+            assert_eq!(
+                config["registries"]["staging"]["index"].as_str(),
+                Some(INDEX_URL)
+            );
+
+            build.cargo().args(&["fetch"]).run()?;
+            Ok(())
+        })?;
+
+    Ok(())
+}
@@ -47,6 +47,242 @@ fn test_fetch() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_fetch_rev() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let mut repo = Repo::new(&workspace)?;
+    let pinned_commit = repo.last_commit_sha.clone().unwrap();
+    repo.commit(&workspace)?;
+    assert_ne!(pinned_commit, repo.last_commit_sha.clone().unwrap());
+
+    let krate = Crate::git_rev(&repo.serve()?, &pinned_commit)?;
+    krate.fetch(&workspace)?;
+    assert_eq!(pinned_commit, krate.git_commit(&workspace).unwrap());
+
+    let mut dir = workspace.build_dir("integration-crates_git-test_fetch_rev");
+    dir.purge()?;
+    let cloned_commit = dir
+        .build(&toolchain, &krate, SandboxBuilder::new())
+        .run(|build| {
+            Ok(Command::new(&workspace, "git")
+                .args(&["rev-parse", "HEAD"])
+                .cd(build.host_source_dir())
+                .run_capture()?
+                .stdout_lines()[0]
+                .to_string())
+        })?;
+    assert_eq!(pinned_commit, cloned_commit);
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+
+    let mut repo = Repo::new(&workspace)?;
+    let initial_commit = repo.last_commit_sha.clone().unwrap();
+    let manifest = repo.source.path().join("Cargo.toml");
+    let contents = std::fs::read_to_string(&manifest)?.replace(
+        "[package]\n",
+        "[package]\nrust-version = \"1.70\"\nlicense = \"MIT\"\n",
+    );
+    std::fs::write(&manifest, contents)?;
+    let subdir = std::path::Path::new("crates").join("bar");
+    Command::new(&workspace, "cargo")
+        .args(&["init", "--name", "bar", "--lib", "--vcs", "none"])
+        .args(&[repo.source.path().join(&subdir)])
+        .run()?;
+    repo.commit(&workspace)?;
+    let url = repo.serve()?;
+
+    // Git crates need to be fetched before reading their manifest.
+    let krate = Crate::git(&url);
+    assert_eq!(None, krate.min_rust_version(&workspace)?);
+    krate.fetch(&workspace)?;
+    assert_eq!(
+        Some(semver::Version::new(1, 70, 0)),
+        krate.min_rust_version(&workspace)?
+    );
+    assert_eq!(Some("MIT".to_string()), krate.license(&workspace)?);
+    assert!(krate.edition(&workspace)?.is_some());
+
+    // The manifest is read at the pinned revision.
+    let pinned = Crate::git_rev(&url, &initial_commit)?;
+    pinned.fetch(&workspace)?;
+    assert_eq!(None, pinned.min_rust_version(&workspace)?);
+    assert_eq!(None, pinned.license(&workspace)?);
+    assert!(pinned.edition(&workspace)?.is_some());
+
+    // The manifest is read from the subdirectory of the crate.
+    let subdir = Crate::git_subdir(&url, &subdir);
+    subdir.fetch(&workspace)?;
+    assert_eq!(None, subdir.min_rust_version(&workspace)?);
+    assert_eq!(None, subdir.license(&workspace)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_fetch_shallow() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let mut repo = Repo::new(&workspace)?;
+    repo.commit(&workspace)?;
+    repo.commit(&workspace)?;
+    // Shallow clones are not supported by the dumb HTTP protocol used by `Repo::serve`.
+    let url = format!("file://{}", repo.source.path().display());
+
+    // Return the number of commits in the history of a build's source.
+    let history_len = |krate: &Crate| -> anyhow::Result<String> {
+        let mut dir = workspace.build_dir("integration-crates_git-test_fetch_shallow");
+        dir.purge()?;
+        dir.build(&toolchain, krate, SandboxBuilder::new())
+            .run(|build| {
+                Ok(Command::new(&workspace, "git")
+                    .args(&["rev-list", "--count", "HEAD"])
+                    .cd(build.host_source_dir())
+                    .run_capture()?
+                    .stdout_lines()[0]
+                    .to_string())
+            })
+    };
+
+    let shallow = Crate::git_shallow(&url, 1);
+    shallow.fetch(&workspace)?;
+    assert_eq!(repo.last_commit_sha, shallow.git_commit(&workspace));
+    assert_eq!(history_len(&shallow)?, "1");
+
+    // Fetching the full history converts the cached shallow clone.
+    let full = Crate::git(&url);
+    full.fetch(&workspace)?;
+    assert_eq!(history_len(&full)?, "3");
+
+    // The cached full clone is kept as-is.
+    shallow.fetch(&workspace)?;
+    assert_eq!(repo.last_commit_sha, shallow.git_commit(&workspace));
+    assert_eq!(history_len(&shallow)?, "3");
+
+    Ok(())
+}
+
+#[test]
+fn test_local_git() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let repo = Repo::new(&workspace)?;
+    // Uncommitted changes are built, while the commit reported is the one of `HEAD`.
+    std::fs::write(repo.source.path().join("uncommitted.txt"), b"uncommitted")?;
+    let krate = Crate::local_git(repo.source.path());
+    krate.fetch(&workspace)?;
+    assert_eq!(
+        repo.last_commit_sha.as_deref(),
+        krate.git_commit(&workspace).as_deref()
+    );
+
+    // Plain local crates report the commit too, unless they're not inside a git checkout.
+    let plain = Crate::local(repo.source.path());
+    assert_eq!(repo.last_commit_sha, plain.git_commit(&workspace));
+    let not_a_repo = tempfile::tempdir()?;
+    assert_eq!(None, Crate::local(not_a_repo.path()).git_commit(&workspace));
+
+    let mut dir = workspace.build_dir("integration-crates_git-test_local_git");
+    dir.purge()?;
+    dir.build(&toolchain, &krate, SandboxBuilder::new())
+        .run(|build| {
+            let contents = std::fs::read(build.host_source_dir().join("uncommitted.txt"))?;
+            assert_eq!(contents, b"uncommitted");
+            Ok(())
+        })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_fetch_with_submodules() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let submodule = Repo::new(&workspace)?;
+    let submodule_url = submodule.serve()?;
+    let mut repo = Repo::new(&workspace)?;
+    Command::new(&workspace, "git")
+        .args(&["submodule", "add", &submodule_url, "vendored"])
+        .cd(repo.source.path())
+        .run()?;
+    repo.commit(&workspace)?;
+    let url = repo.serve()?;
+
+    // Return whether the submodule was checked out in a build's source.
+    let has_submodule = |krate: &Crate| -> anyhow::Result<bool> {
+        krate.fetch(&workspace)?;
+        let mut dir = workspace.build_dir("integration-crates_git-test_fetch_with_submodules");
+        dir.purge()?;
+        dir.build(&toolchain, krate, SandboxBuilder::new())
+            .run(|build| {
+                Ok(build
+                    .host_source_dir()
+                    .join("vendored")
+                    .join("Cargo.toml")
+                    .is_file())
+            })
+    };
+
+    assert!(!has_submodule(&Crate::git(&url))?);
+    assert!(has_submodule(&Crate::git_with_submodules(&url))?);
+
+    Ok(())
+}
+
+#[test]
+fn test_fetch_subdir() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let mut repo = Repo::new(&workspace)?;
+    let subdir = std::path::Path::new("crates").join("bar");
+    Command::new(&workspace, "cargo")
+        .args(&["init", "--name", "bar", "--bin", "--vcs", "none"])
+        .args(&[repo.source.path().join(&subdir)])
+        .run()?;
+    repo.commit(&workspace)?;
+
+    let krate = Crate::git_subdir(&repo.serve()?, &subdir);
+    krate.fetch(&workspace)?;
+
+    let mut dir = workspace.build_dir("integration-crates_git-test_fetch_subdir");
+    dir.purge()?;
+    let pkgid = dir
+        .build(&toolchain, &krate, SandboxBuilder::new())
+        .run(|build| {
+            let members = build
+                .cargo_metadata()
+                .workspace_members()
+                .collect::<Vec<_>>();
+            assert_eq!(members.len(), 1);
+            assert_eq!(members[0].name(), "bar");
+            assert!(build
+                .host_source_dir()
+                .join(&subdir)
+                .join("Cargo.lock")
+                .is_file());
+
+            Ok(build.cargo().args(&["pkgid"]).run_capture()?.stdout_lines()[0].to_string())
+        })?;
+    assert!(pkgid.contains("bar"), "pkgid: {}", pkgid);
+
+    Ok(())
+}
+
 #[test]
 fn test_fetch_with_authentication() -> anyhow::Result<()> {
     let workspace = crate::utils::init_workspace()?;
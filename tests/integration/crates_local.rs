@@ -0,0 +1,40 @@
+use rustwide::cmd::{Command, SandboxBuilder};
+use rustwide::{Crate, Toolchain};
+use std::path::Path;
+
+#[test]
+fn test_local_subdir() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let source = tempfile::tempdir()?;
+    let subdir = Path::new("crates").join("bar");
+    Command::new(&workspace, "cargo")
+        .args(&["init", "--name", "bar", "--bin", "--vcs", "none"])
+        .args(&[source.path().join(&subdir)])
+        .run()?;
+    std::fs::write(source.path().join("README.md"), "outside of the crate")?;
+
+    let krate = Crate::local_subdir(source.path(), &subdir);
+    assert!(krate.edition(&workspace)?.is_some());
+
+    let mut dir = workspace.build_dir("integration-crates_local-test_local_subdir");
+    dir.purge()?;
+    let pkgid = dir
+        .build(&toolchain, &krate, SandboxBuilder::new())
+        .run(|build| {
+            // The whole directory is copied, while the commands run in the subdirectory.
+            assert!(build.host_source_dir().join("README.md").is_file());
+            assert!(build
+                .host_source_dir()
+                .join(&subdir)
+                .join("Cargo.lock")
+                .is_file());
+
+            Ok(build.cargo().args(&["pkgid"]).run_capture()?.stdout_lines()[0].to_string())
+        })?;
+    assert!(pkgid.contains("bar"), "pkgid: {}", pkgid);
+
+    Ok(())
+}
@@ -0,0 +1,22 @@
+const WORKSPACE_NAME: &str = "custom-tools";
+
+#[test]
+fn test_install_custom_tool() -> anyhow::Result<()> {
+    let workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?
+        .install_tool("names", "names")
+        .init()?;
+
+    let path = workspace.tool_path("names").expect("tool not installed");
+    assert!(path.is_file());
+    assert!(workspace.tool_path("git-credential-null").is_some());
+    assert!(workspace.tool_path("not-a-tool").is_none());
+
+    // Initializing the workspace again should update the tool instead of failing.
+    drop(workspace);
+    let workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?
+        .install_tool("names", "names")
+        .init()?;
+    assert_eq!(workspace.tool_path("names"), Some(path));
+
+    Ok(())
+}
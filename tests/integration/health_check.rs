@@ -0,0 +1,31 @@
+#[test]
+fn test_health_check() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let report = workspace.health_check();
+
+    assert!(report.rustup_installed());
+    assert!(report.tools_installed());
+    assert!(report.missing_tools().is_empty());
+    assert!(report.main_toolchain_installed());
+    assert_eq!(
+        report.is_healthy(),
+        report.docker_available(),
+        "unexpected report: {:?}",
+        report
+    );
+
+    Ok(())
+}
+
+#[test]
+// On other platforms the default sandbox image is pulled with the missing runtime.
+#[cfg(target_os = "linux")]
+fn test_health_check_container_runtime() -> anyhow::Result<()> {
+    let workspace = crate::utils::named_workspace_builder("integration")?
+        .container_runtime("rustwide-missing-container-runtime")
+        .init()?;
+    assert!(!workspace.health_check().docker_available());
+    assert!(!rustwide::cmd::docker_running(&workspace));
+
+    Ok(())
+}
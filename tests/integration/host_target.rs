@@ -0,0 +1,14 @@
+use std::path::Path;
+
+#[test]
+fn test_host_target() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+
+    let target_file = Path::new(env!("OUT_DIR")).join("target");
+    let target = std::fs::read_to_string(target_file)?;
+
+    assert!(!workspace.host_target().is_empty());
+    assert_eq!(workspace.host_target(), target);
+
+    Ok(())
+}
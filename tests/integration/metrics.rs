@@ -0,0 +1,47 @@
+use rustwide::metrics::{Counter, MetricsSink, Operation};
+use rustwide::Crate;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+struct MockSink {
+    operations: Mutex<Vec<Operation>>,
+    counters: Mutex<Vec<Counter>>,
+}
+
+impl MetricsSink for MockSink {
+    fn record_duration(&self, operation: Operation, _duration: Duration) {
+        self.operations.lock().unwrap().push(operation);
+    }
+
+    fn increment_counter(&self, counter: Counter) {
+        self.counters.lock().unwrap().push(counter);
+    }
+}
+
+#[test]
+fn test_fetch_metrics() -> anyhow::Result<()> {
+    let sink = Arc::new(MockSink::default());
+    let workspace = crate::utils::named_workspace_builder("metrics")?
+        .metrics_sink(sink.clone())
+        .init()?;
+
+    let krate = Crate::crates_io("lazy_static", "1.0.0");
+    krate.purge_from_cache(&workspace)?;
+    sink.operations.lock().unwrap().clear();
+    sink.counters.lock().unwrap().clear();
+
+    krate.fetch(&workspace)?;
+    krate.fetch(&workspace)?;
+
+    assert_eq!(
+        *sink.operations.lock().unwrap(),
+        [Operation::CrateFetch, Operation::CrateFetch]
+    );
+    assert_eq!(
+        *sink.counters.lock().unwrap(),
+        [Counter::CacheMiss, Counter::CacheHit]
+    );
+
+    Ok(())
+}
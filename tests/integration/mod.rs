@@ -1,3 +1,19 @@
+mod cargo_config;
+mod container_hooks;
+mod crate_filter;
 mod crates_alt;
 mod crates_git;
+mod crates_local;
+mod custom_tools;
+mod health_check;
+mod host_target;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod prebuilt_tools;
 mod purge_caches;
+mod rustdoc_json;
+mod temp_build_dir;
+mod timings;
+mod tokio_handle;
+mod toolchain;
+mod verify;
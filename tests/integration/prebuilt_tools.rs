@@ -0,0 +1,13 @@
+const WORKSPACE_NAME: &str = "prebuilt-tools";
+
+#[test]
+fn test_prebuilt_tools_fallback() -> anyhow::Result<()> {
+    // git-credential-null doesn't have prebuilt binaries, so enabling prebuilt tools must fall
+    // back to installing it with `cargo install`.
+    let workspace = crate::utils::named_workspace_builder(WORKSPACE_NAME)?
+        .prebuilt_tools(true)
+        .init()?;
+    assert!(workspace.tool_path("git-credential-null").is_some());
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{Crate, RustdocOptions, Toolchain};
+use std::path::Path;
+
+#[test]
+fn test_rustdoc_json() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_named_workspace("rustdoc-json")?;
+    let toolchain = Toolchain::dist("nightly");
+    toolchain.install(&workspace)?;
+    let krate = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    );
+
+    let mut build_dir = workspace.build_dir("rustdoc-json");
+    build_dir.purge()?;
+    build_dir
+        .build(
+            &toolchain,
+            &krate,
+            SandboxBuilder::new().enable_networking(false),
+        )
+        .run(|build| {
+            let json = build.rustdoc_json(&RustdocOptions::new().document_private_items(true))?;
+            assert_eq!(json.file_name().unwrap(), "hello_world.json");
+            let parsed: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(json)?)?;
+            assert!(parsed["format_version"].is_u64());
+            assert!(parsed["index"].is_object());
+            Ok(())
+        })?;
+
+    Ok(())
+}
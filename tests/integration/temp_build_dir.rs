@@ -0,0 +1,50 @@
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{Crate, Toolchain};
+use std::path::Path;
+
+#[test]
+fn test_temp_build_dir() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_named_workspace("temp-build-dir")?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+    let krate = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    );
+
+    let target_dir = workspace.with_temp_build_dir(|build_dir| {
+        build_dir
+            .build(
+                &toolchain,
+                &krate,
+                SandboxBuilder::new().enable_networking(false),
+            )
+            .run(|build| {
+                build.cargo().args(&["build"]).run()?;
+                Ok(build.host_target_dir())
+            })
+    })?;
+    assert!(!target_dir.exists());
+
+    // The build directory is removed even if the function fails.
+    let mut target_dir = None;
+    let res: anyhow::Result<()> = workspace.with_temp_build_dir(|build_dir| {
+        build_dir
+            .build(
+                &toolchain,
+                &krate,
+                SandboxBuilder::new().enable_networking(false),
+            )
+            .run(|build| {
+                target_dir = Some(build.host_target_dir());
+                build.cargo().args(&["build"]).run()?;
+                anyhow::bail!("failing on purpose")
+            })
+    });
+    assert!(res.is_err());
+    assert!(target_dir.is_some_and(|dir| !dir.exists()));
+
+    Ok(())
+}
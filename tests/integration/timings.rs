@@ -0,0 +1,41 @@
+use rustwide::cmd::SandboxBuilder;
+use rustwide::{Crate, Toolchain};
+use std::path::Path;
+
+#[test]
+fn test_run_with_timings() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_named_workspace("timings")?;
+    // `--timings=json` is only available on nightly.
+    let toolchain = Toolchain::dist("nightly");
+    toolchain.install(&workspace)?;
+    let krate = Crate::local(
+        &Path::new("tests")
+            .join("buildtest")
+            .join("crates")
+            .join("hello-world"),
+    );
+
+    let mut build_dir = workspace.build_dir("timings");
+    build_dir.purge()?;
+    build_dir
+        .build(
+            &toolchain,
+            &krate,
+            SandboxBuilder::new().enable_networking(false),
+        )
+        .run(|build| {
+            let timings = build.run_with_timings(&["build"])?;
+            let unit = timings
+                .iter()
+                .find(|unit| unit.target().name() == "hello-world")
+                .expect("missing the timings of the crate");
+            assert_eq!(unit.mode(), "build");
+            assert!(unit.duration() > std::time::Duration::ZERO);
+
+            // Units that are already compiled are not reported.
+            assert!(build.run_with_timings(&["build"])?.is_empty());
+            Ok(())
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use rustwide::cmd::Command;
+
+#[test]
+#[cfg(unix)]
+fn test_tokio_handle() -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let workspace = crate::utils::named_workspace_builder("tokio-handle")?
+        .tokio_handle(runtime.handle().clone())
+        .init()?;
+
+    let output = Command::new(&workspace, "echo")
+        .args(&["hello"])
+        .run_capture()?;
+    assert_eq!(output.stdout_lines(), ["hello"]);
+
+    // The blocking methods can be called from the blocking threads of the runtime.
+    let output = runtime.block_on(runtime.spawn_blocking(move || {
+        Command::new(&workspace, "echo")
+            .args(&["world"])
+            .run_capture()
+    }))??;
+    assert_eq!(output.stdout_lines(), ["world"]);
+
+    Ok(())
+}
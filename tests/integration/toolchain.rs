@@ -0,0 +1,20 @@
+use rustwide::cmd::Command;
+use rustwide::Toolchain;
+
+#[test]
+fn test_rustc_direct() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_workspace()?;
+    let toolchain = Toolchain::dist("stable");
+    toolchain.install(&workspace)?;
+
+    let output = Command::new(&workspace, toolchain.rustc_direct(&workspace)?)
+        .args(&["--version"])
+        .run_capture()?;
+    assert!(output.stdout_lines()[0].starts_with("rustc "));
+
+    assert!(Toolchain::dist("not-installed")
+        .rustc_direct(&workspace)
+        .is_err());
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use rustwide::Issue;
+
+#[test]
+fn test_verify_detects_corrupted_tool() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_named_workspace("verify")?;
+    assert_eq!(workspace.verify()?, Vec::new());
+
+    let path = workspace
+        .tool_path("git-credential-null")
+        .expect("tool not installed");
+    let original = std::fs::read(&path)?;
+
+    // Overwriting the file keeps its permissions, so the tool still looks installed.
+    std::fs::write(&path, b"\x7fELF corrupted binary")?;
+    let issues = workspace.verify();
+    std::fs::write(&path, original)?;
+
+    assert_eq!(
+        issues?,
+        vec![Issue::ToolNotRunnable {
+            name: "git-credential-null".into()
+        }]
+    );
+    assert_eq!(workspace.verify()?, Vec::new());
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_missing_rustup() -> anyhow::Result<()> {
+    let workspace = crate::utils::init_named_workspace("repair")?;
+    let toolchains = workspace.installed_toolchains()?;
+
+    let rustup = workspace.tool_path("rustup").expect("rustup not installed");
+    std::fs::remove_file(&rustup)?;
+    assert_eq!(workspace.verify()?, vec![Issue::RustupNotExecutable]);
+
+    assert_eq!(workspace.repair()?, vec![Issue::RustupNotExecutable]);
+    assert!(rustup.is_file());
+    assert_eq!(workspace.installed_toolchains()?, toolchains);
+
+    // Repairing a healthy workspace doesn't do anything.
+    assert_eq!(workspace.repair()?, Vec::new());
+
+    Ok(())
+}
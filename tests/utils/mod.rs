@@ -13,6 +13,10 @@ pub(crate) fn init_workspace() -> anyhow::Result<Workspace> {
 }
 
 pub(crate) fn init_named_workspace(name: &str) -> anyhow::Result<Workspace> {
+    named_workspace_builder(name)?.init()
+}
+
+pub(crate) fn named_workspace_builder(name: &str) -> anyhow::Result<WorkspaceBuilder> {
     init_logs();
     let workspace_path = workspace_path(name);
     let mut builder = WorkspaceBuilder::new(&workspace_path, USER_AGENT).fast_init(true);
@@ -28,7 +32,7 @@ pub(crate) fn init_named_workspace(name: &str) -> anyhow::Result<Workspace> {
         )?);
     }
 
-    builder.init()
+    Ok(builder)
 }
 
 fn init_logs() {